@@ -1,218 +0,0 @@
-import csv
-import os
-import json
-import struct
-
-ORIGINAL_FNT_PATH = 'seura.fnt'
-MAPPING_OUTPUT = 'mapping.toml'
-CSV_CONFIGS = [
-    {
-        'input': 'main.csv',
-        'output': 'main_mapped.csv',
-        'original_cols': ['s'],
-        'translation_cols': ['translated']
-    },
-]
-
-def sjis_generator():
-    for c in range(0x20, 0x7F + 1):
-        yield c
-    for c in range(0xA0, 0xDF + 1):
-        yield c
-
-    for high in range(0x81, 0xA0):
-        for low in range(0x40, 0xFD):
-            if low == 0x7F: continue
-            yield (high << 8) | low
-
-    for high in range(0xE0, 0xEF):
-        for low in range(0x40, 0xFD):
-            if low == 0x7F: continue
-            yield (high << 8) | low
-
-def parse_fnt_inventory(fnt_path):
-    if not os.path.exists(fnt_path):
-        print(f"Font file not found: {fnt_path}")
-        return None, {}
-
-    with open(fnt_path, 'rb') as f:
-        data = f.read()
-
-    if data[0:4] != b'FNT4':
-        print("Invalid FNT4 magic header.")
-        return None, {}
-
-    if data[0x4:0x8] == b"\x01\x00\x00\x00":
-        version = 'v1'
-    elif data[0xC:0x10] == b"\x00\x00\x00\x00":
-        version = 'v0'
-    else:
-        print("Unknown FNT version.")
-        return None, {}
-
-    print(f"Detected FNT4 Version: {version}")
-
-    first_glyph_offset = struct.unpack('<I', data[0x10:0x14])[0]
-    num_chars = (first_glyph_offset - 0x10) // 4
-    
-    inventory = {}
-    seen_offsets = set()
-    
-    print(f"  Scanning {num_chars} entries in character table...")
-
-    if version == 'v0':
-        gen = sjis_generator()
-        for i in range(num_chars):
-            offset = struct.unpack('<I', data[0x10 + i*4 : 0x14 + i*4])[0]
-            
-            try:
-                code = next(gen)
-            except StopIteration:
-                break
-            
-            if offset >= len(data) or offset < 0x10:
-                continue
-
-            if offset in seen_offsets:
-                continue
-            
-            seen_offsets.add(offset)
-
-            try:
-                if code <= 0xFF:
-                    char_obj = code.to_bytes(1, 'big').decode('shift_jis')
-                else:
-                    char_obj = code.to_bytes(2, 'big').decode('shift_jis')
-                inventory[char_obj] = code
-            except:
-                continue
-
-    else: # v1 (Unicode)
-        for i in range(num_chars):
-            offset = struct.unpack('<I', data[0x10 + i*4 : 0x14 + i*4])[0]
-
-            if offset >= len(data) or offset < 0x10:
-                continue
-
-            if offset in seen_offsets:
-                continue
-            
-            seen_offsets.add(offset)
-
-            try:
-                char_obj = chr(i)
-                inventory[char_obj] = i
-            except:
-                continue
-
-    print(f"  Unique Glyphs Found: {len(seen_offsets)}")
-    print(f"  Inventory loaded: {len(inventory)} chars")
-    
-    return version, inventory
-
-def is_cjk_ideograph(char):
-    code_int = ord(char)
-    return 0x4E00 <= code_int <= 0x9FFF
-
-def main():
-    version, font_inventory = parse_fnt_inventory(ORIGINAL_FNT_PATH)
-    if not font_inventory:
-        print("Failed to load font inventory.")
-        return
-        
-    needed_chars = set() 
-    chars_in_csv = set() 
-    
-    for config in CSV_CONFIGS:
-        path = config['input']
-        if not os.path.exists(path):
-            print(f"CSV not found: {path}")
-            continue
-            
-        with open(path, 'r', encoding='utf-8', newline='') as f:
-            reader = csv.DictReader(f)
-            for row in reader:
-                for col in config['original_cols']:
-                    val = row.get(col, '')
-                    if val:
-                        for c in val:
-                            chars_in_csv.add(c)
-                
-                for col in config['translation_cols']:
-                    val = row.get(col, '')
-                    if val:
-                        for c in val:
-                            chars_in_csv.add(c)
-                            if ord(c) >= 0x80 and c not in font_inventory:
-                                needed_chars.add(c)
-
-    potential_slots = [
-        c for c in font_inventory.keys()
-        if is_cjk_ideograph(c) and c not in needed_chars
-    ]
-
-    unused_slots = [c for c in potential_slots if c not in chars_in_csv]
-    low_priority_slots = [c for c in potential_slots if c in chars_in_csv]
-
-    unused_slots.sort(key=lambda x: font_inventory[x])
-    low_priority_slots.sort(key=lambda x: font_inventory[x])
-
-    final_candidates = unused_slots + low_priority_slots
-    missing_chars = sorted(list(needed_chars))
-
-    print(f"\nMissing characters to map: {len(missing_chars)}")
-    print(f"Available slots: Unused({len(unused_slots)}), Low priority({len(low_priority_slots)})")
-
-    if len(missing_chars) > len(final_candidates):
-        print(f"⚠️ Warning - Not enough slots available! Missing: {len(missing_chars)}, Candidates: {len(final_candidates)}")
-        missing_chars = missing_chars[:len(final_candidates)]
-
-    final_mapping = {}  # sjich character in font -> unicode character
-    trans_table = {}    # unicode character -> sjich character in font
-    
-    for i, cn_char in enumerate(missing_chars):
-        slot_jp_char = final_candidates[i]
-        final_mapping[slot_jp_char] = cn_char
-        trans_table[ord(cn_char)] = slot_jp_char
-
-    with open(MAPPING_OUTPUT, 'w', encoding='utf-8') as f:
-        f.write("# Generated Mapping Table for fnt4-tool\n[replace]\n")
-        for jp_char, cn_char in final_mapping.items():
-            k_s = json.dumps(jp_char, ensure_ascii=False)
-            v_s = json.dumps(cn_char, ensure_ascii=False)
-            f.write(f"{k_s} = {v_s}\n")
-
-    for config in CSV_CONFIGS:
-        path = config['input']
-        out_path = config['output']
-        if not os.path.exists(path):
-            continue
-            
-        out_dir = os.path.dirname(out_path)
-        if out_dir:
-            os.makedirs(out_dir, exist_ok=True)
-        
-        with open(path, 'r', encoding='utf-8', newline='') as f:
-            reader = csv.DictReader(f)
-            fieldnames = reader.fieldnames
-            rows = list(reader)
-
-        if fieldnames is None:
-            print(f"Error: Could not read fieldnames from {path}")
-            continue
-
-        with open(out_path, 'w', encoding='utf-8', newline='') as f:
-            writer = csv.DictWriter(f, fieldnames=fieldnames)
-            writer.writeheader()
-            for row in rows:
-                for col in config['translation_cols']:
-                    val = row.get(col, '')
-                    if val:
-                        row[col] = val.translate(trans_table)
-                writer.writerow(row)
-        print(f"Mapped CSV saved: {out_path}")
-
-    print("\nCompleted！")
-
-if __name__ == '__main__':
-    main()
\ No newline at end of file
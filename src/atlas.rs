@@ -0,0 +1,305 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::FntError;
+use crate::extract::ExtractReport;
+use crate::fnt::Fnt;
+use crate::glyph::{MipmapImage, ProcessedGlyph};
+use crate::metadata::FntMetadata;
+use crate::repack::{LoadedGlyph, process_loaded_glyphs, read_alpha_png};
+use crate::utils::ProgressFn;
+
+pub const ATLAS_FILENAME: &str = "atlas.toml";
+
+/// Empty pixels between glyphs, so filtering in image editors does not bleed into neighbours.
+const GLYPH_SPACING: u32 = 1;
+
+/// Position of a glyph in an atlas page, with its metrics.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct AtlasGlyph {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bearing_x: i8,
+    pub bearing_y: i8,
+    pub advance: u8,
+}
+
+/// Sidecar of the atlas pages, stored as `atlas.toml` next to `metadata.toml`.
+///
+/// When repacking, the bearings and advances here take precedence over `metadata.toml`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Atlas {
+    /// Page PNG file names, relative to the atlas directory.
+    pub pages: Vec<String>,
+    pub glyphs: BTreeMap<u32, AtlasGlyph>, // glyph_id -> atlas_glyph
+}
+
+impl Atlas {
+    pub fn read_atlas(path: &Path) -> io::Result<Atlas> {
+        let content = std::fs::read_to_string(path)?;
+
+        toml::from_str(&content).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("TOML parsing error: {}", e),
+            )
+        })
+    }
+
+    pub fn write_atlas(&self, path: &Path) -> io::Result<()> {
+        let content = toml::to_string_pretty(self)
+            .map_err(|e| io::Error::other(format!("TOML serialization error: {}", e)))?;
+
+        let file = std::fs::File::create(path)?;
+        io::BufWriter::new(file).write_all(content.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+/// Places rectangles on pages of `page_size` x `page_size` pixels, tallest first, row by row.
+///
+/// Returns `(page, x, y)` for every size, in the same order, and the used height of each page.
+fn pack_shelves(sizes: &[(u32, u32)], page_size: u32) -> (Vec<(usize, u32, u32)>, Vec<u32>) {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| {
+        (
+            std::cmp::Reverse(sizes[i].1),
+            std::cmp::Reverse(sizes[i].0),
+            i,
+        )
+    });
+
+    let mut positions = vec![(0, 0, 0); sizes.len()];
+    let mut page_heights = vec![0u32];
+    let (mut x, mut y, mut shelf_height) = (0u32, 0u32, 0u32);
+
+    for i in order {
+        let (w, h) = sizes[i];
+
+        if x + w > page_size {
+            x = 0;
+            y += shelf_height + GLYPH_SPACING;
+            shelf_height = 0;
+        }
+        if y + h > page_size {
+            page_heights.push(0);
+            x = 0;
+            y = 0;
+            shelf_height = 0;
+        }
+
+        let page = page_heights.len() - 1;
+        positions[i] = (page, x, y);
+        page_heights[page] = page_heights[page].max(y + h);
+
+        x += w + GLYPH_SPACING;
+        shelf_height = shelf_height.max(h);
+    }
+
+    (positions, page_heights)
+}
+
+/// Decodes all glyphs and packs their level 0 bitmaps into pages of at most
+/// `page_size` x `page_size` pixels, black with the glyph in the alpha channel.
+///
+/// Page file names are `<page_prefix><page>.png`.
+pub(crate) fn build_atlas(
+    fnt: &Fnt,
+    page_size: u32,
+    page_prefix: &str,
+    progress: &ProgressFn,
+) -> Result<(Atlas, Vec<image::RgbaImage>), FntError> {
+    // Glyphs can be up to 255 pixels in each direction
+    let page_size = page_size.max(256);
+
+    let glyphs = fnt.decode_glyphs(progress)?;
+
+    let sizes: Vec<(u32, u32)> = glyphs.values().map(|glyph| glyph.mipmap_size(0)).collect();
+    let (positions, page_heights) = pack_shelves(&sizes, page_size);
+
+    let mut pages: Vec<image::RgbaImage> = page_heights
+        .iter()
+        .map(|&height| image::RgbaImage::new(page_size, height.max(1)))
+        .collect();
+    let mut atlas = Atlas {
+        pages: (0..pages.len())
+            .map(|page| format!("{}{}.png", page_prefix, page))
+            .collect(),
+        glyphs: BTreeMap::new(),
+    };
+
+    for ((glyph_id, glyph), &(page, x, y)) in glyphs.iter().zip(&positions) {
+        let (width, height) = glyph.mipmap_size(0);
+        image::imageops::replace(&mut pages[page], &glyph.to_image(0), x as i64, y as i64);
+
+        atlas.glyphs.insert(
+            *glyph_id,
+            AtlasGlyph {
+                page,
+                x,
+                y,
+                width,
+                height,
+                bearing_x: glyph.info.bearing_x,
+                bearing_y: glyph.info.bearing_y,
+                advance: glyph.info.advance,
+            },
+        );
+    }
+
+    Ok((atlas, pages))
+}
+
+/// Extracts all glyphs into atlas pages of at most `page_size` x `page_size` pixels,
+/// plus `metadata.toml` and the `atlas.toml` sidecar.
+pub fn extract_atlas(
+    fnt: &Fnt,
+    output_dir: &Path,
+    page_size: u32,
+    progress: &ProgressFn,
+) -> Result<ExtractReport, FntError> {
+    std::fs::create_dir_all(output_dir)?;
+    fnt.metadata
+        .write_metadata(&output_dir.join("metadata.toml"))?;
+
+    let (atlas, pages) = build_atlas(fnt, page_size, "atlas_", progress)?;
+
+    for (image, filename) in pages.iter().zip(&atlas.pages) {
+        image
+            .save(output_dir.join(filename))
+            .map_err(io::Error::other)?;
+    }
+    atlas.write_atlas(&output_dir.join(ATLAS_FILENAME))?;
+
+    Ok(ExtractReport {
+        glyph_count: atlas.glyphs.len(),
+        page_count: atlas.pages.len(),
+    })
+}
+
+/// Slices the atlas pages in `input_dir` back into glyphs and encodes them.
+///
+/// Glyphs missing from `atlas.toml` are left out, like missing PNGs in [`crate::process_glyphs`].
+pub fn process_atlas_glyphs(
+    input_dir: &Path,
+    metadata: &FntMetadata,
+    progress: &ProgressFn,
+) -> io::Result<BTreeMap<u32, ProcessedGlyph>> {
+    let atlas = Atlas::read_atlas(&input_dir.join(ATLAS_FILENAME))?;
+
+    let pages: Vec<MipmapImage> = atlas
+        .pages
+        .iter()
+        .map(|filename| {
+            read_alpha_png(&input_dir.join(filename)).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Failed to read atlas page {:?}", filename),
+                )
+            })
+        })
+        .collect::<io::Result<_>>()?;
+
+    Ok(process_loaded_glyphs(
+        metadata,
+        progress,
+        |glyph_id, glyph_info| {
+            let atlas_glyph = atlas.glyphs.get(&glyph_id)?;
+            let page = pages.get(atlas_glyph.page)?;
+
+            let width = atlas_glyph.width.min(255);
+            let height = atlas_glyph.height.min(255);
+            let mut pixels = vec![0u8; (width * height) as usize];
+            for y in 0..height {
+                for x in 0..width {
+                    let (px, py) = (atlas_glyph.x + x, atlas_glyph.y + y);
+                    if px < page.width && py < page.height {
+                        pixels[(y * width + x) as usize] =
+                            page.pixels[(py * page.width + px) as usize];
+                    }
+                }
+            }
+
+            let mut glyph_info = *glyph_info;
+            glyph_info.bearing_x = atlas_glyph.bearing_x;
+            glyph_info.bearing_y = atlas_glyph.bearing_y;
+            glyph_info.advance = atlas_glyph.advance;
+
+            Some(LoadedGlyph {
+                glyph_info,
+                image: MipmapImage {
+                    width,
+                    height,
+                    pixels,
+                },
+                mipmaps: BTreeMap::new(),
+            })
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_shelves() {
+        let sizes = [(100, 20), (200, 30), (0, 0), (150, 30), (60, 255)];
+        let (positions, page_heights) = pack_shelves(&sizes, 256);
+
+        for (i, &(page, x, y)) in positions.iter().enumerate() {
+            let (w, h) = sizes[i];
+            assert!(x + w <= 256 && y + h <= page_heights[page]);
+
+            for (j, &(other_page, ox, oy)) in positions.iter().enumerate() {
+                let (ow, oh) = sizes[j];
+                if i == j || page != other_page || w == 0 || ow == 0 {
+                    continue;
+                }
+                let overlaps = x < ox + ow && ox < x + w && y < oy + oh && oy < y + h;
+                assert!(!overlaps, "glyph {} overlaps glyph {}", i, j);
+            }
+        }
+
+        assert_eq!(page_heights.len(), 2);
+    }
+
+    #[test]
+    fn test_atlas_round_trip() {
+        use crate::fnt::build_test_fnt;
+        use crate::metadata::FntVersion;
+
+        let fnt = build_test_fnt(
+            FntVersion::V1,
+            2,
+            8,
+            &[('A', 0, 8), ('B', 5, 8), ('C', 12, 8)],
+        );
+        let metadata = fnt.metadata.clone();
+
+        let dir =
+            std::env::temp_dir().join(format!("fnt4_atlas_round_trip_{}", std::process::id()));
+        let report = extract_atlas(&fnt, &dir, 256, &|_, _| {}).unwrap();
+        assert_eq!(report.page_count, 1);
+
+        let repacked = process_atlas_glyphs(&dir, &metadata, &|_, _| {}).unwrap();
+
+        assert_eq!(repacked.len(), 3);
+        for (glyph_id, lazy_glyph) in &fnt.lazy_glyphs {
+            let processed = &repacked[glyph_id];
+            assert_eq!(processed.actual_width, lazy_glyph.info.actual_width);
+            assert_eq!(processed.actual_height, lazy_glyph.info.actual_height);
+            assert_eq!(processed.glyph_info.bearing_x, lazy_glyph.info.bearing_x);
+            assert_eq!(processed.data, lazy_glyph.glyph_data.data);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
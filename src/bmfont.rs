@@ -0,0 +1,611 @@
+// AngelCode BMFont descriptor, text and binary (version 3) formats.
+// https://www.angelcode.com/products/bmfont/doc/file_format.html
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use crate::atlas::build_atlas;
+use crate::error::FntError;
+use crate::extract::ExtractReport;
+use crate::fnt::Fnt;
+use crate::glyph::MipmapImage;
+use crate::metadata::{CodeType, FntMetadata, FntVersion, GlyphMetadata};
+use crate::repack::{LoadedGlyph, process_loaded_glyphs};
+use crate::utils::{ProgressFn, decode_sjis_u32, generate_sjis_map};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BmFontFormat {
+    Text,
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BmChar {
+    pub id: u32,
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub xoffset: i16,
+    pub yoffset: i16,
+    pub xadvance: i16,
+    pub page: u8,
+    /// 1 = blue, 2 = green, 4 = red, 8 = alpha, 15 = all channels.
+    pub chnl: u8,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BmFont {
+    pub face: String,
+    pub size: i16,
+    pub line_height: u16,
+    /// Distance from the top of a line to the baseline.
+    pub base: u16,
+    pub scale_w: u16,
+    pub scale_h: u16,
+    pub pages: Vec<String>,
+    pub chars: Vec<BmChar>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Splits a text descriptor line into its tag and `key=value` pairs, unquoting values.
+fn parse_text_line(line: &str) -> (&str, BTreeMap<&str, &str>) {
+    let line = line.trim();
+    let (tag, mut rest) = line.split_once(' ').unwrap_or((line, ""));
+    let mut values = BTreeMap::new();
+
+    loop {
+        rest = rest.trim_start();
+        let Some((key, after_key)) = rest.split_once('=') else {
+            break;
+        };
+
+        let (value, after_value) = if let Some(quoted) = after_key.strip_prefix('"') {
+            quoted.split_once('"').unwrap_or((quoted, ""))
+        } else {
+            after_key.split_once(' ').unwrap_or((after_key, ""))
+        };
+
+        values.insert(key.trim(), value);
+        rest = after_value;
+    }
+
+    (tag, values)
+}
+
+fn text_value<T: std::str::FromStr>(
+    values: &BTreeMap<&str, &str>,
+    key: &str,
+    line_number: usize,
+) -> io::Result<T> {
+    let value = values.get(key).copied().unwrap_or("0");
+    value.parse().map_err(|_| {
+        invalid_data(format!(
+            "BMFont line {}: invalid value {:?} for {}",
+            line_number, value, key
+        ))
+    })
+}
+
+impl BmFont {
+    /// Parses a text or binary descriptor, detected by the `BMF` magic.
+    pub fn from_bytes(data: &[u8]) -> io::Result<BmFont> {
+        if data.starts_with(b"BMF") {
+            Self::from_binary(data)
+        } else {
+            let text = std::str::from_utf8(data)
+                .map_err(|e| invalid_data(format!("BMFont text is not UTF-8: {}", e)))?;
+            Self::from_text(text)
+        }
+    }
+
+    pub fn from_text(text: &str) -> io::Result<BmFont> {
+        let mut font = BmFont::default();
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let (tag, values) = parse_text_line(line);
+
+            match tag {
+                "info" => {
+                    font.face = values.get("face").unwrap_or(&"").to_string();
+                    font.size = text_value(&values, "size", line_number)?;
+                }
+                "common" => {
+                    font.line_height = text_value(&values, "lineHeight", line_number)?;
+                    font.base = text_value(&values, "base", line_number)?;
+                    font.scale_w = text_value(&values, "scaleW", line_number)?;
+                    font.scale_h = text_value(&values, "scaleH", line_number)?;
+                }
+                "page" => {
+                    let id: usize = text_value(&values, "id", line_number)?;
+                    if font.pages.len() <= id {
+                        font.pages.resize(id + 1, String::new());
+                    }
+                    font.pages[id] = values.get("file").unwrap_or(&"").to_string();
+                }
+                "char" => font.chars.push(BmChar {
+                    id: text_value(&values, "id", line_number)?,
+                    x: text_value(&values, "x", line_number)?,
+                    y: text_value(&values, "y", line_number)?,
+                    width: text_value(&values, "width", line_number)?,
+                    height: text_value(&values, "height", line_number)?,
+                    xoffset: text_value(&values, "xoffset", line_number)?,
+                    yoffset: text_value(&values, "yoffset", line_number)?,
+                    xadvance: text_value(&values, "xadvance", line_number)?,
+                    page: text_value(&values, "page", line_number)?,
+                    chnl: values
+                        .get("chnl")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(15),
+                }),
+                _ => {}
+            }
+        }
+
+        Ok(font)
+    }
+
+    pub fn from_binary(data: &[u8]) -> io::Result<BmFont> {
+        if data.len() < 4 || &data[0..3] != b"BMF" || data[3] != 3 {
+            return Err(invalid_data(
+                "Unsupported BMFont binary version, only version 3 is supported".to_string(),
+            ));
+        }
+
+        let mut font = BmFont::default();
+        let mut pos = 4;
+
+        while pos + 5 <= data.len() {
+            let block_type = data[pos];
+            let block_size =
+                u32::from_le_bytes(data[pos + 1..pos + 5].try_into().unwrap()) as usize;
+            pos += 5;
+
+            if pos + block_size > data.len() {
+                return Err(invalid_data(format!(
+                    "BMFont block {} at offset {} is truncated",
+                    block_type,
+                    pos - 5
+                )));
+            }
+            let block = &data[pos..pos + block_size];
+            pos += block_size;
+
+            let u16_at = |offset: usize| u16::from_le_bytes([block[offset], block[offset + 1]]);
+
+            match block_type {
+                1 if block.len() >= 14 => {
+                    font.size = u16_at(0) as i16;
+                    let name = &block[14..];
+                    let end = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                    font.face = String::from_utf8_lossy(&name[..end]).into_owned();
+                }
+                2 if block.len() >= 10 => {
+                    font.line_height = u16_at(0);
+                    font.base = u16_at(2);
+                    font.scale_w = u16_at(4);
+                    font.scale_h = u16_at(6);
+                }
+                3 => {
+                    font.pages = block
+                        .split(|&b| b == 0)
+                        .filter(|name| !name.is_empty())
+                        .map(|name| String::from_utf8_lossy(name).into_owned())
+                        .collect();
+                }
+                4 => {
+                    for c in block.chunks_exact(20) {
+                        let u16_at = |offset: usize| u16::from_le_bytes([c[offset], c[offset + 1]]);
+                        font.chars.push(BmChar {
+                            id: u32::from_le_bytes(c[0..4].try_into().unwrap()),
+                            x: u16_at(4),
+                            y: u16_at(6),
+                            width: u16_at(8),
+                            height: u16_at(10),
+                            xoffset: u16_at(12) as i16,
+                            yoffset: u16_at(14) as i16,
+                            xadvance: u16_at(16) as i16,
+                            page: c[18],
+                            chnl: c[19],
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(font)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+
+        text.push_str(&format!(
+            "info face=\"{}\" size={} bold=0 italic=0 charset=\"\" unicode=1 stretchH=100 smooth=1 aa=1 padding=0,0,0,0 spacing=1,1 outline=0\n",
+            self.face, self.size
+        ));
+        text.push_str(&format!(
+            "common lineHeight={} base={} scaleW={} scaleH={} pages={} packed=0 alphaChnl=0 redChnl=4 greenChnl=4 blueChnl=4\n",
+            self.line_height,
+            self.base,
+            self.scale_w,
+            self.scale_h,
+            self.pages.len()
+        ));
+        for (id, file) in self.pages.iter().enumerate() {
+            text.push_str(&format!("page id={} file=\"{}\"\n", id, file));
+        }
+        text.push_str(&format!("chars count={}\n", self.chars.len()));
+        for c in &self.chars {
+            text.push_str(&format!(
+                "char id={} x={} y={} width={} height={} xoffset={} yoffset={} xadvance={} page={} chnl={}\n",
+                c.id, c.x, c.y, c.width, c.height, c.xoffset, c.yoffset, c.xadvance, c.page, c.chnl
+            ));
+        }
+
+        text
+    }
+
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut data = b"BMF\x03".to_vec();
+
+        let mut push_block = |block_type: u8, block: Vec<u8>| {
+            data.push(block_type);
+            data.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            data.extend_from_slice(&block);
+        };
+
+        // info: size, bit field (unicode, smooth), charset, stretchH, aa, padding, spacing, outline, face
+        let mut info = Vec::new();
+        info.extend_from_slice(&self.size.to_le_bytes());
+        info.push(0b1100_0000);
+        info.push(0);
+        info.extend_from_slice(&100u16.to_le_bytes());
+        info.extend_from_slice(&[1, 0, 0, 0, 0, 1, 1, 0]);
+        info.extend_from_slice(self.face.as_bytes());
+        info.push(0);
+        push_block(1, info);
+
+        // common: lineHeight, base, scaleW, scaleH, pages, bit field, alpha/red/green/blue channels
+        let mut common = Vec::new();
+        for value in [
+            self.line_height,
+            self.base,
+            self.scale_w,
+            self.scale_h,
+            self.pages.len() as u16,
+        ] {
+            common.extend_from_slice(&value.to_le_bytes());
+        }
+        common.extend_from_slice(&[0, 0, 4, 4, 4]);
+        push_block(2, common);
+
+        let mut pages = Vec::new();
+        for page in &self.pages {
+            pages.extend_from_slice(page.as_bytes());
+            pages.push(0);
+        }
+        push_block(3, pages);
+
+        let mut chars = Vec::with_capacity(self.chars.len() * 20);
+        for c in &self.chars {
+            chars.extend_from_slice(&c.id.to_le_bytes());
+            for value in [c.x, c.y, c.width, c.height] {
+                chars.extend_from_slice(&value.to_le_bytes());
+            }
+            for value in [c.xoffset, c.yoffset, c.xadvance] {
+                chars.extend_from_slice(&value.to_le_bytes());
+            }
+            chars.push(c.page);
+            chars.push(c.chnl);
+        }
+        push_block(4, chars);
+
+        data
+    }
+}
+
+/// Writes the font as a BMFont descriptor at `output` and its pages as `<stem>_<page>.png` next to it.
+///
+/// Every glyph is written once, under the Unicode code point of its own character code.
+/// V0 glyphs whose Shift-JIS code has no Unicode equivalent are left out.
+pub fn export_bmfont(
+    fnt: &Fnt,
+    output: &Path,
+    format: BmFontFormat,
+    page_size: u32,
+    progress: &ProgressFn,
+) -> Result<ExtractReport, FntError> {
+    let stem = output
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "font".to_string());
+    let output_dir = output.parent().unwrap_or(Path::new(""));
+    std::fs::create_dir_all(output_dir)?;
+
+    let (atlas, pages) = build_atlas(fnt, page_size, &format!("{}_", stem), progress)?;
+
+    let scale_w = pages.iter().map(|p| p.width()).max().unwrap_or(1);
+    let scale_h = pages.iter().map(|p| p.height()).max().unwrap_or(1);
+
+    let ascent = fnt.metadata.ascent;
+    let mut chars: BTreeMap<u32, BmChar> = BTreeMap::new();
+
+    for (glyph_id, atlas_glyph) in &atlas.glyphs {
+        let glyph_metadata = &fnt.metadata.glyphs[glyph_id];
+        let id = match glyph_metadata.code_type {
+            CodeType::Unicode => Some(glyph_metadata.char_code),
+            CodeType::Sjis => decode_sjis_u32(glyph_metadata.char_code).map(|c| c as u32),
+        };
+        let Some(id) = id else {
+            continue;
+        };
+
+        chars.entry(id).or_insert(BmChar {
+            id,
+            x: atlas_glyph.x as u16,
+            y: atlas_glyph.y as u16,
+            width: atlas_glyph.width as u16,
+            height: atlas_glyph.height as u16,
+            xoffset: atlas_glyph.bearing_x as i16,
+            yoffset: ascent as i16 - atlas_glyph.bearing_y as i16,
+            xadvance: atlas_glyph.advance as i16,
+            page: atlas_glyph.page as u8,
+            chnl: 15,
+        });
+    }
+
+    let line_height = fnt.metadata.ascent as u32 + fnt.metadata.descent as u32;
+    let font = BmFont {
+        face: stem,
+        size: line_height.min(i16::MAX as u32) as i16,
+        line_height: line_height.min(u16::MAX as u32) as u16,
+        base: ascent,
+        scale_w: scale_w as u16,
+        scale_h: scale_h as u16,
+        pages: atlas.pages.clone(),
+        chars: chars.into_values().collect(),
+    };
+
+    // BMFont pages are white with the glyph in the alpha channel, all the same size
+    for (page, filename) in pages.iter().zip(&atlas.pages) {
+        let mut image = image::RgbaImage::new(scale_w, scale_h);
+        for (x, y, pixel) in page.enumerate_pixels() {
+            image.put_pixel(x, y, image::Rgba([255, 255, 255, pixel.0[3]]));
+        }
+        image
+            .save(output_dir.join(filename))
+            .map_err(io::Error::other)?;
+    }
+
+    match format {
+        BmFontFormat::Text => std::fs::write(output, font.to_text())?,
+        BmFontFormat::Binary => std::fs::write(output, font.to_binary())?,
+    }
+
+    Ok(ExtractReport {
+        glyph_count: font.chars.len(),
+        page_count: atlas.pages.len(),
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct BmFontImportReport {
+    pub glyph_count: usize,
+    /// Character ids that could not be stored, because they are no Unicode scalar value or
+    /// have no Shift-JIS encoding (V0).
+    pub skipped_chars: Vec<u32>,
+}
+
+/// Picks the glyph coverage of a page pixel according to the char's channel.
+fn channel_value(pixel: &image::Rgba<u8>, chnl: u8, alpha_is_glyph: bool) -> u8 {
+    match chnl {
+        1 => pixel.0[2],
+        2 => pixel.0[1],
+        4 => pixel.0[0],
+        8 => pixel.0[3],
+        _ if alpha_is_glyph => pixel.0[3],
+        _ => pixel.0[0],
+    }
+}
+
+/// Builds an FNT4 font from a BMFont descriptor and its pages.
+///
+/// The ascent is the BMFont base line and the descent the rest of the line height.
+/// V0 fonts always have a single mipmap level.
+pub fn import_bmfont(
+    path: &Path,
+    version: FntVersion,
+    mipmap_level: usize,
+    progress: &ProgressFn,
+) -> io::Result<(Fnt, BmFontImportReport)> {
+    let font = BmFont::from_bytes(&std::fs::read(path)?)?;
+    let input_dir = path.parent().unwrap_or(Path::new(""));
+
+    let pages: Vec<(image::RgbaImage, bool)> = font
+        .pages
+        .iter()
+        .map(|filename| {
+            let image = image::open(input_dir.join(filename))
+                .map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("Failed to read BMFont page {:?}: {}", filename, e),
+                    )
+                })?
+                .to_rgba8();
+            // Pages without transparency store the glyph as brightness
+            let alpha_is_glyph = image.pixels().any(|p| p.0[3] != 255);
+            Ok((image, alpha_is_glyph))
+        })
+        .collect::<io::Result<_>>()?;
+
+    let (code_type, mipmap_level) = match version {
+        FntVersion::V0 => (CodeType::Sjis, 1),
+        FntVersion::V1 => (CodeType::Unicode, mipmap_level.clamp(1, 4)),
+    };
+
+    let encode = version.key_encoder();
+    let mut chars = font.chars.clone();
+    chars.sort_by_key(|c| c.id);
+    chars.dedup_by_key(|c| c.id);
+
+    let mut glyphs = BTreeMap::new();
+    let mut characters = BTreeMap::new();
+    let mut glyph_chars = BTreeMap::new();
+    let mut skipped_chars = Vec::new();
+
+    for c in chars {
+        let Some((char_code, char_index)) = char::from_u32(c.id).and_then(&encode) else {
+            skipped_chars.push(c.id);
+            continue;
+        };
+
+        let glyph_id = glyphs.len() as u32;
+        glyphs.insert(
+            glyph_id,
+            GlyphMetadata {
+                char_code,
+                code_type,
+                bearing_x: c.xoffset.clamp(i8::MIN as i16, i8::MAX as i16) as i8,
+                bearing_y: (font.base as i16 - c.yoffset).clamp(i8::MIN as i16, i8::MAX as i16)
+                    as i8,
+                advance: c.xadvance.clamp(0, 255) as u8,
+            },
+        );
+        characters.insert(char_index, glyph_id);
+        glyph_chars.insert(glyph_id, c);
+    }
+
+    // V0 tables cover the whole Shift-JIS range, unmapped characters show the space glyph
+    if version == FntVersion::V0 {
+        let default_glyph_id = encode(' ')
+            .and_then(|(_, index)| characters.get(&index))
+            .copied()
+            .unwrap_or(0);
+        for index in 0..generate_sjis_map().len() as u32 {
+            characters.entry(index).or_insert(default_glyph_id);
+        }
+    }
+
+    let ascent = font.base;
+    let descent = font.line_height.saturating_sub(font.base);
+
+    let metadata = FntMetadata {
+        version,
+        mipmap_level,
+        ascent,
+        descent,
+        characters,
+        glyphs,
+        mipmaps: None,
+    };
+
+    let processed_glyphs = process_loaded_glyphs(&metadata, progress, |glyph_id, glyph_info| {
+        let c = glyph_chars.get(&glyph_id)?;
+        let width = (c.width as u32).min(255);
+        let height = (c.height as u32).min(255);
+        let mut pixels = vec![0u8; (width * height) as usize];
+
+        if let Some((page, alpha_is_glyph)) = pages.get(c.page as usize) {
+            for y in 0..height {
+                for x in 0..width {
+                    let (px, py) = (c.x as u32 + x, c.y as u32 + y);
+                    if px < page.width() && py < page.height() {
+                        pixels[(y * width + x) as usize] =
+                            channel_value(page.get_pixel(px, py), c.chnl, *alpha_is_glyph);
+                    }
+                }
+            }
+        }
+
+        Some(LoadedGlyph {
+            glyph_info: *glyph_info,
+            image: MipmapImage {
+                width,
+                height,
+                pixels,
+            },
+            mipmaps: BTreeMap::new(),
+        })
+    });
+
+    let report = BmFontImportReport {
+        glyph_count: processed_glyphs.len(),
+        skipped_chars,
+    };
+
+    Ok((
+        Fnt::from_processed_glyphs(metadata, processed_glyphs),
+        report,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_font() -> BmFont {
+        BmFont {
+            face: "sample".to_string(),
+            size: 24,
+            line_height: 28,
+            base: 22,
+            scale_w: 256,
+            scale_h: 128,
+            pages: vec!["sample_0.png".to_string(), "sample_1.png".to_string()],
+            chars: vec![
+                BmChar {
+                    id: 0x20,
+                    x: 0,
+                    y: 0,
+                    width: 0,
+                    height: 0,
+                    xoffset: 0,
+                    yoffset: 0,
+                    xadvance: 6,
+                    page: 0,
+                    chnl: 15,
+                },
+                BmChar {
+                    id: 0x3042,
+                    x: 12,
+                    y: 40,
+                    width: 20,
+                    height: 21,
+                    xoffset: -1,
+                    yoffset: 3,
+                    xadvance: 24,
+                    page: 1,
+                    chnl: 8,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let font = sample_font();
+        assert_eq!(BmFont::from_bytes(font.to_text().as_bytes()).unwrap(), font);
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let font = sample_font();
+        assert_eq!(BmFont::from_bytes(&font.to_binary()).unwrap(), font);
+    }
+
+    #[test]
+    fn test_parse_text_line() {
+        let (tag, values) = parse_text_line(r#"info face="Noto Sans CJK" size=-24 charset="""#);
+        assert_eq!(tag, "info");
+        assert_eq!(values["face"], "Noto Sans CJK");
+        assert_eq!(values["size"], "-24");
+        assert_eq!(values["charset"], "");
+    }
+}
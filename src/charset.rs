@@ -0,0 +1,209 @@
+use std::collections::BTreeSet;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// A set of characters to build a font with, collected from several sources.
+///
+/// Control characters (line breaks, tabs) are never part of a charset.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Charset {
+    /// Characters given directly.
+    #[serde(default)]
+    pub chars: String,
+    /// Text files whose characters are all included.
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+    /// Unicode ranges, see [`parse_range`].
+    #[serde(default)]
+    pub ranges: Vec<String>,
+    /// Directories scanned recursively for the characters of every UTF-8 file in them, e.g.
+    /// script files or translation CSVs. Other files are skipped.
+    #[serde(default)]
+    pub script_dirs: Vec<PathBuf>,
+}
+
+impl Charset {
+    pub fn is_empty(&self) -> bool {
+        self.chars.is_empty()
+            && self.files.is_empty()
+            && self.ranges.is_empty()
+            && self.script_dirs.is_empty()
+    }
+
+    /// Collects the characters of all sources.
+    pub fn load(&self) -> io::Result<BTreeSet<char>> {
+        let mut chars: BTreeSet<char> = self.chars.chars().collect();
+
+        for path in &self.files {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            chars.extend(text.chars());
+        }
+        for range in &self.ranges {
+            chars.extend(parse_range(range)?);
+        }
+        for dir in &self.script_dirs {
+            scan_dir(dir, &mut chars)?;
+        }
+
+        chars.retain(|c| !c.is_control());
+        Ok(chars)
+    }
+}
+
+/// A class of characters that usually need the same metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CharClass {
+    /// CJK ideographs, with extension A and the compatibility ideographs.
+    Han,
+    /// Hiragana, katakana and halfwidth katakana.
+    Kana,
+    /// Latin letters, ASCII and accented.
+    Latin,
+    /// ASCII and fullwidth digits.
+    Digit,
+    /// ASCII, CJK and fullwidth punctuation.
+    Punctuation,
+    /// Fullwidth forms of ASCII characters, and the fullwidth signs.
+    Fullwidth,
+}
+
+impl CharClass {
+    /// The name used in configs and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            CharClass::Han => "han",
+            CharClass::Kana => "kana",
+            CharClass::Latin => "latin",
+            CharClass::Digit => "digit",
+            CharClass::Punctuation => "punctuation",
+            CharClass::Fullwidth => "fullwidth",
+        }
+    }
+
+    pub fn contains(self, c: char) -> bool {
+        match self {
+            CharClass::Han => matches!(
+                c,
+                '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' | '\u{F900}'..='\u{FAFF}'
+            ),
+            CharClass::Kana => matches!(
+                c,
+                '\u{3040}'..='\u{30FF}' | '\u{31F0}'..='\u{31FF}' | '\u{FF65}'..='\u{FF9F}'
+            ),
+            CharClass::Latin => c.is_alphabetic() && c <= '\u{024F}',
+            CharClass::Digit => c.is_ascii_digit() || matches!(c, '\u{FF10}'..='\u{FF19}'),
+            CharClass::Punctuation => {
+                c.is_ascii_punctuation()
+                    || matches!(c, '\u{3000}'..='\u{303F}')
+                    || (matches!(c, '\u{FF01}'..='\u{FF65}') && !c.is_alphanumeric())
+            }
+            CharClass::Fullwidth => matches!(c, '\u{FF01}'..='\u{FF60}' | '\u{FFE0}'..='\u{FFE6}'),
+        }
+    }
+}
+
+/// Parses a Unicode range: a single code point like `U+3000`, or an inclusive range like
+/// `U+4E00-U+9FFF`. The `U+` prefix is optional. Iterating the range skips surrogates.
+pub fn parse_range(range: &str) -> io::Result<RangeInclusive<char>> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Invalid Unicode range {:?}, expected e.g. U+4E00-U+9FFF",
+                range
+            ),
+        )
+    };
+    let parse_code_point = |s: &str| {
+        let s = s.trim();
+        let s = s
+            .strip_prefix("U+")
+            .or_else(|| s.strip_prefix("u+"))
+            .unwrap_or(s);
+        u32::from_str_radix(s, 16).ok().and_then(char::from_u32)
+    };
+
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_code_point(start), parse_code_point(end)),
+        None => (parse_code_point(range), parse_code_point(range)),
+    };
+    let (Some(start), Some(end)) = (start, end) else {
+        return Err(invalid());
+    };
+    if start > end {
+        return Err(invalid());
+    }
+
+    Ok(start..=end)
+}
+
+fn scan_dir(dir: &Path, chars: &mut BTreeSet<char>) -> io::Result<()> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?;
+
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            scan_dir(&path, chars)?;
+        } else if let Ok(text) = std::fs::read_to_string(&path) {
+            chars.extend(text.chars());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("U+0041").unwrap().collect::<String>(), "A");
+        assert_eq!(parse_range("u+30-39").unwrap().count(), 10);
+        // Surrogates are not characters
+        assert_eq!(parse_range("D7FF-E000").unwrap().count(), 2);
+
+        assert!(parse_range("U+9FFF-U+4E00").is_err());
+        assert!(parse_range("U+110000").is_err());
+        assert!(parse_range("abc-xyz").is_err());
+    }
+
+    #[test]
+    fn test_char_class() {
+        assert!(CharClass::Han.contains('漢'));
+        assert!(!CharClass::Han.contains('あ'));
+        assert!(CharClass::Kana.contains('カ') && CharClass::Kana.contains('ｶ'));
+        assert!(CharClass::Latin.contains('é') && !CharClass::Latin.contains('1'));
+        assert!(CharClass::Digit.contains('１'));
+        assert!(CharClass::Punctuation.contains('。') && CharClass::Punctuation.contains('！'));
+        assert!(!CharClass::Punctuation.contains('Ａ'));
+        assert!(CharClass::Fullwidth.contains('Ａ') && !CharClass::Fullwidth.contains('A'));
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir().join(format!("fnt4_charset_load_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("scripts/sub")).unwrap();
+        std::fs::write(dir.join("chars.txt"), "你好\r\n").unwrap();
+        std::fs::write(dir.join("scripts/a.csv"), "s,translated\nあ,我\n").unwrap();
+        std::fs::write(dir.join("scripts/sub/b.txt"), "们").unwrap();
+        std::fs::write(dir.join("scripts/image.bin"), [0xFF, 0xFE, 0x00, 0xD8]).unwrap();
+
+        let charset = Charset {
+            chars: "A".to_string(),
+            files: vec![dir.join("chars.txt")],
+            ranges: vec!["U+0030-U+0031".to_string()],
+            script_dirs: vec![dir.join("scripts")],
+        };
+        let chars: String = charset.load().unwrap().into_iter().collect();
+        assert_eq!(chars, ",01Aadelnrstあ们你好我");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
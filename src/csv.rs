@@ -0,0 +1,144 @@
+/// A CSV file as written by shin-translation-tools: comma separated, `"` quoted, UTF-8.
+///
+/// Only what is needed to rewrite columns in place is kept, so a file that is read and
+/// written again without changes comes out the same (up to quoting of fields that did not
+/// need it).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Csv {
+    /// All records, the header first.
+    pub rows: Vec<Vec<String>>,
+    line_ending: &'static str,
+    bom: bool,
+}
+
+impl Csv {
+    pub fn parse(text: &str) -> Csv {
+        let bom = text.starts_with('\u{feff}');
+        let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+        let line_ending = if text.contains("\r\n") { "\r\n" } else { "\n" };
+
+        let mut rows = Vec::new();
+        let mut row = Vec::new();
+        let mut field = String::new();
+        let mut in_quotes = false;
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if in_quotes {
+                match c {
+                    '"' if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    '"' => in_quotes = false,
+                    _ => field.push(c),
+                }
+                continue;
+            }
+
+            match c {
+                '"' => in_quotes = true,
+                ',' => row.push(std::mem::take(&mut field)),
+                '\r' if chars.peek() == Some(&'\n') => {}
+                '\n' => {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                }
+                _ => field.push(c),
+            }
+        }
+
+        if !field.is_empty() || !row.is_empty() {
+            row.push(field);
+            rows.push(row);
+        }
+
+        Csv {
+            rows,
+            line_ending,
+            bom,
+        }
+    }
+
+    pub fn read(path: &std::path::Path) -> std::io::Result<Csv> {
+        Ok(Csv::parse(&std::fs::read_to_string(path)?))
+    }
+
+    /// Index of a header column.
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.rows.first()?.iter().position(|field| field == name)
+    }
+
+    /// Values of a column, without the header. Rows too short for the column are skipped.
+    pub fn column_values(&self, index: usize) -> impl Iterator<Item = &str> {
+        self.rows
+            .iter()
+            .skip(1)
+            .filter_map(move |row| row.get(index).map(String::as_str))
+    }
+
+    /// Applies `f` to every value of a column, without the header.
+    pub fn map_column(&mut self, index: usize, f: impl Fn(&str) -> String) {
+        for row in self.rows.iter_mut().skip(1) {
+            if let Some(field) = row.get_mut(index) {
+                *field = f(field);
+            }
+        }
+    }
+
+    pub fn write(&self, path: &std::path::Path) -> std::io::Result<()> {
+        std::fs::write(path, self.to_string())
+    }
+}
+
+impl std::fmt::Display for Csv {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.bom {
+            f.write_str("\u{feff}")?;
+        }
+
+        for row in &self.rows {
+            for (i, field) in row.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(",")?;
+                }
+                if field.contains([',', '"', '\r', '\n']) {
+                    write!(f, "\"{}\"", field.replace('"', "\"\""))?;
+                } else {
+                    f.write_str(field)?;
+                }
+            }
+            f.write_str(self.line_ending)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_write() {
+        let text = "id,s,translated\r\n1,\"a, \"\"b\"\"\",\"line\nbreak\"\r\n2,,x\r\n";
+        let csv = Csv::parse(text);
+
+        assert_eq!(
+            csv.rows,
+            [
+                vec!["id", "s", "translated"],
+                vec!["1", "a, \"b\"", "line\nbreak"],
+                vec!["2", "", "x"],
+            ]
+        );
+        assert_eq!(csv.column("translated"), Some(2));
+        assert_eq!(csv.column_values(1).collect::<Vec<_>>(), ["a, \"b\"", ""]);
+        assert_eq!(csv.to_string(), text);
+
+        // No trailing line break, BOM kept
+        let csv = Csv::parse("\u{feff}a,b\n1,2");
+        assert_eq!(csv.column("a"), Some(0));
+        assert_eq!(csv.to_string(), "\u{feff}a,b\n1,2\n");
+    }
+}
@@ -0,0 +1,356 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rayon::prelude::*;
+
+use crate::error::FntError;
+use crate::fnt::Fnt;
+use crate::glyph::Glyph;
+use crate::utils::ProgressFn;
+
+/// Columns of glyphs in the contact sheet.
+const SHEET_COLUMNS: u32 = 8;
+/// Pixels between the old, new and overlay panels of a glyph.
+const PANEL_SPACING: u32 = 2;
+/// Pixels between glyphs in the contact sheet.
+const CELL_SPACING: u32 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old: i32,
+    pub new: i32,
+}
+
+/// Returns a change for every `(field, old, new)` whose values differ.
+fn field_changes(fields: &[(&'static str, i32, i32)]) -> Vec<FieldChange> {
+    fields
+        .iter()
+        .filter(|(_, old, new)| old != new)
+        .map(|&(field, old, new)| FieldChange { field, old, new })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct GlyphDiff {
+    pub character: char,
+    pub old_glyph_id: u32,
+    pub new_glyph_id: u32,
+    /// Changes of `bearing_x`, `bearing_y`, `advance`, `width` and `height`.
+    pub metric_changes: Vec<FieldChange>,
+    /// Pixels whose alpha differs by more than the threshold, with both glyphs placed on the
+    /// same pen position and baseline.
+    pub changed_pixels: usize,
+    /// Alpha of the old glyph, over the area covered by either glyph.
+    pub old_bitmap: image::GrayImage,
+    /// Alpha of the new glyph, over the same area as `old_bitmap`.
+    pub new_bitmap: image::GrayImage,
+}
+
+#[derive(Debug, Clone)]
+pub struct FntDiff {
+    /// Changes of `version`, `ascent`, `descent` and `mipmap_level`.
+    pub header_changes: Vec<FieldChange>,
+    /// Characters that only have a glyph of their own in the new font.
+    pub added_chars: Vec<char>,
+    /// Characters that only have a glyph of their own in the old font.
+    pub removed_chars: Vec<char>,
+    /// Characters in both fonts whose glyph metrics or pixels changed.
+    pub changed_glyphs: Vec<GlyphDiff>,
+}
+
+/// Compares two fonts character by character, by Unicode character so V0 and V1 fonts
+/// can be compared too.
+///
+/// Characters that fall back to the default glyph count as unmapped. Pixels are compared
+/// with both glyphs placed on the same pen position and baseline, so a glyph that moved
+/// has changed pixels as well.
+pub fn diff_fnt(
+    old: &Fnt,
+    new: &Fnt,
+    threshold: u8,
+    progress: &ProgressFn,
+) -> Result<FntDiff, FntError> {
+    let (old_meta, new_meta) = (&old.metadata, &new.metadata);
+    let header_changes = field_changes(&[
+        ("version", old_meta.version as i32, new_meta.version as i32),
+        ("ascent", old_meta.ascent as i32, new_meta.ascent as i32),
+        ("descent", old_meta.descent as i32, new_meta.descent as i32),
+        (
+            "mipmap_level",
+            old_meta.mipmap_level as i32,
+            new_meta.mipmap_level as i32,
+        ),
+    ]);
+
+    let old_chars = old_meta.mapped_characters();
+    let new_chars = new_meta.mapped_characters();
+
+    let added_chars = new_chars
+        .keys()
+        .filter(|c| !old_chars.contains_key(c))
+        .copied()
+        .collect();
+    let removed_chars = old_chars
+        .keys()
+        .filter(|c| !new_chars.contains_key(c))
+        .copied()
+        .collect();
+
+    let old_glyphs = old.decode_glyphs(&|_, _| {})?;
+    let new_glyphs = new.decode_glyphs(&|_, _| {})?;
+
+    let pairs: Vec<(char, u32, u32)> = old_chars
+        .iter()
+        .filter_map(|(&c, &old_id)| Some((c, old_id, *new_chars.get(&c)?)))
+        .collect();
+
+    let total = pairs.len();
+    let counter = AtomicUsize::new(0);
+
+    let changed_glyphs: Vec<GlyphDiff> = pairs
+        .par_iter()
+        .filter_map(|&(character, old_glyph_id, new_glyph_id)| {
+            let done = counter.fetch_add(1, Ordering::Relaxed) + 1;
+            progress(done, total);
+
+            let (metric_changes, changed_pixels, old_bitmap, new_bitmap) = diff_glyph(
+                old_glyphs.get(&old_glyph_id)?,
+                new_glyphs.get(&new_glyph_id)?,
+                threshold,
+            )?;
+            Some(GlyphDiff {
+                character,
+                old_glyph_id,
+                new_glyph_id,
+                metric_changes,
+                changed_pixels,
+                old_bitmap,
+                new_bitmap,
+            })
+        })
+        .collect();
+
+    Ok(FntDiff {
+        header_changes,
+        added_chars,
+        removed_chars,
+        changed_glyphs,
+    })
+}
+
+type GlyphComparison = (Vec<FieldChange>, usize, image::GrayImage, image::GrayImage);
+
+/// Compares two glyphs, or returns `None` when they are the same.
+fn diff_glyph(old: &Glyph, new: &Glyph, threshold: u8) -> Option<GlyphComparison> {
+    let (old_width, old_height) = old.mipmap_size(0);
+    let (new_width, new_height) = new.mipmap_size(0);
+
+    let metric_changes = field_changes(&[
+        (
+            "bearing_x",
+            old.info.bearing_x as i32,
+            new.info.bearing_x as i32,
+        ),
+        (
+            "bearing_y",
+            old.info.bearing_y as i32,
+            new.info.bearing_y as i32,
+        ),
+        ("advance", old.info.advance as i32, new.info.advance as i32),
+        ("width", old_width as i32, new_width as i32),
+        ("height", old_height as i32, new_height as i32),
+    ]);
+
+    // Area covered by either glyph, relative to the pen position and baseline
+    let old_rect = glyph_rect(old);
+    let new_rect = glyph_rect(new);
+    let left = old_rect.0.min(new_rect.0);
+    let top = old_rect.1.min(new_rect.1);
+    let right = old_rect.2.max(new_rect.2);
+    let bottom = old_rect.3.max(new_rect.3);
+    let (width, height) = ((right - left).max(0) as u32, (bottom - top).max(0) as u32);
+
+    let old_bitmap = place_alpha(old, left - old_rect.0, top - old_rect.1, width, height);
+    let new_bitmap = place_alpha(new, left - new_rect.0, top - new_rect.1, width, height);
+
+    let changed_pixels = old_bitmap
+        .as_raw()
+        .iter()
+        .zip(new_bitmap.as_raw())
+        .filter(|&(&a, &b)| a.abs_diff(b) > threshold)
+        .count();
+
+    if metric_changes.is_empty() && changed_pixels == 0 {
+        return None;
+    }
+
+    Some((metric_changes, changed_pixels, old_bitmap, new_bitmap))
+}
+
+/// `(left, top, right, bottom)` of the level 0 bitmap, relative to the pen position and baseline.
+fn glyph_rect(glyph: &Glyph) -> (i32, i32, i32, i32) {
+    let (width, height) = glyph.mipmap_size(0);
+    let left = glyph.info.bearing_x as i32;
+    let top = -(glyph.info.bearing_y as i32);
+    (left, top, left + width as i32, top + height as i32)
+}
+
+/// Alpha of the glyph in a `width` x `height` image, moved by `(-offset_x, -offset_y)`.
+fn place_alpha(
+    glyph: &Glyph,
+    offset_x: i32,
+    offset_y: i32,
+    width: u32,
+    height: u32,
+) -> image::GrayImage {
+    let mut bitmap = image::GrayImage::new(width, height);
+    for (x, y, pixel) in glyph.to_image(0).enumerate_pixels() {
+        let (bx, by) = (x as i32 - offset_x, y as i32 - offset_y);
+        if bx >= 0 && by >= 0 && (bx as u32) < width && (by as u32) < height {
+            bitmap.put_pixel(bx as u32, by as u32, image::Luma([pixel.0[3]]));
+        }
+    }
+    bitmap
+}
+
+/// Draws every changed glyph as three panels: old, new, and both overlaid with ink only in
+/// the old glyph in red and ink only in the new glyph in blue.
+///
+/// Glyphs are in the order of [`FntDiff::changed_glyphs`], row by row. Returns `None` when
+/// no glyph changed.
+pub fn render_contact_sheet(diff: &FntDiff) -> Option<image::RgbaImage> {
+    if diff.changed_glyphs.is_empty() {
+        return None;
+    }
+
+    let panel_width = diff
+        .changed_glyphs
+        .iter()
+        .map(|g| g.old_bitmap.width())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let panel_height = diff
+        .changed_glyphs
+        .iter()
+        .map(|g| g.old_bitmap.height())
+        .max()
+        .unwrap_or(0)
+        .max(1);
+
+    let cell_width = panel_width * 3 + PANEL_SPACING * 2 + CELL_SPACING;
+    let cell_height = panel_height + CELL_SPACING;
+    let count = diff.changed_glyphs.len() as u32;
+    let columns = count.min(SHEET_COLUMNS);
+    let rows = count.div_ceil(SHEET_COLUMNS);
+
+    let mut sheet = image::RgbaImage::from_pixel(
+        columns * cell_width + CELL_SPACING,
+        rows * cell_height + CELL_SPACING,
+        image::Rgba([160, 160, 160, 255]),
+    );
+
+    for (i, glyph) in diff.changed_glyphs.iter().enumerate() {
+        let cell_x = CELL_SPACING + (i as u32 % SHEET_COLUMNS) * cell_width;
+        let cell_y = CELL_SPACING + (i as u32 / SHEET_COLUMNS) * cell_height;
+
+        for panel in 0..3 {
+            let panel_x = cell_x + panel * (panel_width + PANEL_SPACING);
+            for y in 0..panel_height {
+                for x in 0..panel_width {
+                    let old = glyph
+                        .old_bitmap
+                        .get_pixel_checked(x, y)
+                        .map_or(0, |p| p.0[0]);
+                    let new = glyph
+                        .new_bitmap
+                        .get_pixel_checked(x, y)
+                        .map_or(0, |p| p.0[0]);
+                    let color = match panel {
+                        0 => [255 - old, 255 - old, 255 - old],
+                        1 => [255 - new, 255 - new, 255 - new],
+                        _ => [255 - new, 255 - old.max(new), 255 - old],
+                    };
+                    sheet.put_pixel(
+                        panel_x + x,
+                        cell_y + y,
+                        image::Rgba([color[0], color[1], color[2], 255]),
+                    );
+                }
+            }
+        }
+    }
+
+    Some(sheet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fnt::build_test_fnt;
+    use crate::metadata::FntVersion;
+
+    #[test]
+    fn test_diff_fnt() {
+        let old = build_test_fnt(
+            FntVersion::V1,
+            1,
+            12,
+            &[('\0', 4, 6), ('a', 6, 8), ('b', 6, 8), ('c', 6, 8)],
+        );
+        let new = build_test_fnt(
+            FntVersion::V1,
+            1,
+            13,
+            &[('\0', 4, 6), ('a', 6, 8), ('b', 7, 9), ('d', 6, 8)],
+        );
+        // Written fonts map every other character to the default glyph
+        let new = Fnt::from_data(&new.to_bytes().unwrap()).unwrap();
+
+        let diff = diff_fnt(&old, &new, 0, &|_, _| {}).unwrap();
+
+        assert_eq!(
+            diff.header_changes,
+            [FieldChange {
+                field: "ascent",
+                old: 12,
+                new: 13
+            }]
+        );
+        assert_eq!(diff.added_chars, ['d']);
+        assert_eq!(diff.removed_chars, ['c']);
+
+        assert_eq!(diff.changed_glyphs.len(), 1);
+        let glyph = &diff.changed_glyphs[0];
+        assert_eq!(glyph.character, 'b');
+        assert_eq!(
+            glyph.metric_changes,
+            [
+                FieldChange {
+                    field: "advance",
+                    old: 8,
+                    new: 9
+                },
+                FieldChange {
+                    field: "width",
+                    old: 6,
+                    new: 7
+                },
+            ]
+        );
+        // One more column of 10 pixels
+        assert_eq!(glyph.changed_pixels, 10);
+        assert_eq!(glyph.old_bitmap.dimensions(), (7, 10));
+
+        let sheet = render_contact_sheet(&diff).unwrap();
+        let panel_x = CELL_SPACING + 7 + PANEL_SPACING;
+        // The new glyph is black in its own panel, the added column is blue in the overlay
+        assert_eq!(sheet.get_pixel(panel_x + 6, CELL_SPACING).0, [0, 0, 0, 255]);
+        assert_eq!(
+            sheet
+                .get_pixel(panel_x * 2 - CELL_SPACING + 6, CELL_SPACING)
+                .0,
+            [0, 0, 255, 255]
+        );
+    }
+}
@@ -0,0 +1,347 @@
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::glyph::RenderedGlyph;
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+/// Outline, glow and shadow baked into the alpha of the rendered glyphs.
+///
+/// All effects are drawn behind the glyph and merged into its single alpha channel. The
+/// glyph texture grows by the space the effects need, and the bearings move with it so the
+/// glyph itself stays in place. Advances are not changed, use `letter_spacing` to make room
+/// for wide outlines.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GlyphEffects {
+    /// Width in pixels of the outline around the glyph.
+    #[serde(default)]
+    pub stroke_width: f32,
+    /// Radius in pixels of the soft glow around the glyph and its outline.
+    #[serde(default)]
+    pub glow_radius: f32,
+    /// Opacity of the glow, from 0.0 to 1.0.
+    #[serde(default = "default_opacity")]
+    pub glow_opacity: f32,
+    /// Pixels the shadow of the glyph and its outline is moved right, or left when negative.
+    #[serde(default)]
+    pub shadow_offset_x: i8,
+    /// Pixels the shadow is moved up, or down when negative.
+    #[serde(default)]
+    pub shadow_offset_y: i8,
+    /// Opacity of the shadow, from 0.0 to 1.0.
+    #[serde(default = "default_opacity")]
+    pub shadow_opacity: f32,
+}
+
+impl Default for GlyphEffects {
+    fn default() -> Self {
+        GlyphEffects {
+            stroke_width: 0.0,
+            glow_radius: 0.0,
+            glow_opacity: default_opacity(),
+            shadow_offset_x: 0,
+            shadow_offset_y: 0,
+            shadow_opacity: default_opacity(),
+        }
+    }
+}
+
+impl GlyphEffects {
+    fn has_shadow(&self) -> bool {
+        (self.shadow_offset_x != 0 || self.shadow_offset_y != 0) && self.shadow_opacity > 0.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stroke_width <= 0.0
+            && (self.glow_radius <= 0.0 || self.glow_opacity <= 0.0)
+            && !self.has_shadow()
+    }
+
+    /// Draws the effects around a glyph. Empty glyphs (spaces) are returned unchanged.
+    ///
+    /// Fails when the grown glyph is wider or higher than `max_size` pixels, or its bearings
+    /// no longer fit in a byte.
+    pub fn apply(&self, glyph: &RenderedGlyph, max_size: usize) -> io::Result<RenderedGlyph> {
+        if self.is_empty() || glyph.actual_width == 0 || glyph.actual_height == 0 {
+            return Ok(glyph.clone());
+        }
+
+        let stroke_width = self.stroke_width.max(0.0);
+        let glow_radius = if self.glow_opacity > 0.0 {
+            self.glow_radius.max(0.0)
+        } else {
+            0.0
+        };
+        let (shadow_x, shadow_y) = if self.has_shadow() {
+            // Image rows go down
+            (
+                self.shadow_offset_x as isize,
+                -(self.shadow_offset_y as isize),
+            )
+        } else {
+            (0, 0)
+        };
+
+        let margin = stroke_width.ceil() as usize + glow_radius.ceil() as usize;
+        let left = margin + shadow_x.min(0).unsigned_abs();
+        let right = margin + shadow_x.max(0) as usize;
+        let top = margin + shadow_y.min(0).unsigned_abs();
+        let bottom = margin + shadow_y.max(0) as usize;
+
+        let (glyph_width, glyph_height) =
+            (glyph.actual_width as usize, glyph.actual_height as usize);
+        let width = glyph_width + left + right;
+        let height = glyph_height + top + bottom;
+        if width > max_size || height > max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "effects grow the glyph to {}x{} pixels, above the limit of {}",
+                    width, height, max_size
+                ),
+            ));
+        }
+        let bearing_x = glyph.bearing_x as isize - left as isize;
+        let bearing_y = glyph.bearing_y as isize + top as isize;
+        let (Ok(bearing_x), Ok(bearing_y)) = (i8::try_from(bearing_x), i8::try_from(bearing_y))
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "effects move the glyph bearings to ({}, {}), outside -128 to 127",
+                    bearing_x, bearing_y
+                ),
+            ));
+        };
+
+        let mut shape = vec![0.0f32; width * height];
+        for y in 0..glyph_height {
+            for x in 0..glyph_width {
+                shape[(y + top) * width + x + left] =
+                    glyph.raw_pixels[y * glyph_width + x] as f32 / 255.0;
+            }
+        }
+        if stroke_width > 0.0 {
+            shape = dilate(&shape, width, height, stroke_width);
+        }
+
+        let mut layers = vec![shape.clone()];
+        if glow_radius > 0.0 {
+            let mut glow = blur(&shape, width, height, glow_radius);
+            glow.iter_mut()
+                .for_each(|a| *a *= self.glow_opacity.min(1.0));
+            layers.push(glow);
+        }
+        if shadow_x != 0 || shadow_y != 0 {
+            let mut shadow = vec![0.0f32; width * height];
+            for y in 0..height {
+                for x in 0..width {
+                    let (src_x, src_y) = (x as isize - shadow_x, y as isize - shadow_y);
+                    if (0..width as isize).contains(&src_x) && (0..height as isize).contains(&src_y)
+                    {
+                        shadow[y * width + x] = shape[src_y as usize * width + src_x as usize]
+                            * self.shadow_opacity.min(1.0);
+                    }
+                }
+            }
+            layers.push(shadow);
+        }
+
+        // Layers drawn over each other in one alpha channel
+        let alpha = (0..width * height).map(|i| {
+            1.0 - layers
+                .iter()
+                .map(|layer| 1.0 - layer[i].clamp(0.0, 1.0))
+                .product::<f32>()
+        });
+
+        let raw_pixels = alpha.map(|a| (a * 255.0).round() as u8).collect();
+
+        Ok(RenderedGlyph {
+            bearing_x,
+            bearing_y,
+            advance: glyph.advance,
+            actual_width: width as u8,
+            actual_height: height as u8,
+            raw_pixels,
+        })
+    }
+}
+
+/// Grows the coverage by `radius` pixels in every direction, with antialiased edges.
+fn dilate(src: &[f32], width: usize, height: usize, radius: f32) -> Vec<f32> {
+    let reach = radius.ceil() as isize;
+    let mut dst = vec![0.0f32; src.len()];
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let mut value = 0.0f32;
+            for dy in -reach..=reach {
+                for dx in -reach..=reach {
+                    let (sx, sy) = (x + dx, y + dy);
+                    if sx < 0 || sy < 0 || sx >= width as isize || sy >= height as isize {
+                        continue;
+                    }
+                    let distance = ((dx * dx + dy * dy) as f32).sqrt();
+                    // The pixel spans distance - 0.5 to distance + 0.5 from the source pixel
+                    // center, the outline reaches radius past its edge
+                    let weight = (radius + 1.0 - distance).clamp(0.0, 1.0);
+                    value = value.max(src[sy as usize * width + sx as usize] * weight);
+                }
+            }
+            dst[y as usize * width + x as usize] = value;
+        }
+    }
+    dst
+}
+
+/// Gaussian blur reaching `radius` pixels.
+fn blur(src: &[f32], width: usize, height: usize, radius: f32) -> Vec<f32> {
+    let reach = radius.ceil() as isize;
+    let sigma = radius / 2.0;
+    let kernel: Vec<f32> = (-reach..=reach)
+        .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    let kernel: Vec<f32> = kernel.iter().map(|k| k / sum).collect();
+
+    let pass = |src: &[f32], step: (isize, isize)| {
+        let mut dst = vec![0.0f32; src.len()];
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                let mut value = 0.0;
+                for (k, weight) in (-reach..=reach).zip(&kernel) {
+                    let (sx, sy) = (x + k * step.0, y + k * step.1);
+                    if sx >= 0 && sy >= 0 && sx < width as isize && sy < height as isize {
+                        value += src[sy as usize * width + sx as usize] * weight;
+                    }
+                }
+                dst[y as usize * width + x as usize] = value;
+            }
+        }
+        dst
+    };
+
+    pass(&pass(src, (1, 0)), (0, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dot() -> RenderedGlyph {
+        RenderedGlyph {
+            bearing_x: 1,
+            bearing_y: 5,
+            advance: 8,
+            actual_width: 1,
+            actual_height: 1,
+            raw_pixels: vec![255],
+        }
+    }
+
+    #[test]
+    fn test_stroke() {
+        let effects = GlyphEffects {
+            stroke_width: 1.0,
+            ..Default::default()
+        };
+        let stroked = effects.apply(&dot(), 128).unwrap();
+
+        // 1 pixel of room on every side, the glyph stays where it was
+        assert_eq!((stroked.actual_width, stroked.actual_height), (3, 3));
+        assert_eq!((stroked.bearing_x, stroked.bearing_y), (0, 6));
+        assert_eq!(stroked.advance, 8);
+        // Diagonal neighbours are partly covered
+        assert_eq!(
+            stroked.raw_pixels,
+            [149, 255, 149, 255, 255, 255, 149, 255, 149]
+        );
+
+        let effects = GlyphEffects {
+            stroke_width: 1.5,
+            ..Default::default()
+        };
+        let stroked = effects.apply(&dot(), 128).unwrap();
+        assert_eq!((stroked.actual_width, stroked.actual_height), (5, 5));
+        assert_eq!(stroked.raw_pixels[2 * 5], 128);
+    }
+
+    #[test]
+    fn test_shadow_and_glow() {
+        let effects = GlyphEffects {
+            shadow_offset_x: 2,
+            shadow_offset_y: -1,
+            shadow_opacity: 0.5,
+            ..Default::default()
+        };
+        let shadowed = effects.apply(&dot(), 128).unwrap();
+
+        // Room for the shadow to the right and below only
+        assert_eq!((shadowed.actual_width, shadowed.actual_height), (3, 2));
+        assert_eq!((shadowed.bearing_x, shadowed.bearing_y), (1, 5));
+        assert_eq!(shadowed.raw_pixels, [255, 0, 0, 0, 0, 128]);
+
+        let effects = GlyphEffects {
+            glow_radius: 2.0,
+            glow_opacity: 0.5,
+            ..Default::default()
+        };
+        let glowing = effects.apply(&dot(), 128).unwrap();
+        assert_eq!((glowing.actual_width, glowing.actual_height), (5, 5));
+        assert_eq!(glowing.raw_pixels[2 * 5 + 2], 255);
+        assert!(glowing.raw_pixels[2 * 5 + 1] > 0);
+
+        // Spaces and empty effects stay as they are
+        let space = RenderedGlyph {
+            actual_width: 0,
+            actual_height: 0,
+            raw_pixels: vec![],
+            ..dot()
+        };
+        assert_eq!(effects.apply(&space, 128).unwrap().actual_width, 0);
+        assert_eq!(
+            GlyphEffects::default()
+                .apply(&dot(), 128)
+                .unwrap()
+                .raw_pixels,
+            [255]
+        );
+    }
+
+    #[test]
+    fn test_oversize_effects() {
+        let wide = RenderedGlyph {
+            actual_width: 120,
+            raw_pixels: vec![255; 120],
+            ..dot()
+        };
+        let effects = GlyphEffects {
+            stroke_width: 4.0,
+            ..Default::default()
+        };
+        // 120 + 2 * 4 fits exactly
+        let stroked = effects.apply(&wide, 128).unwrap();
+        assert_eq!(stroked.actual_width, 128);
+
+        let effects = GlyphEffects {
+            glow_radius: 5.0,
+            ..effects
+        };
+        let error = effects.apply(&wide, 128).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        // A shadow far below a glyph high above the baseline
+        let high = RenderedGlyph {
+            bearing_y: 120,
+            ..dot()
+        };
+        let effects = GlyphEffects {
+            shadow_offset_y: 10,
+            ..Default::default()
+        };
+        assert!(effects.apply(&high, 128).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FntError {
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error(
+        "data too short for {field} at offset 0x{offset:X}: need {needed} bytes, only {available} available"
+    )]
+    TooShort {
+        field: &'static str,
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+
+    #[error("invalid magic number {0:02X?}, expected \"FNT4\"")]
+    InvalidMagic([u8; 4]),
+
+    #[error("unknown FNT4 version")]
+    UnknownVersion,
+
+    #[error("FNT4 font size in header ({header}) does not match actual data size ({actual})")]
+    FileSizeMismatch { header: u32, actual: usize },
+
+    #[error(
+        "character table at offset 0x{offset:X} is invalid: first glyph offset 0x{first_glyph_offset:X}"
+    )]
+    InvalidCharacterTable {
+        offset: usize,
+        first_glyph_offset: u32,
+    },
+
+    #[error(
+        "LZ77 back-reference at input offset {input_offset} reaches {back_offset} bytes back, but only {output_len} bytes were decoded"
+    )]
+    InvalidBackReference {
+        input_offset: usize,
+        back_offset: usize,
+        output_len: usize,
+    },
+
+    #[error("LZ77 back-reference at input offset {input_offset} is truncated")]
+    TruncatedBackReference { input_offset: usize },
+
+    #[error("glyph at offset 0x{glyph_offset:X} (character index {character_index}): {source}")]
+    Glyph {
+        glyph_offset: u32,
+        character_index: usize,
+        #[source]
+        source: Box<FntError>,
+    },
+
+    #[error("glyph {glyph_id}: {source}")]
+    GlyphData {
+        glyph_id: u32,
+        #[source]
+        source: Box<FntError>,
+    },
+}
+
+impl FntError {
+    pub(crate) fn too_short(
+        field: &'static str,
+        data: &[u8],
+        offset: usize,
+        needed: usize,
+    ) -> FntError {
+        FntError::TooShort {
+            field,
+            offset,
+            needed,
+            available: data.len().saturating_sub(offset),
+        }
+    }
+}
@@ -1,13 +1,30 @@
-use std::io::Write;
 use std::path::Path;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use rayon::prelude::*;
 
+use crate::error::FntError;
 use crate::fnt::Fnt;
 use crate::glyph::Glyph;
+use crate::utils::ProgressFn;
 
-pub fn extract_fnt(fnt: &Fnt, output_dir: &Path) -> std::io::Result<()> {
+/// File name of a glyph mipmap level PNG, e.g. `0042_8140_0.png`.
+pub fn glyph_png_filename(glyph_id: u32, char_code: u32, level: u8) -> String {
+    format!("{:04}_{:04x}_{}.png", glyph_id, char_code, level)
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractReport {
+    pub glyph_count: usize,
+    /// Number of atlas pages written, 0 when every glyph is written as its own PNG.
+    pub page_count: usize,
+}
+
+pub fn extract_fnt(
+    fnt: &Fnt,
+    output_dir: &Path,
+    progress: &ProgressFn,
+) -> Result<ExtractReport, FntError> {
     std::fs::create_dir_all(output_dir)?;
 
     let lazy_glyphs = fnt.lazy_glyphs.clone();
@@ -19,26 +36,28 @@ pub fn extract_fnt(fnt: &Fnt, output_dir: &Path) -> std::io::Result<()> {
     let total = lazy_glyphs.len();
     let counter = AtomicUsize::new(0);
 
-    lazy_glyphs.par_iter().for_each(|(glyph_id, lazy_glyph)| {
-        let glyph = Glyph::from_lazy_glyph(lazy_glyph, fnt.metadata.version);
-        let info = &lazy_glyph.info;
-        let filename = format!("{:04}_{:04x}_0.png", glyph_id, info.char_code);
-        let glyph_path = output_dir.join(&filename);
-        glyph.write_png(&glyph_path).unwrap();
-
-        let done = counter.fetch_add(1, Ordering::Relaxed) + 1;
-        if done % 100 == 0 || done == total {
-            print!(
-                "\rExporting glyphs: {}/{} ({:.1}%)",
-                done,
-                total,
-                done as f64 / total as f64 * 100.0
-            );
-            std::io::stdout().flush().ok();
-        }
-    });
-
-    println!();
-
-    Ok(())
+    lazy_glyphs
+        .par_iter()
+        .try_for_each(|(glyph_id, lazy_glyph)| -> Result<(), FntError> {
+            let glyph = Glyph::from_lazy_glyph(lazy_glyph, fnt.metadata.version).map_err(|e| {
+                FntError::GlyphData {
+                    glyph_id: *glyph_id,
+                    source: Box::new(e),
+                }
+            })?;
+            for &level in glyph.mipmap.keys() {
+                let filename = glyph_png_filename(*glyph_id, lazy_glyph.info.char_code, level);
+                glyph.write_png(level, &output_dir.join(&filename))?;
+            }
+
+            let done = counter.fetch_add(1, Ordering::Relaxed) + 1;
+            progress(done, total);
+
+            Ok(())
+        })?;
+
+    Ok(ExtractReport {
+        glyph_count: total,
+        page_count: 0,
+    })
 }
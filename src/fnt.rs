@@ -1,10 +1,14 @@
 use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rayon::prelude::*;
 
 use crate::{
     crc32,
-    glyph::{GlyphData, GlyphHeader, GlyphInfo, LazyGlyph, ProcessedGlyph},
+    error::FntError,
+    glyph::{Glyph, GlyphData, GlyphHeader, GlyphInfo, LazyGlyph, ProcessedGlyph},
     metadata::{CodeType, FntMetadata, FntVersion, GlyphMetadata, detect_mipmap_level},
-    utils::generate_sjis_map,
+    utils::{ProgressFn, generate_sjis_map},
 };
 
 #[derive(Debug)]
@@ -28,14 +32,14 @@ impl FntHeader {
     pub const SIZE_V0: usize = 16;
     pub const SIZE_V1: usize = 16;
 
-    pub fn from_data(data: &[u8]) -> Result<Self, &'static str> {
+    pub fn from_data(data: &[u8]) -> Result<Self, FntError> {
         if data.len() < 16 {
-            return Err("Data too short for FNT4 header");
+            return Err(FntError::too_short("FNT4 header", data, 0, 16));
         }
 
         let magic: [u8; 4] = data[0..4].try_into().unwrap();
         if &magic != b"FNT4" {
-            return Err("Invalid magic number");
+            return Err(FntError::InvalidMagic(magic));
         }
 
         // Check version based on data layout
@@ -68,7 +72,7 @@ impl FntHeader {
                 descent,
             })
         } else {
-            Err("Unknown FNT4 version")
+            Err(FntError::UnknownVersion)
         }
     }
 
@@ -102,16 +106,33 @@ impl FntHeader {
 }
 
 impl Fnt {
-    pub fn from_data(data: &[u8]) -> Result<Fnt, &'static str> {
+    pub fn from_data(data: &[u8]) -> Result<Fnt, FntError> {
         let header = FntHeader::from_data(data)?;
 
         if header.file_size as usize != data.len() {
-            return Err("FNT4 font size in header does not match actual data size");
+            return Err(FntError::FileSizeMismatch {
+                header: header.file_size,
+                actual: data.len(),
+            });
         }
 
         // Calculate character table size
-        let first_glyph_offset = u32::from_le_bytes(data[0x10..0x14].try_into().unwrap());
-        let character_size = ((first_glyph_offset as usize) - 0x10) / 4;
+        let table_start = header.size();
+        if data.len() < table_start + 4 {
+            return Err(FntError::too_short("character table", data, table_start, 4));
+        }
+
+        let first_glyph_offset =
+            u32::from_le_bytes(data[table_start..table_start + 4].try_into().unwrap());
+        if (first_glyph_offset as usize) < table_start + 4
+            || first_glyph_offset as usize > data.len()
+        {
+            return Err(FntError::InvalidCharacterTable {
+                offset: table_start,
+                first_glyph_offset,
+            });
+        }
+        let character_size = ((first_glyph_offset as usize) - table_start) / 4;
 
         // Read character table
         let mut character_table: Vec<u32> = Vec::with_capacity(character_size);
@@ -160,7 +181,12 @@ impl Fnt {
             };
 
             let lazy_glyph =
-                LazyGlyph::from_data(data, glyph_offset as usize, char_code, header.version)?;
+                LazyGlyph::from_data(data, glyph_offset as usize, char_code, header.version)
+                    .map_err(|e| FntError::Glyph {
+                        glyph_offset,
+                        character_index,
+                        source: Box::new(e),
+                    })?;
             lazy_glyphs.insert(glyph_id, lazy_glyph);
         }
 
@@ -183,7 +209,7 @@ impl Fnt {
                 glyph_id,
                 GlyphMetadata {
                     char_code: info.char_code,
-                    code_type: code_type.clone(),
+                    code_type,
                     bearing_x: info.bearing_x,
                     bearing_y: info.bearing_y,
                     advance: info.advance,
@@ -198,6 +224,7 @@ impl Fnt {
             descent: header.descent,
             characters,
             glyphs,
+            mipmaps: None,
         };
 
         Ok(Fnt {
@@ -224,14 +251,20 @@ impl Fnt {
                 is_compressed,
             };
 
+            // V0 has no texture size in the glyph header, the texture is the actual size
+            let (texture_width, texture_height) = match metadata.version {
+                FntVersion::V0 => (pg.actual_width, pg.actual_height),
+                FntVersion::V1 => (pg.texture_width, pg.texture_height),
+            };
+
             let info = GlyphInfo {
                 bearing_x: pg.glyph_info.bearing_x,
                 bearing_y: pg.glyph_info.bearing_y,
                 advance: pg.glyph_info.advance,
                 actual_width: pg.actual_width,
                 actual_height: pg.actual_height,
-                texture_width: pg.texture_width,
-                texture_height: pg.texture_height,
+                texture_width,
+                texture_height,
                 char_code: pg.glyph_info.char_code,
             };
 
@@ -239,24 +272,12 @@ impl Fnt {
                 id,
                 LazyGlyph {
                     info,
-                    texture_size: (pg.texture_width, pg.texture_height),
+                    texture_size: (texture_width, texture_height),
                     glyph_data,
                 },
             );
         }
 
-        let mut characters = vec![0u32; 65536];
-
-        let default_glyph_id = lazy_glyphs.keys().min().copied().unwrap_or(0);
-
-        for c in characters.iter_mut() {
-            *c = default_glyph_id;
-        }
-
-        for (glyph_id, glyph) in metadata.clone().glyphs {
-            characters[glyph.char_code as usize] = glyph_id;
-        }
-
         Fnt {
             metadata,
             character_table_crc: 0,
@@ -267,25 +288,64 @@ impl Fnt {
 }
 
 impl Fnt {
-    fn write<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        let header_size = 16usize;
-        let character_table_size = 65536 * 4;
-
-        let mut lazy_glyphs = self.lazy_glyphs.clone();
+    /// Number of entries in the character table.
+    ///
+    /// V1 is indexed by Unicode code point and has 65536 entries, or more when characters
+    /// beyond the Basic Multilingual Plane are in use. The reader takes the table size from
+    /// the first glyph offset, so longer tables read back as they are.
+    ///
+    /// V0 is indexed in `generate_sjis_map` order, so `characters` keys are table indices
+    /// and the table is as long as the highest index in use.
+    fn character_table_len(&self) -> usize {
+        let used_len = self
+            .metadata
+            .characters
+            .keys()
+            .next_back()
+            .map(|&key| key as usize + 1);
+        match self.metadata.version {
+            FntVersion::V0 => used_len.unwrap_or_else(|| generate_sjis_map().len()),
+            FntVersion::V1 => used_len.unwrap_or(0).max(65536),
+        }
+    }
 
-        let mut current_offset = header_size + character_table_size;
+    pub fn write<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let header_size = match self.metadata.version {
+            FntVersion::V0 => FntHeader::SIZE_V0,
+            FntVersion::V1 => FntHeader::SIZE_V1,
+        };
+        let glyph_header_size = match self.metadata.version {
+            FntVersion::V0 => GlyphHeader::SIZE_V0,
+            FntVersion::V1 => GlyphHeader::SIZE_V1,
+        };
+        let character_table_len = self.character_table_len();
+
+        // The reader derives the table size from the first table entry,
+        // so the glyph of character 0 must be the first glyph in the file.
+        let default_glyph_id = self
+            .metadata
+            .characters
+            .get(&0)
+            .copied()
+            .filter(|glyph_id| self.lazy_glyphs.contains_key(glyph_id))
+            .or_else(|| self.lazy_glyphs.keys().next().copied());
+
+        let glyph_order: Vec<u32> = default_glyph_id
+            .into_iter()
+            .chain(
+                self.lazy_glyphs
+                    .keys()
+                    .copied()
+                    .filter(|&glyph_id| Some(glyph_id) != default_glyph_id),
+            )
+            .collect();
+
+        let mut current_offset = header_size + character_table_len * 4;
         let mut glyph_id_to_offset: BTreeMap<u32, u32> = BTreeMap::new();
 
-        for (glyph_id, lazy_glyph) in &lazy_glyphs {
+        for glyph_id in &glyph_order {
             glyph_id_to_offset.insert(*glyph_id, current_offset as u32);
-
-            let header_len = match self.metadata.version {
-                FntVersion::V0 => GlyphHeader::SIZE_V0,
-                FntVersion::V1 => GlyphHeader::SIZE_V1,
-            };
-            let data_len = lazy_glyph.glyph_data.data.len();
-
-            current_offset += header_len + data_len;
+            current_offset += glyph_header_size + self.lazy_glyphs[glyph_id].glyph_data.data.len();
         }
 
         let total_file_size = current_offset as u32;
@@ -299,22 +359,15 @@ impl Fnt {
         };
         writer.write_all(&header.to_bytes())?;
 
-        let first_valid_offset = *glyph_id_to_offset
-            .get(lazy_glyphs.first_entry().unwrap().key())
-            .unwrap_or(&(header_size as u32 + character_table_size as u32));
-
-        let default_glyph_id = 0;
-        let default_offset = *glyph_id_to_offset
-            .get(&default_glyph_id)
-            .unwrap_or(&first_valid_offset);
+        let default_offset = default_glyph_id
+            .and_then(|glyph_id| glyph_id_to_offset.get(&glyph_id).copied())
+            .unwrap_or(total_file_size);
 
-        let mut final_table = vec![default_offset; 65536];
+        let mut final_table = vec![default_offset; character_table_len];
 
-        for (char_code, glyph_id) in &self.metadata.characters {
-            if *char_code < 65536 {
-                if let Some(offset) = glyph_id_to_offset.get(glyph_id) {
-                    final_table[*char_code as usize] = *offset;
-                }
+        for (char_index, glyph_id) in &self.metadata.characters {
+            if let Some(offset) = glyph_id_to_offset.get(glyph_id) {
+                final_table[*char_index as usize] = *offset;
             }
         }
 
@@ -322,7 +375,9 @@ impl Fnt {
             writer.write_all(&offset.to_le_bytes())?;
         }
 
-        for (_glyph_id, lazy_glyph) in lazy_glyphs {
+        for glyph_id in &glyph_order {
+            let lazy_glyph = &self.lazy_glyphs[glyph_id];
+
             let compressed_size = if lazy_glyph.glyph_data.is_compressed {
                 lazy_glyph.glyph_data.data.len() as u16
             } else {
@@ -354,15 +409,309 @@ impl Fnt {
 }
 
 impl Fnt {
-    pub fn read_fnt(path: &std::path::Path) -> Result<Fnt, &'static str> {
-        let data = std::fs::read(path).map_err(|_| "Failed to read FNT4 font file")?;
+    /// Decodes every glyph, in parallel.
+    pub fn decode_glyphs(&self, progress: &ProgressFn) -> Result<BTreeMap<u32, Glyph>, FntError> {
+        let total = self.lazy_glyphs.len();
+        let counter = AtomicUsize::new(0);
+
+        self.lazy_glyphs
+            .par_iter()
+            .map(|(&glyph_id, lazy_glyph)| {
+                let glyph =
+                    Glyph::from_lazy_glyph(lazy_glyph, self.metadata.version).map_err(|e| {
+                        FntError::GlyphData {
+                            glyph_id,
+                            source: Box::new(e),
+                        }
+                    })?;
+
+                let done = counter.fetch_add(1, Ordering::Relaxed) + 1;
+                progress(done, total);
+
+                Ok((glyph_id, glyph))
+            })
+            .collect()
+    }
+}
+
+impl Fnt {
+    pub fn read_fnt(path: &std::path::Path) -> Result<Fnt, FntError> {
+        let data = std::fs::read(path)?;
         Self::from_data(&data)
     }
 }
 
 impl Fnt {
+    pub fn to_bytes(&self) -> std::io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.write(&mut data)?;
+        Ok(data)
+    }
+
     pub fn write_fnt(&self, path: &std::path::Path) -> std::io::Result<()> {
-        let mut file = std::fs::File::create(path)?;
-        self.write(&mut file)
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write(&mut writer)?;
+        std::io::Write::flush(&mut writer)
+    }
+}
+
+/// Test font with one opaque glyph per `(character, width, advance)`, the first being the
+/// default glyph 0 that V0 fonts also map all other characters to. Glyphs are 10 pixels
+/// high, or empty when 0 pixels wide.
+#[cfg(test)]
+pub(crate) fn build_test_fnt(
+    version: FntVersion,
+    mipmap_level: usize,
+    ascent: u16,
+    glyphs: &[(char, u8, u8)],
+) -> Fnt {
+    use crate::glyph::encode_glyph_texture;
+    use crate::utils::encode_sjis_u32;
+
+    let sjis_map = generate_sjis_map();
+    let mut metadata = FntMetadata {
+        version,
+        mipmap_level,
+        ascent,
+        descent: 4,
+        characters: BTreeMap::new(),
+        glyphs: BTreeMap::new(),
+        mipmaps: None,
+    };
+    if version == FntVersion::V0 {
+        metadata.characters = (0..sjis_map.len() as u32).map(|index| (index, 0)).collect();
+    }
+    let mut processed = BTreeMap::new();
+
+    for (glyph_id, &(c, width, advance)) in glyphs.iter().enumerate() {
+        let glyph_id = glyph_id as u32;
+        let (char_code, code_type, key) = match version {
+            FntVersion::V0 => {
+                let code = encode_sjis_u32(c).unwrap();
+                let index = sjis_map.iter().position(|&m| m == code).unwrap();
+                (code, CodeType::Sjis, index as u32)
+            }
+            FntVersion::V1 => (c as u32, CodeType::Unicode, c as u32),
+        };
+        let info = GlyphMetadata {
+            char_code,
+            code_type,
+            bearing_x: 1,
+            bearing_y: 10,
+            advance,
+        };
+        let height = if width == 0 { 0 } else { 10 };
+        let pixels = vec![0xFF; width as usize * height as usize];
+        let texture = encode_glyph_texture(&pixels, width, height, mipmap_level, version);
+
+        metadata.characters.insert(key, glyph_id);
+        metadata.glyphs.insert(glyph_id, info);
+        processed.insert(
+            glyph_id,
+            ProcessedGlyph {
+                glyph_info: info,
+                actual_width: width,
+                actual_height: height,
+                texture_width: texture.texture_width,
+                texture_height: texture.texture_height,
+                data: texture.data,
+                compressed_size: texture.compressed_size,
+            },
+        );
+    }
+
+    Fnt::from_processed_glyphs(metadata, processed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glyph::Glyph;
+
+    fn build_fnt(version: FntVersion) -> Fnt {
+        let mipmap_level = match version {
+            FntVersion::V0 => 1,
+            FntVersion::V1 => 4,
+        };
+        build_test_fnt(version, mipmap_level, 12, &[(' ', 0, 9), ('A', 7, 9)])
+    }
+
+    #[test]
+    fn test_write_v0_round_trip() {
+        let fnt = build_fnt(FntVersion::V0);
+        let data = fnt.to_bytes().unwrap();
+
+        let parsed = Fnt::from_data(&data).unwrap();
+        assert_eq!(parsed.metadata.version, FntVersion::V0);
+        assert_eq!(parsed.metadata.ascent, 12);
+        assert_eq!(parsed.metadata.descent, 4);
+        assert_eq!(parsed.metadata.characters.len(), generate_sjis_map().len());
+        assert_eq!(parsed.metadata.glyphs.len(), 2);
+
+        let glyph = &parsed.lazy_glyphs[&1];
+        assert_eq!(glyph.info.char_code, 0x41);
+        assert_eq!(glyph.texture_size, (7, 10));
+
+        let original = Glyph::from_lazy_glyph(&fnt.lazy_glyphs[&1], FntVersion::V0).unwrap();
+        let decoded = Glyph::from_lazy_glyph(glyph, FntVersion::V0).unwrap();
+        assert_eq!(original.mipmap[&0], decoded.mipmap[&0]);
+
+        assert_eq!(data, parsed.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_write_v1_round_trip() {
+        let fnt = build_fnt(FntVersion::V1);
+        let data = fnt.to_bytes().unwrap();
+
+        let parsed = Fnt::from_data(&data).unwrap();
+        assert_eq!(parsed.metadata.version, FntVersion::V1);
+        assert_eq!(parsed.metadata.characters.len(), 65536);
+        assert_eq!(parsed.metadata.characters[&0x41], 1);
+        assert_eq!(parsed.metadata.characters[&0x42], 0);
+
+        assert_eq!(data, parsed.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_write_v1_supplementary_characters() {
+        let mut fnt = build_fnt(FntVersion::V1);
+        fnt.metadata.characters.insert(0x1F600, 1);
+        let data = fnt.to_bytes().unwrap();
+
+        let parsed = Fnt::from_data(&data).unwrap();
+        assert_eq!(parsed.metadata.characters.len(), 0x1F601);
+        assert_eq!(parsed.metadata.characters[&0x1F600], 1);
+        assert_eq!(parsed.metadata.characters[&0x41], 1);
+        assert_eq!(parsed.metadata.characters[&0x1F5FF], 0);
+        assert_eq!(parsed.metadata.mapped_characters()[&'😀'], 1);
+
+        assert_eq!(data, parsed.to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            Fnt::from_data(b"FNT4"),
+            Err(FntError::TooShort {
+                field: "FNT4 header",
+                ..
+            })
+        ));
+
+        let mut data = build_fnt(FntVersion::V1).to_bytes().unwrap();
+        assert!(matches!(
+            Fnt::from_data(&data[..data.len() - 1]),
+            Err(FntError::FileSizeMismatch { .. })
+        ));
+
+        let second_glyph_offset = 16 + 65536 * 4 + GlyphHeader::SIZE_V1;
+        data.truncate(second_glyph_offset + 5);
+        let file_size = data.len() as u32;
+        data[8..12].copy_from_slice(&file_size.to_le_bytes());
+
+        match Fnt::from_data(&data) {
+            Err(FntError::Glyph {
+                glyph_offset,
+                character_index,
+                source,
+            }) => {
+                assert_eq!(glyph_offset as usize, second_glyph_offset);
+                assert_eq!(character_index, 0x41);
+                assert!(matches!(
+                    *source,
+                    FntError::TooShort {
+                        field: "glyph header v1",
+                        ..
+                    }
+                ));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        data[0] = b'X';
+        assert!(matches!(
+            Fnt::from_data(&data),
+            Err(FntError::InvalidMagic(_))
+        ));
+    }
+
+    /// Small deterministic xorshift generator, so the mutation corpus is the same on every run.
+    fn next_random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    /// Parses `data` and decodes every glyph. Must never panic, errors are fine.
+    fn parse_and_decode(data: &[u8]) {
+        if let Ok(fnt) = Fnt::from_data(data) {
+            for lazy_glyph in fnt.lazy_glyphs.values() {
+                let _ = Glyph::from_lazy_glyph(lazy_glyph, fnt.metadata.version);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mutated_headers_do_not_panic() {
+        for version in [FntVersion::V0, FntVersion::V1] {
+            let data = build_fnt(version).to_bytes().unwrap();
+            let fnt = Fnt::from_data(&data).unwrap();
+
+            let table_end = *fnt.glyph_offsets.iter().min().unwrap() as usize;
+            let glyph_header_size = match version {
+                FntVersion::V0 => GlyphHeader::SIZE_V0,
+                FntVersion::V1 => GlyphHeader::SIZE_V1,
+            };
+
+            let mut glyph_offsets: Vec<usize> =
+                fnt.glyph_offsets.iter().map(|&o| o as usize).collect();
+            glyph_offsets.sort();
+            glyph_offsets.dedup();
+
+            // FNT4 header, first character table entries and all glyph headers
+            let mut interesting: Vec<usize> = (0..FntHeader::SIZE_V1 + 16).collect();
+            for &offset in &glyph_offsets {
+                interesting.extend(offset..offset + glyph_header_size);
+            }
+
+            for &pos in &interesting {
+                for value in [0x00, 0xFF] {
+                    let mut mutated = data.clone();
+                    mutated[pos] = value;
+                    parse_and_decode(&mutated);
+                }
+            }
+
+            // Truncations, with the header file size patched to match
+            let size_pos = match version {
+                FntVersion::V0 => 4,
+                FntVersion::V1 => 8,
+            };
+            let mut lengths: Vec<usize> = (0..24).chain(data.len() - 4..data.len()).collect();
+            for &offset in &glyph_offsets {
+                lengths.extend(offset - 2..offset + glyph_header_size + 2);
+            }
+            for len in lengths {
+                let mut truncated = data[..len].to_vec();
+                if truncated.len() >= size_pos + 4 {
+                    truncated[size_pos..size_pos + 4].copy_from_slice(&(len as u32).to_le_bytes());
+                }
+                parse_and_decode(&truncated);
+            }
+
+            // Random multi-byte mutations of glyph headers and data
+            let mut state = 0x2545_F491_4F6C_DD1D;
+            for _ in 0..50 {
+                let mut mutated = data.clone();
+                for _ in 0..4 {
+                    let pos =
+                        table_end + (next_random(&mut state) as usize) % (data.len() - table_end);
+                    mutated[pos] = next_random(&mut state) as u8;
+                }
+                parse_and_decode(&mutated);
+            }
+        }
     }
 }
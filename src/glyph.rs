@@ -1,8 +1,10 @@
 use std::collections::BTreeMap;
 
 use crate::{
+    error::FntError,
     lz77,
     metadata::{FntVersion, GlyphMetadata},
+    mipmap::{MipmapOptions, generate_mipmaps},
     utils::ceil_power_of_2,
 };
 
@@ -23,15 +25,16 @@ impl GlyphHeader {
     pub const SIZE_V0: usize = 8;
     pub const SIZE_V1: usize = 10;
 
-    pub fn from_data(
-        data: &[u8],
-        offset: usize,
-        version: FntVersion,
-    ) -> Result<Self, &'static str> {
+    pub fn from_data(data: &[u8], offset: usize, version: FntVersion) -> Result<Self, FntError> {
         match version {
             FntVersion::V1 => {
                 if data.len() < offset + Self::SIZE_V1 {
-                    return Err("Data too short for glyph header v1");
+                    return Err(FntError::too_short(
+                        "glyph header v1",
+                        data,
+                        offset,
+                        Self::SIZE_V1,
+                    ));
                 }
                 Ok(GlyphHeader {
                     bearing_x: data[offset] as i8,
@@ -49,7 +52,12 @@ impl GlyphHeader {
             }
             FntVersion::V0 => {
                 if data.len() < offset + Self::SIZE_V0 {
-                    return Err("Data too short for glyph header v0");
+                    return Err(FntError::too_short(
+                        "glyph header v0",
+                        data,
+                        offset,
+                        Self::SIZE_V0,
+                    ));
                 }
                 Ok(GlyphHeader {
                     bearing_x: data[offset] as i8,
@@ -160,11 +168,11 @@ pub struct GlyphData {
 }
 
 impl GlyphData {
-    pub fn decompress(&self, low_bits: usize, ref_bytes: usize) -> Vec<u8> {
+    pub fn decompress(&self, low_bits: usize, ref_bytes: usize) -> Result<Vec<u8>, FntError> {
         if self.is_compressed {
             crate::lz77::decompress(&self.data, low_bits, ref_bytes)
         } else {
-            self.data.clone()
+            Ok(self.data.clone())
         }
     }
 }
@@ -187,6 +195,7 @@ pub struct ProcessedGlyph {
     pub compressed_size: u16,
 }
 
+#[derive(Debug, Clone)]
 pub struct RenderedGlyph {
     pub bearing_x: i8,
     pub bearing_y: i8,
@@ -203,12 +212,42 @@ pub struct EncodedTexture {
     pub compressed_size: u16,
 }
 
+/// A hand-edited mipmap level, placed at the top-left corner of the level's texture.
+#[derive(Debug, Clone)]
+pub struct MipmapImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
 pub fn encode_glyph_texture(
     raw_pixels: &[u8],
     actual_width: u8,
     actual_height: u8,
     mipmap_level: usize,
     fnt_version: FntVersion,
+) -> EncodedTexture {
+    encode_glyph_texture_with_mipmaps(
+        raw_pixels,
+        actual_width,
+        actual_height,
+        mipmap_level,
+        fnt_version,
+        &BTreeMap::new(),
+        MipmapOptions::default(),
+    )
+}
+
+/// Like [`encode_glyph_texture`], but uses the supplied images for mipmap levels 1 and up
+/// where present. Missing levels are generated as `mipmaps` says.
+pub fn encode_glyph_texture_with_mipmaps(
+    raw_pixels: &[u8],
+    actual_width: u8,
+    actual_height: u8,
+    mipmap_level: usize,
+    fnt_version: FntVersion,
+    supplied_mipmaps: &BTreeMap<u8, MipmapImage>,
+    mipmaps: MipmapOptions,
 ) -> EncodedTexture {
     if actual_width == 0 || actual_height == 0 {
         return EncodedTexture {
@@ -235,36 +274,15 @@ pub fn encode_glyph_texture(
                 }
             }
 
-            let mut mipmaps = vec![canvas];
-            let mut w = texture_width as usize;
-            let mut h = texture_height as usize;
-
-            for _ in 1..mipmap_level {
-                if w <= 1 && h <= 1 {
-                    break;
-                }
-                if w > 1 && h > 1 {
-                    let new_w = w / 2;
-                    let new_h = h / 2;
-                    let prev = mipmaps.last().unwrap();
-                    let mut mip = vec![0u8; new_w * new_h];
-
-                    for y in 0..new_h {
-                        for x in 0..new_w {
-                            let tl = prev[(y * 2) * w + (x * 2)] as u32;
-                            let tr = prev[(y * 2) * w + (x * 2 + 1)] as u32;
-                            let bl = prev[(y * 2 + 1) * w + (x * 2)] as u32;
-                            let br = prev[(y * 2 + 1) * w + (x * 2 + 1)] as u32;
-                            mip[y * new_w + x] = ((tl + tr + bl + br) / 4) as u8;
-                        }
-                    }
-                    mipmaps.push(mip);
-                    w = new_w;
-                    h = new_h;
-                } else {
-                    break;
-                }
-            }
+            let generated = generate_mipmaps(
+                &canvas,
+                texture_width as usize,
+                texture_height as usize,
+                mipmap_level,
+                supplied_mipmaps,
+                mipmaps,
+            );
+            let mipmaps = std::iter::once(canvas).chain(generated);
 
             let raw_combined_data: Vec<u8> = mipmaps.into_iter().flatten().collect();
 
@@ -290,7 +308,7 @@ pub fn encode_glyph_texture(
             let w = actual_width as usize;
             let h = actual_height as usize;
 
-            let stride = (w + 1) / 2;
+            let stride = w.div_ceil(2);
             let mut packed_data = vec![0u8; stride * h];
 
             for y in 0..h {
@@ -342,7 +360,7 @@ impl LazyGlyph {
         offset: usize,
         char_code: u32,
         version: FntVersion,
-    ) -> Result<LazyGlyph, &'static str> {
+    ) -> Result<LazyGlyph, FntError> {
         let glyph_header = GlyphHeader::from_data(data, offset, version)?;
         let compressed_size = glyph_header.compressed_size;
 
@@ -363,7 +381,7 @@ impl LazyGlyph {
             FntVersion::V0 => {
                 let w = glyph_header.actual_width as usize;
                 let h = glyph_header.actual_height as usize;
-                let stride = (w + 1) / 2; // ceil(width/2) for 4bpp
+                let stride = w.div_ceil(2); // ceil(width/2) for 4bpp
                 (
                     (glyph_header.actual_width, glyph_header.actual_height),
                     stride * h,
@@ -375,18 +393,23 @@ impl LazyGlyph {
         let header_size = glyph_header.size(version);
         let data_start = offset + header_size;
 
-        let (glyph_bytes, is_compressed) = if compressed_size == 0 {
-            (
-                data[data_start..data_start + uncompressed_size].to_vec(),
-                false,
-            )
+        let (data_size, is_compressed) = if compressed_size == 0 {
+            (uncompressed_size, false)
         } else {
-            (
-                data[data_start..data_start + compressed_size as usize].to_vec(),
-                true,
-            )
+            (compressed_size as usize, true)
         };
 
+        if data_start + data_size > data.len() {
+            return Err(FntError::too_short(
+                "glyph data",
+                data,
+                data_start,
+                data_size,
+            ));
+        }
+
+        let glyph_bytes = data[data_start..data_start + data_size].to_vec();
+
         Ok(LazyGlyph {
             info,
             texture_size,
@@ -399,11 +422,11 @@ impl LazyGlyph {
 }
 
 impl Glyph {
-    pub fn from_lazy_glyph(lazy_glyph: &LazyGlyph, version: FntVersion) -> Glyph {
+    pub fn from_lazy_glyph(lazy_glyph: &LazyGlyph, version: FntVersion) -> Result<Glyph, FntError> {
         let low_bits = version.get_low_bits();
         let ref_bytes = version.get_ref_bytes();
 
-        let decompressed = lazy_glyph.glyph_data.decompress(low_bits, ref_bytes);
+        let decompressed = lazy_glyph.glyph_data.decompress(low_bits, ref_bytes)?;
         let (tw, th) = lazy_glyph.texture_size;
         let tw = tw as usize;
         let th = th as usize;
@@ -431,16 +454,16 @@ impl Glyph {
                     mipmap.insert(level as u8, level_data);
                 }
 
-                Glyph {
+                Ok(Glyph {
                     info: lazy_glyph.info.clone(),
                     mipmap,
                     width: tw as u32,
                     height: th as u32,
-                }
+                })
             }
             FntVersion::V0 => {
                 // 4bpp to 8bpp conversion
-                let stride = (tw + 1) / 2;
+                let stride = tw.div_ceil(2);
                 let mut pixels = Vec::with_capacity(tw * th);
 
                 for y in 0..th {
@@ -459,40 +482,125 @@ impl Glyph {
                     }
                 }
 
-                Glyph {
+                Ok(Glyph {
                     info: lazy_glyph.info.clone(),
                     mipmap: vec![(0, pixels)].into_iter().collect(),
                     width: tw as u32,
                     height: th as u32,
-                }
+                })
             }
         }
     }
 }
 
 impl Glyph {
-    pub fn write_png(&self, output_path: &std::path::Path) -> std::io::Result<()> {
+    /// Size of the glyph content in the given mipmap level.
+    ///
+    /// Each level halves the level above it, rounding up so no pixels are cut off.
+    pub fn mipmap_size(&self, level: u8) -> (u32, u32) {
         let (aw, ah) = self.info.actual_size();
-        let aw = aw as u32;
-        let ah = ah as u32;
+        let scale = 1u32 << level;
+        ((aw as u32).div_ceil(scale), (ah as u32).div_ceil(scale))
+    }
 
-        if aw == 0 || ah == 0 {
-            return Ok(());
-        }
+    /// Content area of a mipmap level, black with the glyph in the alpha channel.
+    pub fn to_image(&self, level: u8) -> image::RgbaImage {
+        let (aw, ah) = self.mipmap_size(level);
+        let stride = self.width >> level;
 
         let mut img = image::RgbaImage::new(aw, ah);
+        let pixels = self
+            .mipmap
+            .get(&level)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
 
         for y in 0..ah {
-            for x in 0..aw {
-                let idx = (y * self.width + x) as usize;
-                if idx < self.mipmap[&0].len() {
-                    let alpha = self.mipmap[&0][idx];
-                    img.put_pixel(x, y, image::Rgba([0, 0, 0, alpha]));
+            for x in 0..aw.min(stride) {
+                let idx = (y * stride + x) as usize;
+                if idx < pixels.len() {
+                    img.put_pixel(x, y, image::Rgba([0, 0, 0, pixels[idx]]));
                 }
             }
         }
 
-        img.save(output_path)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        img
+    }
+
+    /// Writes the content area of a mipmap level as a black PNG with the glyph in the alpha channel.
+    pub fn write_png(&self, level: u8, output_path: &std::path::Path) -> std::io::Result<()> {
+        let (aw, ah) = self.mipmap_size(level);
+        if aw == 0 || ah == 0 {
+            return Ok(());
+        }
+
+        self.to_image(level)
+            .save(output_path)
+            .map_err(std::io::Error::other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(encoded: &EncodedTexture, actual_width: u8, actual_height: u8) -> Glyph {
+        let lazy_glyph = LazyGlyph {
+            info: GlyphInfo {
+                bearing_x: 0,
+                bearing_y: 0,
+                advance: 0,
+                actual_width,
+                actual_height,
+                texture_width: encoded.texture_width,
+                texture_height: encoded.texture_height,
+                char_code: 0x41,
+            },
+            texture_size: (encoded.texture_width, encoded.texture_height),
+            glyph_data: GlyphData {
+                data: encoded.data.clone(),
+                is_compressed: encoded.compressed_size > 0,
+            },
+        };
+        Glyph::from_lazy_glyph(&lazy_glyph, FntVersion::V1).unwrap()
+    }
+
+    #[test]
+    fn test_supplied_mipmaps() {
+        let pixels = vec![0x80u8; 6 * 6];
+        let supplied = BTreeMap::from([(
+            1,
+            MipmapImage {
+                width: 3,
+                height: 3,
+                pixels: vec![0xFF; 9],
+            },
+        )]);
+
+        let encoded = encode_glyph_texture_with_mipmaps(
+            &pixels,
+            6,
+            6,
+            3,
+            FntVersion::V1,
+            &supplied,
+            MipmapOptions::default(),
+        );
+        let glyph = decode(&encoded, 6, 6);
+
+        assert_eq!(glyph.mipmap.len(), 3);
+        assert_eq!(glyph.mipmap_size(1), (3, 3));
+        // Level 1 is used as supplied, padded with zeroes to the 4x4 texture
+        assert_eq!(glyph.mipmap[&1][0], 0xFF);
+        assert_eq!(glyph.mipmap[&1][3], 0x00);
+        // Level 2 is generated from the supplied level 1
+        assert_eq!(glyph.mipmap[&2][0], 0xFF);
+
+        let generated = decode(
+            &encode_glyph_texture(&pixels, 6, 6, 3, FntVersion::V1),
+            6,
+            6,
+        );
+        assert_eq!(generated.mipmap[&1][0], 0x80);
     }
 }
@@ -0,0 +1,257 @@
+use ab_glyph::{Font, GlyphId, OutlineCurve, Point, PxScale, ScaleFont, point};
+use ab_glyph_rasterizer::Rasterizer;
+
+use crate::glyph::RenderedGlyph;
+
+/// Widest stem snapped as a whole, relative to the font size. Wider runs between two edges
+/// are counters or bowls, whose edges are snapped one by one.
+const MAX_STEM: f32 = 0.15;
+
+/// An edge of a horizontal or vertical stem, in pixels along one axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Edge {
+    position: f32,
+    /// The outline is filled after the edge (right of it, or below it).
+    opens: bool,
+}
+
+/// Piecewise linear map of coordinates along one axis, moving the stem edges onto the pixel
+/// grid and everything between them along.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct EdgeMap {
+    /// Original and fitted positions, both increasing.
+    points: Vec<(f32, f32)>,
+}
+
+impl EdgeMap {
+    /// Snaps stems (an opening edge followed closely by a closing one) to whole pixels,
+    /// keeping their width at least one pixel, and rounds the other edges. Edges that were
+    /// apart stay apart.
+    fn fit(mut edges: Vec<Edge>, max_stem: f32) -> EdgeMap {
+        edges.sort_by(|a, b| a.position.total_cmp(&b.position));
+        edges.dedup_by(|b, a| b.position - a.position < 0.1 && b.opens == a.opens);
+
+        let mut points: Vec<(f32, f32)> = Vec::new();
+        let mut i = 0;
+        while i < edges.len() {
+            let edge = edges[i];
+            let (mut start, width, end) = match edges.get(i + 1) {
+                Some(next)
+                    if edge.opens && !next.opens && next.position - edge.position <= max_stem =>
+                {
+                    let width = (next.position - edge.position).round().max(1.0);
+                    let center = (edge.position + next.position) / 2.0;
+                    i += 2;
+                    ((center - width / 2.0).round(), width, Some(next.position))
+                }
+                _ => {
+                    i += 1;
+                    (edge.position.round(), 0.0, None)
+                }
+            };
+
+            if let Some(&(previous, previous_fitted)) = points.last() {
+                let min_gap = if edge.position - previous >= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                };
+                start = start.max(previous_fitted + min_gap);
+                if edge.position <= previous {
+                    continue;
+                }
+            }
+            points.push((edge.position, start));
+            if let Some(end) = end {
+                points.push((end, start + width));
+            }
+        }
+
+        EdgeMap { points }
+    }
+
+    fn map(&self, v: f32) -> f32 {
+        let (Some(&first), Some(&last)) = (self.points.first(), self.points.last()) else {
+            return v;
+        };
+        if v <= first.0 {
+            return v + first.1 - first.0;
+        }
+        if v >= last.0 {
+            return v + last.1 - last.0;
+        }
+
+        let i = self.points.partition_point(|&(original, _)| original <= v);
+        let (a, b) = (self.points[i - 1], self.points[i]);
+        a.1 + (v - a.0) / (b.0 - a.0) * (b.1 - a.1)
+    }
+}
+
+fn curve_points(curve: &OutlineCurve) -> Vec<Point> {
+    match *curve {
+        OutlineCurve::Line(p0, p1) => vec![p0, p1],
+        OutlineCurve::Quad(p0, p1, p2) => vec![p0, p1, p2],
+        OutlineCurve::Cubic(p0, p1, p2, p3) => vec![p0, p1, p2, p3],
+    }
+}
+
+/// Renders a glyph with its horizontal and vertical stems snapped to the pixel grid, at the
+/// font size without supersampling.
+///
+/// This is a small autohinter: straight horizontal and vertical outline segments are the
+/// stem edges, each axis is fitted with an [`EdgeMap`] and the moved outline is rasterized
+/// with exact coverage.
+pub fn render_hinted_glyph<F: Font>(
+    font: &F,
+    glyph_id: GlyphId,
+    font_size: f32,
+) -> Option<RenderedGlyph> {
+    let scaled_font = font.as_scaled(PxScale::from(font_size));
+    let scale = scaled_font.scale_factor();
+    let advance = scaled_font.h_advance(glyph_id).round().clamp(0.0, 255.0) as u8;
+    let outline = font.outline(glyph_id);
+
+    // Pixels right of and below the pen position
+    let curves: Vec<Vec<Point>> = outline
+        .iter()
+        .flat_map(|outline| &outline.curves)
+        .map(|curve| {
+            curve_points(curve)
+                .into_iter()
+                .map(|p| point(p.x * scale.horizontal, -p.y * scale.vertical))
+                .collect()
+        })
+        .collect();
+
+    // Fill is on the right of clockwise contours (TrueType) and on the left of
+    // counterclockwise ones (CFF). In pixels, y goes down, so clockwise has a positive area.
+    let area: f32 = curves
+        .iter()
+        .map(|points| {
+            let (p0, p1) = (points[0], points[points.len() - 1]);
+            p0.x * p1.y - p1.x * p0.y
+        })
+        .sum();
+    let clockwise = area > 0.0;
+
+    let (mut x_edges, mut y_edges) = (Vec::new(), Vec::new());
+    for points in &curves {
+        let (p0, p1) = (points[0], points[points.len() - 1]);
+        let (dx, dy) = (p1.x - p0.x, p1.y - p0.y);
+        let flat_y = points.iter().all(|p| (p.y - p0.y).abs() < 0.05);
+        let flat_x = points.iter().all(|p| (p.x - p0.x).abs() < 0.05);
+        if flat_y && dx.abs() >= 0.5 {
+            // Moving right along a clockwise contour, the fill is below
+            y_edges.push(Edge {
+                position: p0.y,
+                opens: (dx > 0.0) == clockwise,
+            });
+        } else if flat_x && dy.abs() >= 0.5 {
+            // Moving down along a clockwise contour, the fill is on the left
+            x_edges.push(Edge {
+                position: p0.x,
+                opens: (dy < 0.0) == clockwise,
+            });
+        }
+    }
+
+    let max_stem = font_size * MAX_STEM;
+    let x_map = EdgeMap::fit(x_edges, max_stem);
+    let y_map = EdgeMap::fit(y_edges, max_stem);
+    let curves: Vec<Vec<Point>> = curves
+        .into_iter()
+        .map(|points| {
+            points
+                .into_iter()
+                .map(|p| point(x_map.map(p.x), y_map.map(p.y)))
+                .collect()
+        })
+        .collect();
+
+    let (mut min, mut max) = (point(f32::MAX, f32::MAX), point(f32::MIN, f32::MIN));
+    for p in curves.iter().flatten() {
+        min = point(min.x.min(p.x), min.y.min(p.y));
+        max = point(max.x.max(p.x), max.y.max(p.y));
+    }
+    let (left, top) = (min.x.floor(), min.y.floor());
+    let width = (max.x.ceil() - left).clamp(0.0, 255.0) as usize;
+    let height = (max.y.ceil() - top).clamp(0.0, 255.0) as usize;
+
+    if curves.is_empty() || width == 0 || height == 0 {
+        return Some(RenderedGlyph {
+            bearing_x: 0,
+            bearing_y: 0,
+            advance,
+            actual_width: 0,
+            actual_height: 0,
+            raw_pixels: vec![],
+        });
+    }
+
+    let mut rasterizer = Rasterizer::new(width, height);
+    let offset = |p: &Point| point(p.x - left, p.y - top);
+    for points in &curves {
+        match points.as_slice() {
+            [p0, p1] => rasterizer.draw_line(offset(p0), offset(p1)),
+            [p0, p1, p2] => rasterizer.draw_quad(offset(p0), offset(p1), offset(p2)),
+            [p0, p1, p2, p3] => {
+                rasterizer.draw_cubic(offset(p0), offset(p1), offset(p2), offset(p3))
+            }
+            _ => {}
+        }
+    }
+
+    let mut raw_pixels = vec![0u8; width * height];
+    rasterizer.for_each_pixel_2d(|x, y, coverage| {
+        raw_pixels[y as usize * width + x as usize] =
+            (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+    });
+
+    Some(RenderedGlyph {
+        bearing_x: left.clamp(i8::MIN as f32, i8::MAX as f32) as i8,
+        bearing_y: (-top).clamp(i8::MIN as f32, i8::MAX as f32) as i8,
+        advance,
+        actual_width: width as u8,
+        actual_height: height as u8,
+        raw_pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edge(position: f32, opens: bool) -> Edge {
+        Edge { position, opens }
+    }
+
+    #[test]
+    fn test_fit_edges() {
+        // Two stems 1.6 and 2.6 pixels wide with a counter between them, and a lone edge
+        let map = EdgeMap::fit(
+            vec![
+                edge(12.2, false),
+                edge(3.2, true),
+                edge(4.8, false),
+                edge(9.6, true),
+                edge(20.4, true),
+            ],
+            3.0,
+        );
+        let fitted: Vec<f32> = map.points.iter().map(|&(_, fitted)| fitted).collect();
+        assert_eq!(fitted, [3.0, 5.0, 9.0, 12.0, 20.0]);
+
+        // Points between edges move along, points outside shift with the nearest edge
+        assert!((map.map(4.0) - 4.0).abs() < 1e-5);
+        assert!((map.map(1.2) - 1.0).abs() < 1e-5);
+        assert!((map.map(22.0) - 21.6).abs() < 1e-5);
+
+        // A hairline stays one pixel wide, and edges that were apart don't collapse
+        let map = EdgeMap::fit(vec![edge(5.4, true), edge(5.7, false)], 3.0);
+        assert_eq!(map.points, [(5.4, 5.0), (5.7, 6.0)]);
+        let map = EdgeMap::fit(vec![edge(4.9, false), edge(5.45, false)], 3.0);
+        assert_eq!(map.points, [(4.9, 5.0), (5.45, 6.0)]);
+
+        assert_eq!(EdgeMap::default().map(2.5), 2.5);
+    }
+}
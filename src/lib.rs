@@ -0,0 +1,62 @@
+//! Read, modify and write FNT4 fonts used by shin-engine games.
+//!
+//! - [`Fnt`] parses and writes FNT4 V0/V1 font files.
+//! - [`FntMetadata`] is the editable description of a font (header values, character table and glyph metrics).
+//! - [`extract_fnt`] and [`process_glyphs`] convert between a font and a directory of PNG glyphs,
+//!   [`extract_atlas`] and [`process_atlas_glyphs`] do the same with atlas pages.
+//! - [`export_bmfont`] and [`import_bmfont`] convert between a font and AngelCode BMFont files.
+//! - [`export_ttf`] writes a font as an OpenType bitmap font for previewing text.
+//! - [`render_preview`] lays out and renders text with a font.
+//! - [`diff_fnt`] compares two fonts, [`render_contact_sheet`] draws the changed glyphs.
+//! - [`create_mapping`] assigns characters missing from a font to unused glyph slots, for translations.
+//! - [`rebuild_fnt`] renders every glyph of a font again from a TTF/OTF font, or a [`Charset`]
+//!   with the font as template. [`create_fnt`] builds a new font from a TTF/OTF font alone.
+
+pub mod atlas;
+pub mod bmfont;
+pub mod charset;
+pub mod crc32;
+pub mod csv;
+pub mod diff;
+pub mod effects;
+pub mod error;
+pub mod extract;
+pub mod fnt;
+pub mod glyph;
+pub mod hinting;
+pub mod lz77;
+pub mod mapping;
+pub mod metadata;
+pub mod mipmap;
+pub mod preview;
+pub mod rebuild;
+pub mod repack;
+pub mod resample;
+pub mod sdf;
+pub mod ttf;
+pub mod utils;
+pub mod vertical;
+
+pub use atlas::{Atlas, extract_atlas, process_atlas_glyphs};
+pub use bmfont::{BmFont, BmFontFormat, export_bmfont, import_bmfont};
+pub use charset::{CharClass, Charset};
+pub use diff::{FieldChange, FntDiff, GlyphDiff, diff_fnt, render_contact_sheet};
+pub use effects::GlyphEffects;
+pub use error::FntError;
+pub use extract::{ExtractReport, extract_fnt};
+pub use fnt::Fnt;
+pub use glyph::{Glyph, ProcessedGlyph};
+pub use mapping::{CsvConfig, Mapping, MappingConfig, create_mapping};
+pub use metadata::{CodeType, FntMetadata, FntVersion, GlyphMetadata, MipmapMetadata};
+pub use mipmap::{MipmapOptions, MipmapStrategy};
+pub use preview::{PreviewOptions, PreviewReport, render_preview};
+pub use rebuild::{
+    CreateOptions, MetricOverride, RebuildConfig, RebuildReport, RestoredGlyph, SourceFont,
+    create_fnt, rebuild_fnt,
+};
+pub use repack::process_glyphs;
+pub use resample::ResampleFilter;
+pub use sdf::SdfConfig;
+pub use ttf::{TtfExportReport, export_ttf};
+pub use utils::ProgressFn;
+pub use vertical::VerticalConfig;
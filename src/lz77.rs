@@ -1,9 +1,15 @@
-// Ported from https://github.com/lzhhzl/about-shin/blob/main/konosuba_py/lz77.py
+// Decompressor ported from https://github.com/lzhhzl/about-shin/blob/main/konosuba_py/lz77.py
 
 // FNT4 V0 low_bits = 3, ref_bytes = 1
 // FNT4 V1 low_bits = 10, ref_bytes = 2
 
-pub fn decompress(input_data: &[u8], low_bits: usize, ref_bytes: usize) -> Vec<u8> {
+use crate::error::FntError;
+
+pub fn decompress(
+    input_data: &[u8],
+    low_bits: usize,
+    ref_bytes: usize,
+) -> Result<Vec<u8>, FntError> {
     let mut input_pos = 0;
     let mut output = Vec::new();
 
@@ -22,6 +28,13 @@ pub fn decompress(input_data: &[u8], low_bits: usize, ref_bytes: usize) -> Vec<u
                 input_pos += 1;
             } else {
                 // Back reference
+                let reference_pos = input_pos;
+                if input_pos + ref_bytes > input_data.len() {
+                    return Err(FntError::TruncatedBackReference {
+                        input_offset: reference_pos,
+                    });
+                }
+
                 let backseek_spec = if ref_bytes == 2 {
                     let hi = input_data[input_pos] as u16;
                     let lo = input_data[input_pos + 1] as u16;
@@ -49,6 +62,14 @@ pub fn decompress(input_data: &[u8], low_bits: usize, ref_bytes: usize) -> Vec<u
                     (back_offset, back_length)
                 };
 
+                if back_offset > output.len() {
+                    return Err(FntError::InvalidBackReference {
+                        input_offset: reference_pos,
+                        back_offset,
+                        output_len: output.len(),
+                    });
+                }
+
                 for _ in 0..back_length {
                     let last = output.len() - back_offset;
                     let byte = output[last];
@@ -58,7 +79,7 @@ pub fn decompress(input_data: &[u8], low_bits: usize, ref_bytes: usize) -> Vec<u
         }
     }
 
-    output
+    Ok(output)
 }
 
 #[derive(Clone, Debug)]
@@ -67,222 +88,153 @@ enum Instruction {
     Reference { length: usize, offset: usize },
 }
 
+/// Most bits of the hashes of the hash chains.
+const MAX_HASH_BITS: u32 = 16;
+
+/// Compresses `input_bytes` into the smallest stream [`decompress`] accepts.
+///
+/// Every back reference has the same size whatever its length and offset, so only the
+/// longest match at each position matters: a hash chain over the reference window finds it,
+/// and a backward pass picks the literals and reference lengths with the fewest bits overall
+/// (one flag bit, plus the literal byte or the reference bytes).
 pub fn compress(input_bytes: &[u8], low_bits: usize, ref_bytes: usize) -> Vec<u8> {
     if input_bytes.is_empty() {
         return Vec::new();
     }
 
-    let (max_count, max_offset) = if ref_bytes == 2 {
+    let (min_count, max_count, max_offset) = if ref_bytes == 2 {
         let count_bits = 16 - low_bits;
         let cnt = ((1usize << count_bits) - 1) + 3;
         let off = ((1usize << low_bits) - 1) + 1;
-        (cnt, off)
+        (3, cnt, off)
     } else {
         let offset_bits = 8 - low_bits;
         let cnt = ((1usize << low_bits) - 1) + 2;
         let off = ((1usize << offset_bits) - 1) + 1;
-        (cnt, off)
+        (2, cnt, off)
     };
 
-    fn find_offset(search_bytes: &[u8], map_bytes: &[u8]) -> usize {
-        for i in 0..search_bytes.len() {
-            let pos = search_bytes.len() - i - 1;
-            if search_bytes[pos] == map_bytes[0] && search_bytes[pos..].starts_with(map_bytes) {
-                return i + 1;
+    let longest = longest_matches(input_bytes, min_count, max_count, max_offset);
+
+    // Bits needed for the rest of the input from each position, and the length of the
+    // reference taken there (0 for a literal)
+    let literal_bits = 9;
+    let reference_bits = 8 * ref_bytes as u32 + 1;
+    let n = input_bytes.len();
+    let mut bits = vec![0u32; n + 1];
+    let mut lengths = vec![0usize; n];
+    for i in (0..n).rev() {
+        bits[i] = bits[i + 1] + literal_bits;
+        let length = longest[i].0;
+        if length >= min_count {
+            // Any shorter part of the match works as well
+            let ends = &bits[i + min_count..=i + length];
+            let fewest = *ends.iter().min().unwrap();
+            if fewest + reference_bits < bits[i] {
+                lengths[i] = min_count + ends.iter().position(|&b| b == fewest).unwrap();
+                bits[i] = fewest + reference_bits;
             }
         }
-        panic!("find_offset: pattern not found");
     }
 
-    fn all_the_same(input_list: &[u8], compare: u8) -> bool {
-        input_list.iter().all(|&item| item == compare)
-    }
-
-    let mut instructions: Vec<Instruction> = vec![Instruction::Literal(input_bytes[0])];
-    let mut log_len: usize = 1;
-    let mut map_bytes: Vec<u8> = Vec::new();
-    let mut search_buf: Option<&[u8]> = None;
-    let mut len_offset: Option<(usize, usize)> = None;
-
-    let mut i: usize = 1;
-    while i < input_bytes.len() {
-        if !map_bytes.is_empty() {
-            let search_buf_ref = search_buf.unwrap();
-            let len_offset_ref = len_offset.unwrap();
-
-            if len_offset_ref.0 == len_offset_ref.1 && input_bytes[i] == map_bytes[0] {
-                let main_map_len = map_bytes.len();
-                let mut sub_map_len = main_map_len;
-                let mut sub_pos = i;
-
-                while (max_count - map_bytes.len()) > 0 {
-                    if (max_count - map_bytes.len()) < main_map_len {
-                        sub_map_len = max_count - map_bytes.len();
-                    }
-                    if sub_pos + sub_map_len > input_bytes.len()
-                        || &input_bytes[sub_pos..sub_pos + sub_map_len] != &map_bytes[..sub_map_len]
-                    {
-                        break;
-                    }
-                    map_bytes.extend_from_slice(&map_bytes[..sub_map_len].to_vec());
-                    sub_pos += sub_map_len;
-                }
-
-                if map_bytes.len() < max_count {
-                    for j in (1..=map_bytes.len()).rev() {
-                        if sub_pos + j <= input_bytes.len()
-                            && &input_bytes[sub_pos..sub_pos + j] == &map_bytes[..j]
-                        {
-                            let part = map_bytes[..j].to_vec();
-                            map_bytes.extend_from_slice(&part);
-                            sub_pos += j;
-                            break;
-                        }
-                    }
-                }
-
-                i = sub_pos;
-                len_offset = Some((map_bytes.len(), len_offset_ref.1));
-                let len_offset_ref = len_offset.unwrap();
-
-                if len_offset_ref.0 == max_count || i == input_bytes.len() {
-                    if map_bytes.len() > 0 && map_bytes.len() < 3 {
-                        if len_offset_ref.0 == 2 {
-                            if all_the_same(&map_bytes, map_bytes[0]) && len_offset_ref.1 == 1 {
-                                for &b in &map_bytes {
-                                    instructions.push(Instruction::Literal(b));
-                                }
-                            }
-                        } else {
-                            panic!("usually will not run in here, please debug");
-                        }
-                    } else {
-                        instructions.push(Instruction::Reference {
-                            length: len_offset_ref.0,
-                            offset: len_offset_ref.1,
-                        });
-                    }
-                    log_len += map_bytes.len();
-                    map_bytes.clear();
-                    search_buf = None;
-                    len_offset = None;
-                    continue;
-                }
-            }
-
-            let mut test_bytes = map_bytes.clone();
-            test_bytes.push(input_bytes[i]);
-
-            if !contains_slice(search_buf_ref, &test_bytes) {
-                if map_bytes.len() > 0 && map_bytes.len() < 3 {
-                    if map_bytes.len() == 2
-                        && (!all_the_same(&map_bytes, map_bytes[0])
-                            || contains_slice(search_buf_ref, &[map_bytes[1], input_bytes[i]]))
-                    {
-                        map_bytes.truncate(1);
-                        i -= 1;
-                    }
-                    for &b in &map_bytes {
-                        instructions.push(Instruction::Literal(b));
-                    }
-                } else {
-                    let len_offset_val = (map_bytes.len(), len_offset_ref.1);
-                    if len_offset_val.0 == 2 {
-                        panic!("usually will not run in here, please debug");
-                    }
-                    instructions.push(Instruction::Reference {
-                        length: len_offset_val.0,
-                        offset: len_offset_val.1,
-                    });
-                }
-                log_len += map_bytes.len();
-                map_bytes.clear();
-                search_buf = None;
-                len_offset = None;
-            } else {
-                if map_bytes.len() == max_count {
-                    let offset = find_offset(search_buf_ref, &map_bytes);
-                    instructions.push(Instruction::Reference {
-                        length: map_bytes.len(),
-                        offset,
-                    });
-                    log_len += map_bytes.len();
-                    map_bytes.clear();
-                    search_buf = None;
-                } else {
-                    map_bytes.push(input_bytes[i]);
-                    let offset = find_offset(search_buf_ref, &map_bytes);
-                    len_offset = Some((map_bytes.len(), offset));
-
-                    if i + 1 == input_bytes.len() {
-                        let len_offset_ref = len_offset.unwrap();
-                        if len_offset_ref.0 < 3 {
-                            for &b in &map_bytes {
-                                instructions.push(Instruction::Literal(b));
-                            }
-                        } else {
-                            instructions.push(Instruction::Reference {
-                                length: len_offset_ref.0,
-                                offset: len_offset_ref.1,
-                            });
-                        }
-                        log_len += map_bytes.len();
-                    }
-                    i += 1;
-                }
-            }
-        } else {
-            if search_buf.is_none() {
-                let start = if log_len > max_offset {
-                    log_len - max_offset
-                } else {
-                    0
-                };
-                search_buf = Some(&input_bytes[start..log_len]);
-            }
-
-            let search_buf_ref = search_buf.unwrap();
-
-            if contains_slice(search_buf_ref, &[input_bytes[i]]) && i + 1 != input_bytes.len() {
-                map_bytes.push(input_bytes[i]);
-                let offset = find_offset(search_buf_ref, &map_bytes);
-                len_offset = Some((1, offset));
-            } else {
-                instructions.push(Instruction::Literal(input_bytes[i]));
-                log_len += 1;
-                search_buf = None;
-            }
+    let mut instructions = Vec::new();
+    let mut i = 0;
+    while i < n {
+        if lengths[i] == 0 {
+            instructions.push(Instruction::Literal(input_bytes[i]));
             i += 1;
+        } else {
+            instructions.push(Instruction::Reference {
+                length: lengths[i],
+                offset: longest[i].1,
+            });
+            i += lengths[i];
         }
     }
 
     encode_instructions(&instructions, low_bits, ref_bytes, max_count, max_offset)
 }
 
-fn contains_slice(haystack: &[u8], needle: &[u8]) -> bool {
-    if needle.is_empty() {
-        return true;
+/// Longest match at each position, as (length, offset), up to `max_count` bytes and
+/// `max_offset` bytes back. The length is 0 where no match reaches `min_count` bytes. Matches
+/// may run into the position itself, the decompressor copies byte by byte.
+fn longest_matches(
+    input: &[u8],
+    min_count: usize,
+    max_count: usize,
+    max_offset: usize,
+) -> Vec<(usize, usize)> {
+    let n = input.len();
+    // About one chain per position
+    let hash_bits = (usize::BITS - n.leading_zeros()).clamp(1, MAX_HASH_BITS);
+    let hash = |i: usize| {
+        let key = input[i..i + min_count]
+            .iter()
+            .fold(0u32, |key, &b| (key << 8) | b as u32);
+        (key.wrapping_mul(0x9E37_79B1) >> (32 - hash_bits)) as usize
+    };
+
+    // Length of the run of equal bytes from each position, and where that run starts
+    let mut run = vec![1usize; n];
+    for i in (0..n.saturating_sub(1)).rev() {
+        if input[i] == input[i + 1] {
+            run[i] = run[i + 1] + 1;
+        }
     }
-    if needle.len() > haystack.len() {
-        return false;
+    let mut run_start: Vec<usize> = (0..n).collect();
+    for i in 1..n {
+        if input[i] == input[i - 1] {
+            run_start[i] = run_start[i - 1];
+        }
     }
-    let first = needle[0];
-    let needle_len = needle.len();
-    let mut pos = 0;
-    while pos + needle_len <= haystack.len() {
-        if let Some(idx) = haystack[pos..].iter().position(|&b| b == first) {
-            let start = pos + idx;
-            if start + needle_len <= haystack.len()
-                && &haystack[start..start + needle_len] == needle
-            {
-                return true;
+
+    // Last position of each hash, and the position before it with the same hash
+    let mut head = vec![usize::MAX; 1 << hash_bits];
+    let mut prev = vec![usize::MAX; n];
+    let mut matches = vec![(0, 0); n];
+
+    for i in 0..(n + 1).saturating_sub(min_count) {
+        let h = hash(i);
+        let limit = (n - i).min(max_count);
+        let mut best = (0, 0);
+        let mut candidate = head[h];
+        while candidate != usize::MAX && i - candidate <= max_offset {
+            let (mut position, mut next) = (candidate, prev[candidate]);
+            if run[i] >= min_count && run[candidate] >= min_count && input[candidate] == input[i] {
+                // In a run of the byte at the position, only the candidate with a run as long
+                // can match past the run, the others match the shorter of the two runs. Check
+                // that one, or the closest one in the window, and skip the rest of the run,
+                // which is all in this chain.
+                let start = run_start[candidate].max(i.saturating_sub(max_offset));
+                position = (candidate + run[candidate])
+                    .saturating_sub(run[i])
+                    .clamp(start, candidate);
+                next = prev[run_start[candidate]];
             }
-            pos = start + 1;
-        } else {
-            break;
+            // A candidate can only beat the best match if it agrees on the byte past it
+            if input[position + best.0] == input[i + best.0] {
+                let length = input[position..]
+                    .iter()
+                    .zip(&input[i..i + limit])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best.0 {
+                    best = (length, i - position);
+                    if length == limit {
+                        break;
+                    }
+                }
+            }
+            candidate = next;
         }
+        if best.0 >= min_count {
+            matches[i] = best;
+        }
+        prev[i] = head[h];
+        head[h] = i;
     }
-    false
+
+    matches
 }
 
 fn encode_instructions(
@@ -365,7 +317,7 @@ mod tests {
         let input = b"ABCCCCCC_ABCCCCCC";
 
         let compressed = compress(input, 3, 1);
-        let decompressed = decompress(&compressed, 3, 1);
+        let decompressed = decompress(&compressed, 3, 1).unwrap();
 
         println!("Original Len: {}", input.len());
         println!("Comp Len:     {}", compressed.len());
@@ -380,7 +332,7 @@ mod tests {
         let input = generate_test_data();
 
         let compressed = compress(&input, 10, 2);
-        let decompressed = decompress(&compressed, 10, 2);
+        let decompressed = decompress(&compressed, 10, 2).unwrap();
 
         println!("Original Len: {}", input.len());
         println!("Comp Len:     {}", compressed.len());
@@ -393,11 +345,103 @@ mod tests {
         let input = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit.";
 
         let c0 = compress(input, 3, 1);
-        let d0 = decompress(&c0, 3, 1);
+        let d0 = decompress(&c0, 3, 1).unwrap();
         assert_eq!(input, &d0[..]);
 
         let c1 = compress(input, 10, 2);
-        let d1 = decompress(&c1, 10, 2);
+        let d1 = decompress(&c1, 10, 2).unwrap();
         assert_eq!(input, &d1[..]);
     }
+
+    #[test]
+    fn test_compress_optimal() {
+        // A run is one literal, then references as long as the format allows
+        let zeros = vec![0u8; 1000];
+        let compressed = compress(&zeros, 10, 2);
+        // 17 instructions: 3 flag bytes, 1 literal, 16 references of 2 bytes
+        assert_eq!(compressed.len(), 36);
+        assert_eq!(decompress(&compressed, 10, 2).unwrap(), zeros);
+        let compressed = compress(&zeros, 3, 1);
+        // 1 literal and 111 references of up to 9 bytes, 112 instructions in 14 groups
+        assert_eq!(compressed.len(), 14 + 1 + 111);
+        assert_eq!(decompress(&compressed, 3, 1).unwrap(), zeros);
+
+        // Inputs shorter than a reference
+        for input in [&b"A"[..], b"AB", b"AAA"] {
+            assert_eq!(decompress(&compress(input, 3, 1), 3, 1).unwrap(), input);
+            assert_eq!(decompress(&compress(input, 10, 2), 10, 2).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn test_longest_matches() {
+        // Few distinct bytes, with runs, like glyph textures
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let mut input = Vec::new();
+        while input.len() < 3000 {
+            let byte = [0, 0, 0, 255, 128, 7][(next() % 6) as usize];
+            let count = [1, 1, 2, 5, 40, 100][(next() % 6) as usize];
+            input.extend(std::iter::repeat_n(byte, count));
+        }
+
+        for (min_count, max_count, max_offset) in [(2, 9, 32), (3, 66, 1024)] {
+            let matches = longest_matches(&input, min_count, max_count, max_offset);
+            for (i, &(length, offset)) in matches.iter().enumerate() {
+                let longest = (1..=max_offset.min(i))
+                    .map(|offset| {
+                        (0..max_count.min(input.len() - i))
+                            .take_while(|&k| input[i + k - offset] == input[i + k])
+                            .count()
+                    })
+                    .max()
+                    .unwrap_or(0);
+                let expected = if longest >= min_count { longest } else { 0 };
+                assert_eq!(length, expected, "position {}", i);
+                if length > 0 {
+                    assert_eq!(input[i - offset..i - offset + length], input[i..i + length]);
+                }
+            }
+        }
+
+        assert_eq!(decompress(&compress(&input, 3, 1), 3, 1).unwrap(), input);
+        assert_eq!(decompress(&compress(&input, 10, 2), 10, 2).unwrap(), input);
+    }
+
+    #[test]
+    fn test_decompress_invalid_input() {
+        assert!(matches!(
+            decompress(&[0x01, 0x00, 0x00], 10, 2),
+            Err(FntError::InvalidBackReference { .. })
+        ));
+        assert!(matches!(
+            decompress(&[0x02, b'A', 0x00], 10, 2),
+            Err(FntError::TruncatedBackReference { .. })
+        ));
+        assert!(matches!(
+            decompress(&[0x02, b'A', 0xF8], 3, 1),
+            Err(FntError::InvalidBackReference { .. })
+        ));
+
+        // Random input must never panic
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        for _ in 0..1000 {
+            let len = (state % 64) as usize;
+            let input: Vec<u8> = (0..len)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            let _ = decompress(&input, 3, 1);
+            let _ = decompress(&input, 10, 2);
+        }
+    }
 }
@@ -1,26 +1,30 @@
-use std::path::PathBuf;
-
-use anyhow::Result;
-use clap::{Parser, Subcommand};
-
-use crate::{
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+
+use fnt4_tool::{
+    atlas::{ATLAS_FILENAME, extract_atlas, process_atlas_glyphs},
+    bmfont::{BmFontFormat, export_bmfont, import_bmfont},
+    charset::CharClass,
+    diff::{FieldChange, diff_fnt, render_contact_sheet},
     extract::extract_fnt,
     fnt::Fnt,
-    metadata::FntMetadata,
-    rebuild::{RebuildConfig, rebuild_fnt},
+    mapping::{
+        CsvConfig, MappingConfig, SLOT_CLASSES, SlotOptions, create_mapping, read_frequency_file,
+        read_mapping,
+    },
+    metadata::{FntMetadata, FntVersion},
+    mipmap::MipmapStrategy,
+    preview::{PreviewOptions, render_preview},
+    rebuild::{CreateOptions, RebuildConfig, RebuildReport, create_fnt, rebuild_fnt},
     repack::process_glyphs,
+    resample::ResampleFilter,
+    ttf::export_ttf,
 };
 
-pub mod crc32;
-pub mod extract;
-pub mod fnt;
-pub mod glyph;
-pub mod lz77;
-pub mod metadata;
-pub mod rebuild;
-pub mod repack;
-pub mod utils;
-
 #[derive(Parser, Debug)]
 #[command(name = "fnt4-tool")]
 #[command(author, version, about = "FNT4 font extract/repack/rebuild tool")]
@@ -35,39 +39,446 @@ enum Commands {
     Extract {
         input_fnt: PathBuf,
         output_dir: PathBuf,
+        /// Pack all glyphs into atlas pages with an atlas.toml sidecar instead of one PNG per glyph.
+        #[arg(long)]
+        atlas: bool,
+        /// Atlas page width and maximum height in pixels.
+        /// Default: 2048
+        #[arg(long, default_value_t = 2048)]
+        atlas_size: u32,
     },
 
-    /// Repack PNG glyphs and metadata into FNT4 font file (FNT4 V1 only)
+    /// Repack PNG glyphs and metadata into FNT4 font file
     Repack {
         input_dir: PathBuf,
         output_fnt: PathBuf,
+        /// Read glyphs from atlas pages and atlas.toml written by `extract --atlas`.
+        #[arg(long)]
+        atlas: bool,
+    },
+
+    /// Export FNT4 font file to AngelCode BMFont descriptor and page PNGs
+    ExportBmfont {
+        input_fnt: PathBuf,
+        /// BMFont descriptor path. Pages are written next to it as <name>_<page>.png
+        output_bmfont: PathBuf,
+        /// Write the binary descriptor format instead of the text format.
+        #[arg(long)]
+        binary: bool,
+        /// Page width and maximum height in pixels.
+        /// Default: 2048
+        #[arg(long, default_value_t = 2048)]
+        page_size: u32,
+    },
+
+    /// Export FNT4 font file to an OpenType bitmap font (CBDT/CBLC) for previewing text
+    ExportTtf {
+        input_fnt: PathBuf,
+        output_ttf: PathBuf,
+        /// Font family name.
+        /// Default: the input file name
+        #[arg(long)]
+        family_name: Option<String>,
+    },
+
+    /// Import AngelCode BMFont descriptor (text or binary) and page PNGs into FNT4 font file
+    ImportBmfont {
+        input_bmfont: PathBuf,
+        output_fnt: PathBuf,
+        /// FNT4 version to write, v0 (Shift-JIS) or v1 (Unicode).
+        /// Default: v1
+        #[arg(long, value_parser = parse_fnt_version, default_value = "v1")]
+        fnt_version: FntVersion,
+        /// Total number of mipmap levels (1-4), FNT4 V1 only.
+        /// Default: 4
+        #[arg(long, default_value_t = 4)]
+        mipmap_level: usize,
+    },
+
+    /// Render a text with an FNT4 font (or an extract directory) to a PNG
+    Preview {
+        /// FNT4 font file, or a directory written by `extract`
+        input: PathBuf,
+        output_png: PathBuf,
+        /// Text to render. Use \n in a text file for line breaks.
+        #[arg(required_unless_present = "text_file")]
+        text: Option<String>,
+        /// Read the text from a UTF-8 text file instead.
+        #[arg(long, conflicts_with = "text")]
+        text_file: Option<PathBuf>,
+        /// Wrap lines wider than this many pixels.
+        #[arg(long)]
+        max_width: Option<u32>,
+    },
+
+    /// Compare two FNT4 fonts (or extract directories) character by character
+    Diff {
+        /// FNT4 font file, or a directory written by `extract`
+        old: PathBuf,
+        /// FNT4 font file, or a directory written by `extract`
+        new: PathBuf,
+        /// Pixels whose alpha differs by at most this much count as unchanged.
+        /// Default: 16
+        #[arg(long, default_value_t = 16)]
+        threshold: u8,
+        /// Write the old, new and overlaid bitmaps of every changed glyph to this PNG.
+        #[arg(long)]
+        contact_sheet: Option<PathBuf>,
+    },
+
+    /// Assign characters missing from an FNT4 font to unused glyph slots and remap translation CSVs
+    Map {
+        input_fnt: PathBuf,
+        /// Mapping TOML to write, with the [replace] table used by `rebuild --config`.
+        output_mapping: PathBuf,
+        /// shin-translation-tools CSV to scan and remap, written as <name>_mapped.csv next to it.
+        #[arg(long)]
+        csv: Vec<PathBuf>,
+        /// Comma-separated original text columns of --csv files.
+        /// Default: s
+        #[arg(long, value_delimiter = ',', default_value = "s")]
+        original_cols: Vec<String>,
+        /// Comma-separated translation columns of --csv files, which get remapped.
+        /// Default: translated
+        #[arg(long, value_delimiter = ',', default_value = "translated")]
+        translation_cols: Vec<String>,
+        /// Characters that are never used as slots, e.g. ones drawn in images.
+        #[arg(long, default_value = "")]
+        reserve: String,
+        /// Character frequencies of the original script, one "<char> <count>" per line.
+        /// Default: count the original columns of the CSVs
+        #[arg(long)]
+        frequency_file: Option<PathBuf>,
+        /// Mapping from an earlier run. Its assignments are kept for characters that are still
+        /// needed, so only new characters take slots.
+        #[arg(long)]
+        previous: Option<PathBuf>,
+        /// Comma-separated character classes used as slots besides CJK ideographs
+        /// (latin, digit, fullwidth). Kana and punctuation are never used.
+        #[arg(long, value_delimiter = ',', value_parser = parse_slot_class)]
+        slot_classes: Vec<CharClass>,
+        /// Map config from a toml file, with [[csv]] tables. See examples/mapping.toml.
+        #[arg(short, long)]
+        config: Option<PathBuf>,
     },
 
-    /// Rebuild FNT4 font file from FNT4 font file and TTF/OTF font file (FNT4 V1 only)
+    /// Rebuild FNT4 font file from FNT4 font file and TTF/OTF font file
     Rebuild {
         input_fnt: PathBuf,
         output_fnt: PathBuf,
         source_font: PathBuf,
-        /// Font size in pixels.
-        /// If not specified, auto-calculated from original FNT (ascent + descent)
-        #[arg(short = 's', long)]
-        size: Option<f32>,
-        /// Quality factor. Renders at higher resolution then downsamples with Lanczos filter.
-        /// Higher = cleaner edges but slower. Recommended: 2-4. Default: 1 (no supersampling)
-        #[arg(short = 'q', long)]
-        quality: Option<u8>,
-        /// Letter spacing pixels.
-        /// Default: 0
+        #[command(flatten)]
+        render: RenderArgs,
+        /// Text file with characters to add to the font, rendered from the source font.
+        /// V1 fonts can hold any character, V0 fonts Shift-JIS.
         #[arg(long)]
-        letter_spacing: Option<i8>,
-        /// Texture padding pixels.
-        /// If not specified, auto-calculated from original FNT (mipmap level)
-        #[arg(long)]
-        texture_padding: Option<u8>,
+        add_chars: Option<PathBuf>,
         /// Rebuild config from a toml file.
         #[arg(short = 'c', long)]
         config: Option<PathBuf>,
     },
+
+    /// Create a new FNT4 font file from a TTF/OTF font file and a charset
+    Create {
+        source_font: PathBuf,
+        output_fnt: PathBuf,
+        /// FNT4 version to write, v0 (Shift-JIS) or v1 (Unicode).
+        /// Default: v1
+        #[arg(long, value_parser = parse_fnt_version, default_value = "v1")]
+        fnt_version: FntVersion,
+        /// Total number of mipmap levels (1-4), FNT4 V1 only.
+        /// Default: 4
+        #[arg(long, default_value_t = 4)]
+        mipmap_level: usize,
+        /// Pixels above the baseline.
+        /// If not specified, taken from the source font at the font size
+        #[arg(long)]
+        ascent: Option<u16>,
+        /// Pixels below the baseline.
+        /// If not specified, taken from the source font at the font size
+        #[arg(long)]
+        descent: Option<u16>,
+        #[command(flatten)]
+        render: RenderArgs,
+        /// Rebuild config from a toml file, e.g. with a [charset] section.
+        #[arg(short = 'c', long)]
+        config: Option<PathBuf>,
+    },
+}
+
+/// Rendering options shared by `rebuild` and `create`, applied over the config file.
+#[derive(Args, Debug)]
+struct RenderArgs {
+    /// Font size in pixels.
+    /// Rebuild auto-calculates it from the input FNT (ascent + descent) if not specified,
+    /// create requires it here or in the config
+    #[arg(short = 's', long)]
+    size: Option<f32>,
+    /// Quality factor. Renders at higher resolution then downsamples with --resample-filter.
+    /// Higher = cleaner edges but slower. Recommended: 2-4. Default: 1 (no supersampling)
+    #[arg(short = 'q', long)]
+    quality: Option<u8>,
+    /// Filter for downsampling supersampled glyphs: box, bilinear, mitchell, lanczos2,
+    /// lanczos3, lanczos4 or kaiser.
+    /// Default: lanczos3
+    #[arg(long, value_parser = parse_resample_filter)]
+    resample_filter: Option<ResampleFilter>,
+    /// How mipmap levels are generated: box, sharpened, filter (every level resampled
+    /// from level 0 with --mipmap-filter), max or coverage.
+    /// Default: box, or filter when --mipmap-filter is given
+    #[arg(long, value_parser = parse_mipmap_strategy)]
+    mipmap_strategy: Option<MipmapStrategy>,
+    /// Filter of the filter mipmap strategy, same choices as --resample-filter.
+    /// Default: lanczos3
+    #[arg(long, value_parser = parse_resample_filter)]
+    mipmap_filter: Option<ResampleFilter>,
+    /// Snap horizontal and vertical stems to the pixel grid and render at the font size,
+    /// ignoring the quality factor. Crisper CJK at small sizes.
+    #[arg(long)]
+    hinting: bool,
+    /// Gamma of the glyph alpha. Above 1 makes antialiased edges heavier, below 1 lighter.
+    /// Default: 1.0
+    #[arg(long)]
+    gamma: Option<f32>,
+    /// Contrast of the glyph alpha, at least 1. Higher values make edges sharper.
+    /// Default: 1.0
+    #[arg(long)]
+    contrast: Option<f32>,
+    /// Letter spacing pixels.
+    /// Default: 0
+    #[arg(long)]
+    letter_spacing: Option<i8>,
+    /// Texture padding pixels.
+    /// If not specified, auto-calculated from the mipmap level
+    #[arg(long)]
+    texture_padding: Option<u8>,
+    /// Text file with the characters of the font. Rebuild then uses the input font only for
+    /// its header values. Can be repeated.
+    #[arg(long)]
+    charset_file: Vec<PathBuf>,
+    /// Comma-separated Unicode ranges of the font, e.g. U+0020-U+007E,U+4E00-U+9FFF.
+    #[arg(long, value_delimiter = ',')]
+    charset_range: Vec<String>,
+    /// Directory of script files whose characters the font is built with. Can be repeated.
+    #[arg(long)]
+    charset_dir: Vec<PathBuf>,
+    /// Render the glyphs for vertical text, with vertical forms and vertical advances.
+    #[arg(long)]
+    vertical: bool,
+    /// Encode the glyphs as signed distance fields, for games that draw text with a
+    /// distance field shader.
+    #[arg(long)]
+    sdf: bool,
+    /// Pixels of distance on each side of the outline covered by the SDF alpha range.
+    /// Implies --sdf. Default: 4.0
+    #[arg(long)]
+    sdf_spread: Option<f32>,
+}
+
+impl RenderArgs {
+    /// Overrides the values of `config` with the options given on the command line.
+    fn apply(self, config: &mut RebuildConfig) {
+        if self.size.is_some() {
+            config.size = self.size;
+        }
+
+        if let Some(quality) = self.quality {
+            config.quality = quality;
+        }
+
+        if let Some(resample_filter) = self.resample_filter {
+            config.resample_filter = resample_filter;
+        }
+
+        if let Some(mipmap_filter) = self.mipmap_filter {
+            config.mipmaps.filter = mipmap_filter;
+            config.mipmaps.strategy = MipmapStrategy::Filter;
+        }
+
+        if let Some(mipmap_strategy) = self.mipmap_strategy {
+            config.mipmaps.strategy = mipmap_strategy;
+        }
+
+        if self.hinting {
+            config.hinting = true;
+        }
+
+        if let Some(gamma) = self.gamma {
+            config.gamma = gamma;
+        }
+
+        if let Some(contrast) = self.contrast {
+            config.contrast = contrast;
+        }
+
+        if let Some(letter_spacing) = self.letter_spacing {
+            config.letter_spacing = letter_spacing;
+        }
+
+        if self.texture_padding.is_some() {
+            config.texture_padding = self.texture_padding;
+        }
+
+        if !self.charset_file.is_empty()
+            || !self.charset_range.is_empty()
+            || !self.charset_dir.is_empty()
+        {
+            let charset = config.charset.get_or_insert_default();
+            charset.files.extend(self.charset_file);
+            charset.ranges.extend(self.charset_range);
+            charset.script_dirs.extend(self.charset_dir);
+        }
+
+        if self.vertical {
+            config.vertical.get_or_insert_default();
+        }
+
+        if self.sdf || self.sdf_spread.is_some() {
+            let sdf = config.sdf.get_or_insert_default();
+            if let Some(spread) = self.sdf_spread {
+                sdf.spread = spread;
+            }
+        }
+    }
+}
+
+fn parse_fnt_version(s: &str) -> Result<FntVersion, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "v0" | "0" => Ok(FntVersion::V0),
+        "v1" | "1" => Ok(FntVersion::V1),
+        _ => Err(format!("unknown FNT4 version {:?}, expected v0 or v1", s)),
+    }
+}
+
+fn parse_resample_filter(s: &str) -> Result<ResampleFilter, String> {
+    let name = s.to_ascii_lowercase();
+    ResampleFilter::ALL
+        .into_iter()
+        .find(|filter| filter.name() == name)
+        .ok_or_else(|| {
+            let names: Vec<&str> = ResampleFilter::ALL.iter().map(|f| f.name()).collect();
+            format!(
+                "unknown filter {:?}, expected one of {}",
+                s,
+                names.join(", ")
+            )
+        })
+}
+
+fn parse_mipmap_strategy(s: &str) -> Result<MipmapStrategy, String> {
+    let name = s.to_ascii_lowercase();
+    MipmapStrategy::ALL
+        .into_iter()
+        .find(|strategy| strategy.name() == name)
+        .ok_or_else(|| {
+            let names: Vec<&str> = MipmapStrategy::ALL.iter().map(|s| s.name()).collect();
+            format!(
+                "unknown mipmap strategy {:?}, expected one of {}",
+                s,
+                names.join(", ")
+            )
+        })
+}
+
+fn parse_slot_class(s: &str) -> Result<CharClass, String> {
+    let name = s.to_ascii_lowercase();
+    SLOT_CLASSES
+        .into_iter()
+        .find(|class| class.name() == name)
+        .ok_or_else(|| {
+            let names: Vec<&str> = SLOT_CLASSES.iter().map(|c| c.name()).collect();
+            format!(
+                "unknown slot class {:?}, expected one of {}",
+                s,
+                names.join(", ")
+            )
+        })
+}
+
+/// Reads an FNT4 font file, or repacks a directory written by `extract` (with or without `--atlas`).
+fn load_font(input: &Path) -> Result<Fnt> {
+    if !input.is_dir() {
+        return Fnt::read_fnt(input)
+            .with_context(|| format!("Failed to parse FNT4 font {:?}", input));
+    }
+
+    let metadata = FntMetadata::read_metadata(&input.join("metadata.toml"))
+        .with_context(|| format!("Failed to read metadata.toml in {:?}", input))?;
+    let progress = print_progress("Processing glyphs");
+    let processed_glyphs = if input.join(ATLAS_FILENAME).exists() {
+        process_atlas_glyphs(input, &metadata, &progress)?
+    } else {
+        process_glyphs(input, &metadata, &progress)?
+    };
+
+    Ok(Fnt::from_processed_glyphs(metadata, processed_glyphs))
+}
+
+fn format_changes(changes: &[FieldChange]) -> String {
+    changes
+        .iter()
+        .map(|change| format!("{} {} -> {}", change.field, change.old, change.new))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn print_progress(label: &str) -> impl Fn(usize, usize) + Sync + '_ {
+    move |done, total| {
+        if done.is_multiple_of(100) || done == total {
+            print!(
+                "\r{}: {}/{} ({:.1}%)",
+                label,
+                done,
+                total,
+                done as f64 / total as f64 * 100.0
+            );
+            std::io::stdout().flush().ok();
+            if done == total {
+                println!();
+            }
+        }
+    }
+}
+
+/// Prints how many glyphs were rendered from each font of the fallback chain.
+fn print_source_glyph_counts(report: &RebuildReport, config: &RebuildConfig, source_font: &Path) {
+    if config.fonts.is_empty() {
+        return;
+    }
+    let paths = config
+        .fonts
+        .iter()
+        .map(|font| font.path.as_path())
+        .chain([source_font]);
+    for (path, count) in paths.zip(&report.source_glyph_counts) {
+        println!("Glyphs from {:?}: {}", path, count);
+    }
+}
+
+/// Prints which characters of the charset and `add_chars` got a glyph, and which didn't.
+fn print_added_chars(report: &RebuildReport, config: &RebuildConfig, version: FntVersion) {
+    if config.charset.is_some() {
+        println!("Charset chars: {}", report.added_chars.len());
+    } else if !report.added_chars.is_empty() {
+        println!("Added chars: {}", report.added_chars.len());
+    }
+    if !report.unsupported_chars.is_empty() {
+        let chars: String = report.unsupported_chars.iter().collect();
+        println!(
+            "Warning: {} chars cannot be stored in FNT4 {:?}: {:?}",
+            report.unsupported_chars.len(),
+            version,
+            chars
+        );
+    }
+    if !report.missing_chars.is_empty() {
+        let chars: String = report.missing_chars.iter().collect();
+        println!(
+            "Warning: {} chars not added, missing in TTF/OTF: {:?}",
+            report.missing_chars.len(),
+            chars
+        );
+    }
 }
 
 fn main() -> Result<()> {
@@ -77,11 +488,13 @@ fn main() -> Result<()> {
         Commands::Extract {
             input_fnt,
             output_dir,
+            atlas,
+            atlas_size,
         } => {
             println!("Reading FNT4 font: {:?}", input_fnt);
 
             let fnt = Fnt::read_fnt(&input_fnt)
-                .map_err(|e| anyhow::anyhow!("Failed to parse FNT4 font: {}", e))?;
+                .with_context(|| format!("Failed to parse FNT4 font {:?}", input_fnt))?;
 
             println!("FNT4 version: {:?}", fnt.metadata.version);
             println!(
@@ -92,7 +505,17 @@ fn main() -> Result<()> {
             println!("Mipmap level: {}", fnt.metadata.mipmap_level);
 
             println!("Extracting to: {:?}", output_dir);
-            extract_fnt(&fnt, &output_dir)?;
+            if atlas {
+                let report = extract_atlas(
+                    &fnt,
+                    &output_dir,
+                    atlas_size,
+                    &print_progress("Decoding glyphs"),
+                )?;
+                println!("Atlas pages: {}", report.page_count);
+            } else {
+                extract_fnt(&fnt, &output_dir, &print_progress("Exporting glyphs"))?;
+            }
 
             println!("Done!");
         }
@@ -100,6 +523,7 @@ fn main() -> Result<()> {
         Commands::Repack {
             input_dir,
             output_fnt,
+            atlas,
         } => {
             println!("Input directory: {:?}", input_dir);
             println!("Output FNT4 font: {:?}", output_fnt);
@@ -107,7 +531,9 @@ fn main() -> Result<()> {
             let metadata_path = input_dir.join("metadata.toml");
 
             if !metadata_path.exists() {
-                return Err(anyhow::anyhow!("metadata.txt not found in input directory"));
+                return Err(anyhow::anyhow!(
+                    "metadata.toml not found in input directory"
+                ));
             }
 
             let metadata = FntMetadata::read_metadata(&metadata_path)?;
@@ -116,7 +542,12 @@ fn main() -> Result<()> {
             println!("Total glyphs: {}", metadata.glyphs.len());
             println!("Mipmap level: {}", metadata.mipmap_level);
 
-            let processed_glyphs = process_glyphs(input_dir.as_path(), &metadata)?;
+            let progress = print_progress("Processing glyphs");
+            let processed_glyphs = if atlas {
+                process_atlas_glyphs(input_dir.as_path(), &metadata, &progress)?
+            } else {
+                process_glyphs(input_dir.as_path(), &metadata, &progress)?
+            };
 
             let fnt = Fnt::from_processed_glyphs(metadata, processed_glyphs);
 
@@ -124,26 +555,301 @@ fn main() -> Result<()> {
 
             println!("Done!");
         }
+        Commands::ExportBmfont {
+            input_fnt,
+            output_bmfont,
+            binary,
+            page_size,
+        } => {
+            println!("Reading FNT4 font: {:?}", input_fnt);
+
+            let fnt = Fnt::read_fnt(&input_fnt)
+                .with_context(|| format!("Failed to parse FNT4 font {:?}", input_fnt))?;
+
+            println!("FNT4 version: {:?}", fnt.metadata.version);
+            println!("Total glyphs: {}", fnt.metadata.glyphs.len());
+
+            let format = if binary {
+                BmFontFormat::Binary
+            } else {
+                BmFontFormat::Text
+            };
+            let report = export_bmfont(
+                &fnt,
+                &output_bmfont,
+                format,
+                page_size,
+                &print_progress("Decoding glyphs"),
+            )?;
+
+            println!("Exported chars: {}", report.glyph_count);
+            println!("Pages: {}", report.page_count);
+            println!("Done!");
+        }
+
+        Commands::ExportTtf {
+            input_fnt,
+            output_ttf,
+            family_name,
+        } => {
+            println!("Reading FNT4 font: {:?}", input_fnt);
+
+            let fnt = Fnt::read_fnt(&input_fnt)
+                .with_context(|| format!("Failed to parse FNT4 font {:?}", input_fnt))?;
+
+            println!("FNT4 version: {:?}", fnt.metadata.version);
+            println!("Total glyphs: {}", fnt.metadata.glyphs.len());
+
+            let family_name = family_name.unwrap_or_else(|| {
+                input_fnt
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "FNT4".to_string())
+            });
+            let report = export_ttf(
+                &fnt,
+                &output_ttf,
+                &family_name,
+                &print_progress("Decoding glyphs"),
+            )?;
+
+            println!("Family name: {}", family_name);
+            println!("Mapped characters: {}", report.character_count);
+            println!("Pixel size: {}", report.ppem);
+            println!("Done!");
+        }
+
+        Commands::ImportBmfont {
+            input_bmfont,
+            output_fnt,
+            fnt_version,
+            mipmap_level,
+        } => {
+            println!("Input BMFont: {:?}", input_bmfont);
+            println!("Output FNT4 font: {:?}", output_fnt);
+
+            let (fnt, report) = import_bmfont(
+                &input_bmfont,
+                fnt_version,
+                mipmap_level,
+                &print_progress("Processing glyphs"),
+            )?;
+
+            println!("FNT4 version: {:?}", fnt.metadata.version);
+            println!(
+                "Ascent: {}, Descent: {}",
+                fnt.metadata.ascent, fnt.metadata.descent
+            );
+            println!("Total glyphs: {}", report.glyph_count);
+            if !report.skipped_chars.is_empty() {
+                println!(
+                    "Skipped {} chars that cannot be stored in FNT4 {:?}",
+                    report.skipped_chars.len(),
+                    fnt.metadata.version
+                );
+            }
+
+            fnt.write_fnt(&output_fnt)?;
+
+            println!("Done!");
+        }
+
+        Commands::Preview {
+            input,
+            output_png,
+            text,
+            text_file,
+            max_width,
+        } => {
+            println!("Reading FNT4 font: {:?}", input);
+
+            let fnt = load_font(&input)?;
+
+            let text = match text_file {
+                Some(path) => std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read text file {:?}", path))?,
+                None => text.unwrap_or_default(),
+            };
+
+            let (image, report) = render_preview(&fnt, &text, &PreviewOptions { max_width })?;
+            image
+                .save(&output_png)
+                .with_context(|| format!("Failed to write {:?}", output_png))?;
+
+            println!("Lines: {}", report.line_count);
+            if !report.missing_chars.is_empty() {
+                let missing: String = report.missing_chars.iter().collect();
+                println!(
+                    "Drawn with the default glyph ({} chars): {:?}",
+                    report.missing_chars.len(),
+                    missing
+                );
+            }
+            println!("Output: {:?}", output_png);
+            println!("Done!");
+        }
+
+        Commands::Diff {
+            old,
+            new,
+            threshold,
+            contact_sheet,
+        } => {
+            println!("Old: {:?}", old);
+            println!("New: {:?}", new);
+
+            let old_fnt = load_font(&old)?;
+            let new_fnt = load_font(&new)?;
+
+            let diff = diff_fnt(
+                &old_fnt,
+                &new_fnt,
+                threshold,
+                &print_progress("Comparing glyphs"),
+            )?;
+
+            if !diff.header_changes.is_empty() {
+                println!("Header: {}", format_changes(&diff.header_changes));
+            }
+            if !diff.added_chars.is_empty() {
+                let chars: String = diff.added_chars.iter().collect();
+                println!("Added {} chars: {:?}", diff.added_chars.len(), chars);
+            }
+            if !diff.removed_chars.is_empty() {
+                let chars: String = diff.removed_chars.iter().collect();
+                println!("Removed {} chars: {:?}", diff.removed_chars.len(), chars);
+            }
+            for glyph in &diff.changed_glyphs {
+                let mut changes = format_changes(&glyph.metric_changes);
+                if glyph.changed_pixels > 0 {
+                    if !changes.is_empty() {
+                        changes.push_str(", ");
+                    }
+                    changes.push_str(&format!("{} pixels", glyph.changed_pixels));
+                }
+                println!(
+                    "U+{:04X} {:?}: {}",
+                    glyph.character as u32, glyph.character, changes
+                );
+            }
+            println!("Changed glyphs: {}", diff.changed_glyphs.len());
+
+            if let Some(path) = contact_sheet {
+                match render_contact_sheet(&diff) {
+                    Some(sheet) => {
+                        sheet
+                            .save(&path)
+                            .with_context(|| format!("Failed to write {:?}", path))?;
+                        println!("Contact sheet: {:?}", path);
+                    }
+                    None => println!("No changed glyphs, contact sheet not written"),
+                }
+            }
+
+            println!("Done!");
+        }
+
+        Commands::Map {
+            input_fnt,
+            output_mapping,
+            csv,
+            original_cols,
+            translation_cols,
+            reserve,
+            frequency_file,
+            previous,
+            slot_classes,
+            config,
+        } => {
+            println!("Reading FNT4 font: {:?}", input_fnt);
+
+            let fnt = Fnt::read_fnt(&input_fnt)
+                .with_context(|| format!("Failed to parse FNT4 font {:?}", input_fnt))?;
+            println!("FNT4 version: {:?}", fnt.metadata.version);
+
+            let mut map_config = match &config {
+                Some(path) => MappingConfig::load(path)
+                    .with_context(|| format!("Failed to load map config {:?}", path))?,
+                None => MappingConfig::default(),
+            };
+            map_config
+                .csv
+                .extend(csv.into_iter().map(|input| CsvConfig {
+                    original_cols: original_cols.clone(),
+                    translation_cols: translation_cols.clone(),
+                    ..CsvConfig::new(input)
+                }));
+            if map_config.csv.is_empty() {
+                return Err(anyhow::anyhow!("No CSV given, use --csv or --config"));
+            }
+
+            let frequencies = match frequency_file.or(map_config.frequency_file) {
+                Some(path) => Some(
+                    read_frequency_file(&path)
+                        .with_context(|| format!("Failed to read frequency file {:?}", path))?,
+                ),
+                None => None,
+            };
+            let previous = match previous.or(map_config.previous) {
+                Some(path) => read_mapping(&path)
+                    .with_context(|| format!("Failed to read previous mapping {:?}", path))?,
+                None => BTreeMap::new(),
+            };
+            let options = SlotOptions {
+                reserved: map_config.reserve.chars().chain(reserve.chars()).collect(),
+                frequencies,
+                previous,
+                classes: map_config
+                    .slot_classes
+                    .into_iter()
+                    .chain(slot_classes)
+                    .collect(),
+            };
+
+            let mapping =
+                create_mapping(&fnt.metadata, &map_config.csv, &options, &output_mapping)?;
+
+            println!("Missing characters to map: {}", mapping.needed_chars.len());
+            if !options.previous.is_empty() {
+                println!(
+                    "Kept {} previous assignments, freed {}",
+                    mapping.kept_count,
+                    mapping.freed.len()
+                );
+            }
+            println!(
+                "Available slots: Unused({}), Low priority({})",
+                mapping.unused_slot_count, mapping.low_priority_slot_count
+            );
+            if !mapping.unplaced_chars.is_empty() {
+                let chars: String = mapping.unplaced_chars.iter().collect();
+                println!(
+                    "Warning: not enough slots, {} chars left unmapped: {:?}",
+                    mapping.unplaced_chars.len(),
+                    chars
+                );
+            }
+            println!("Mapping saved: {:?}", output_mapping);
+            for csv_config in &map_config.csv {
+                println!("Mapped CSV saved: {:?}", csv_config.output);
+            }
+            println!("Done!");
+        }
+
         Commands::Rebuild {
             input_fnt,
             output_fnt,
             source_font,
-            size,
-            quality,
-            letter_spacing,
-            texture_padding,
+            render,
+            add_chars,
             config,
         } => {
             println!("Input FNT4 font: {:?}", input_fnt);
             println!("Output FNT4 font: {:?}", output_fnt);
             println!("Source font: {:?}", source_font);
 
-            let fnt = Fnt::read_fnt(&input_fnt).map_err(|e| {
-                std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("Failed to parse FNT4 font: {}", e),
-                )
-            })?;
+            let fnt = Fnt::read_fnt(&input_fnt)
+                .with_context(|| format!("Failed to parse FNT4 font {:?}", input_fnt))?;
 
             println!("FNT4 version: {:?}", fnt.metadata.version);
             println!(
@@ -156,29 +862,153 @@ fn main() -> Result<()> {
 
             let mut config = if let Some(path) = config {
                 println!("Config {:?}", path);
-                RebuildConfig::load(&path)?
+                let config = RebuildConfig::load(&path)?;
+                println!("Loaded {} replace entries.", config.replace.len());
+                config
             } else {
                 RebuildConfig::default()
             };
 
-            if let Some(size) = size {
-                config.size = Some(size);
+            render.apply(&mut config);
+
+            if let Some(path) = add_chars {
+                let chars = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {:?}", path))?;
+                config.add_chars.push_str(&chars);
             }
 
-            if let Some(quality) = quality {
-                config.quality = quality;
+            let ascent = fnt.metadata.ascent;
+            let descent = fnt.metadata.descent;
+            let mipmap_level = fnt.metadata.mipmap_level;
+
+            let font_data = std::fs::read(&source_font)?;
+            let (new_fnt, report) = rebuild_fnt(
+                fnt,
+                &font_data,
+                &config,
+                &print_progress("Processing glyphs"),
+            )?;
+
+            if config.size.is_none() {
+                println!(
+                    "Auto-calculated font size: {:.1} (ascent={}, descent={})",
+                    report.size, ascent, descent
+                );
+            }
+            if config.texture_padding.is_none() {
+                println!(
+                    "Auto-calculated texture padding: {} (based on mipmap level {})",
+                    report.texture_padding, mipmap_level
+                );
+            }
+
+            for restored in &report.restored_glyphs {
+                match restored.replaced_with {
+                    Some(target_char) => println!(
+                        "Restored glyph ID: {} ({:?} 0x{:04X} '{}' -> '{}') from original fnt",
+                        restored.glyph_id,
+                        restored.code_type,
+                        restored.char_code,
+                        restored.character,
+                        target_char
+                    ),
+                    None => println!(
+                        "Restored glyph ID: {} ({:?} 0x{:04X} '{}') from original fnt",
+                        restored.glyph_id,
+                        restored.code_type,
+                        restored.char_code,
+                        restored.character
+                    ),
+                }
             }
 
-            if let Some(letter_spacing) = letter_spacing {
-                config.letter_spacing = letter_spacing;
+            if !report.restored_glyphs.is_empty() {
+                println!(
+                    "Fallback Summary: Restored {} glyphs from original fnt (missing or empty in TTF/OTF).",
+                    report.restored_glyphs.len()
+                );
             }
 
-            if let Some(texture_padding) = texture_padding {
-                config.texture_padding = Some(texture_padding);
+            for unrestored in &report.unrestored_glyphs {
+                let replaced = unrestored
+                    .replaced_with
+                    .map(|c| format!(" -> '{}'", c))
+                    .unwrap_or_default();
+                println!(
+                    "Left glyph ID: {} ({:?} 0x{:04X} '{}'{}) empty, original fnt glyphs are no SDF",
+                    unrestored.glyph_id,
+                    unrestored.code_type,
+                    unrestored.char_code,
+                    unrestored.character,
+                    replaced
+                );
+            }
+            if !report.unrestored_glyphs.is_empty() {
+                println!(
+                    "Warning: {} glyphs missing in TTF/OTF were left empty instead of restored.",
+                    report.unrestored_glyphs.len()
+                );
             }
 
-            rebuild_fnt(fnt, &output_fnt, &source_font, &config)?;
+            print_source_glyph_counts(&report, &config, &source_font);
+            print_added_chars(&report, &config, new_fnt.metadata.version);
+
+            new_fnt.write_fnt(&output_fnt)?;
+
+            println!("Successfully rebuilt to {:?}", output_fnt);
+            println!("Done!");
+        }
+
+        Commands::Create {
+            source_font,
+            output_fnt,
+            fnt_version,
+            mipmap_level,
+            ascent,
+            descent,
+            render,
+            config,
+        } => {
+            println!("Source font: {:?}", source_font);
+            println!("Output FNT4 font: {:?}", output_fnt);
+
+            let mut config = match config {
+                Some(path) => {
+                    println!("Config {:?}", path);
+                    RebuildConfig::load(&path)?
+                }
+                None => RebuildConfig::default(),
+            };
+            render.apply(&mut config);
+
+            let options = CreateOptions {
+                version: fnt_version,
+                mipmap_level,
+                ascent,
+                descent,
+            };
+            let font_data = std::fs::read(&source_font)
+                .with_context(|| format!("Failed to read {:?}", source_font))?;
+            let (fnt, report) = create_fnt(
+                &font_data,
+                &options,
+                &config,
+                &print_progress("Processing glyphs"),
+            )?;
+
+            println!("FNT4 version: {:?}", fnt.metadata.version);
+            println!(
+                "Ascent: {}, Descent: {}",
+                fnt.metadata.ascent, fnt.metadata.descent
+            );
+            println!("Mipmap level: {}", fnt.metadata.mipmap_level);
+            println!("Texture padding: {}", report.texture_padding);
+            print_source_glyph_counts(&report, &config, &source_font);
+            print_added_chars(&report, &config, fnt.metadata.version);
+
+            fnt.write_fnt(&output_fnt)?;
 
+            println!("Successfully created {:?}", output_fnt);
             println!("Done!");
         }
     }
@@ -0,0 +1,594 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::charset::CharClass;
+use crate::csv::Csv;
+use crate::metadata::FntMetadata;
+
+fn default_original_cols() -> Vec<String> {
+    vec!["s".to_string()]
+}
+
+fn default_translation_cols() -> Vec<String> {
+    vec!["translated".to_string()]
+}
+
+/// A shin-translation-tools CSV to scan, and where to write its remapped copy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CsvConfig {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Columns with the original script text.
+    #[serde(default = "default_original_cols")]
+    pub original_cols: Vec<String>,
+    /// Columns with the translated text. These are remapped in the output.
+    #[serde(default = "default_translation_cols")]
+    pub translation_cols: Vec<String>,
+}
+
+impl CsvConfig {
+    /// Default columns, written to `<input stem>_mapped.csv` next to the input.
+    pub fn new(input: PathBuf) -> Self {
+        let stem = input
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let output = input.with_file_name(format!("{}_mapped.csv", stem));
+
+        CsvConfig {
+            input,
+            output,
+            original_cols: default_original_cols(),
+            translation_cols: default_translation_cols(),
+        }
+    }
+}
+
+/// Configuration of the `map` command.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MappingConfig {
+    #[serde(default)]
+    pub csv: Vec<CsvConfig>,
+    /// Characters that are never used as slots, e.g. ones drawn in images or UI.
+    #[serde(default)]
+    pub reserve: String,
+    /// Character frequencies of the original script, see [`read_frequency_file`].
+    /// Used instead of the original columns of the CSVs to rank slots.
+    #[serde(default)]
+    pub frequency_file: Option<PathBuf>,
+    /// Mapping written by an earlier run, whose assignments are kept where possible.
+    #[serde(default)]
+    pub previous: Option<PathBuf>,
+    /// Character classes used as slots besides CJK ideographs, from [`SLOT_CLASSES`].
+    #[serde(default)]
+    pub slot_classes: Vec<CharClass>,
+}
+
+impl MappingConfig {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        let config: MappingConfig = toml::from_str(&content).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("TOML parse error: {}", e),
+            )
+        })?;
+
+        if let Some(class) = config
+            .slot_classes
+            .iter()
+            .find(|class| !SLOT_CLASSES.contains(class))
+        {
+            let names: Vec<&str> = SLOT_CLASSES.iter().map(|c| c.name()).collect();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} can't be a slot class, expected one of {}",
+                    class.name(),
+                    names.join(", ")
+                ),
+            ));
+        }
+
+        Ok(config)
+    }
+}
+
+/// Characters used by the scripts, with how often they occur.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptChars {
+    /// Characters of the original columns.
+    pub original: BTreeMap<char, usize>,
+    /// Characters of the translation columns.
+    pub translated: BTreeMap<char, usize>,
+}
+
+fn count_chars(counts: &mut BTreeMap<char, usize>, text: &str) {
+    for c in text.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+}
+
+/// Reads character frequencies, one `<character> <count>` per line. Empty lines and lines
+/// starting with `#` are skipped.
+pub fn read_frequency_file(path: &Path) -> io::Result<BTreeMap<char, usize>> {
+    let content = std::fs::read_to_string(path)?;
+    let mut frequencies = BTreeMap::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let mut chars = line.chars();
+        let Some(c) = chars.next().filter(|&c| c != '#') else {
+            continue;
+        };
+        let count = chars.as_str().trim().parse::<usize>().map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} line {}: {}", path, i + 1, e),
+            )
+        })?;
+        *frequencies.entry(c).or_default() += count;
+    }
+
+    Ok(frequencies)
+}
+
+fn find_column(csv: &Csv, name: &str, path: &Path) -> io::Result<usize> {
+    csv.column(name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Column {:?} not found in {:?}", name, path),
+        )
+    })
+}
+
+impl ScriptChars {
+    pub fn scan_csv(&mut self, csv: &Csv, config: &CsvConfig) -> io::Result<()> {
+        for name in &config.original_cols {
+            let column = find_column(csv, name, &config.input)?;
+            for value in csv.column_values(column) {
+                count_chars(&mut self.original, value);
+            }
+        }
+
+        for name in &config.translation_cols {
+            let column = find_column(csv, name, &config.input)?;
+            for value in csv.column_values(column) {
+                count_chars(&mut self.translated, value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The first character of every glyph, with its `characters` key.
+///
+/// Other characters sharing a glyph are aliases: they have no glyph of their own to draw into.
+pub fn font_inventory(metadata: &FntMetadata) -> BTreeMap<char, u32> {
+    let decode = metadata.key_decoder();
+    let mut seen_glyphs = BTreeSet::new();
+    let mut inventory = BTreeMap::new();
+
+    for (&key, &glyph_id) in &metadata.characters {
+        if !seen_glyphs.insert(glyph_id) {
+            continue;
+        }
+        if let Some(c) = decode(key) {
+            inventory.insert(c, key);
+        }
+    }
+
+    inventory
+}
+
+/// Character classes that can be added to the slots. Kana and punctuation never are, the
+/// original script can't do without them.
+pub const SLOT_CLASSES: [CharClass; 3] = [CharClass::Latin, CharClass::Digit, CharClass::Fullwidth];
+
+/// Whether a glyph may be drawn over: a CJK ideograph, or a non-ASCII character of one of
+/// `classes`, but never kana or punctuation.
+///
+/// Everything else is left alone by default, as the original script can't do without digits,
+/// and may well use fullwidth Latin, Greek or iteration marks.
+pub fn is_slot_candidate(c: char, classes: &[CharClass]) -> bool {
+    if CharClass::Kana.contains(c) || CharClass::Punctuation.contains(c) {
+        return false;
+    }
+    CharClass::Han.contains(c) || (!c.is_ascii() && classes.iter().any(|class| class.contains(c)))
+}
+
+/// Options of [`allocate_slots`].
+#[derive(Debug, Clone, Default)]
+pub struct SlotOptions {
+    /// Characters that are never used as slots.
+    pub reserved: BTreeSet<char>,
+    /// Character frequencies of the original script, used instead of
+    /// [`ScriptChars::original`] to rank slots.
+    pub frequencies: Option<BTreeMap<char, usize>>,
+    /// `[replace]` table of an earlier run. Its assignments are kept as long as the character
+    /// is still needed and the slot still usable.
+    pub previous: BTreeMap<char, char>,
+    /// Character classes used as slots besides CJK ideographs.
+    pub classes: Vec<CharClass>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mapping {
+    /// Slot character in the font -> character drawn into its glyph, the `[replace]` table
+    /// of [`RebuildConfig`](crate::rebuild::RebuildConfig).
+    pub replace: BTreeMap<char, char>,
+    /// Translated characters without a glyph of their own in the font, most frequent first.
+    pub needed_chars: Vec<char>,
+    /// Needed characters that did not get a slot, because there are not enough of them.
+    pub unplaced_chars: Vec<char>,
+    /// Slots not used by the original script.
+    pub unused_slot_count: usize,
+    /// Slots used by the original script, taken once the unused slots run out.
+    pub low_priority_slot_count: usize,
+    /// Assignments kept from [`SlotOptions::previous`].
+    pub kept_count: usize,
+    /// Assignments of [`SlotOptions::previous`] that were dropped, because the character is
+    /// no longer needed or the slot can no longer be used.
+    pub freed: BTreeMap<char, char>,
+}
+
+/// Assigns every translated non-ASCII character without a glyph of its own to a slot: a glyph
+/// whose character passes [`is_slot_candidate`], is not reserved and is not used by the
+/// translation.
+///
+/// Slots are taken from the rarest in the original script to the most common, ties in
+/// character table order. Needed characters get them from the most frequent in the
+/// translation to the rarest, ties in code point order, so the rarest ones are left
+/// unplaced when slots run out. The same input always gives the same mapping.
+///
+/// Assignments of a previous mapping are kept first, so only characters that are new to the
+/// translation move into slots, and existing patches stay valid.
+pub fn allocate_slots(
+    metadata: &FntMetadata,
+    chars: &ScriptChars,
+    options: &SlotOptions,
+) -> Mapping {
+    let inventory = font_inventory(metadata);
+    let frequencies = options.frequencies.as_ref().unwrap_or(&chars.original);
+
+    let mut needed_chars: Vec<char> = chars
+        .translated
+        .keys()
+        .filter(|&&c| c as u32 >= 0x80 && !inventory.contains_key(&c))
+        .copied()
+        .collect();
+    needed_chars.sort_by_key(|c| (std::cmp::Reverse(chars.translated[c]), *c));
+
+    let usable = |c: char| {
+        inventory.contains_key(&c)
+            && is_slot_candidate(c, &options.classes)
+            && !options.reserved.contains(&c)
+            && !chars.translated.contains_key(&c)
+    };
+
+    let mut replace: BTreeMap<char, char> = BTreeMap::new();
+    let mut placed: BTreeSet<char> = BTreeSet::new();
+    for (&slot, &c) in &options.previous {
+        if usable(slot) && needed_chars.contains(&c) && placed.insert(c) {
+            replace.insert(slot, c);
+        }
+    }
+    let kept_count = replace.len();
+    let freed = options
+        .previous
+        .iter()
+        .filter(|&(slot, c)| replace.get(slot) != Some(c))
+        .map(|(&slot, &c)| (slot, c))
+        .collect();
+
+    // (frequency, key, character)
+    let mut slots: Vec<(usize, u32, char)> = inventory
+        .iter()
+        .filter(|&(&c, _)| usable(c) && !replace.contains_key(&c))
+        .map(|(&c, &key)| (frequencies.get(&c).copied().unwrap_or(0), key, c))
+        .collect();
+    slots.sort();
+
+    let unused_slot_count = slots
+        .iter()
+        .filter(|(frequency, _, _)| *frequency == 0)
+        .count();
+
+    let new_chars: Vec<char> = needed_chars
+        .iter()
+        .filter(|c| !placed.contains(c))
+        .copied()
+        .collect();
+    replace.extend(
+        slots
+            .iter()
+            .zip(&new_chars)
+            .map(|(&(_, _, slot), &c)| (slot, c)),
+    );
+    let unplaced_chars = new_chars.iter().skip(slots.len()).copied().collect();
+
+    Mapping {
+        replace,
+        needed_chars,
+        unplaced_chars,
+        unused_slot_count,
+        low_priority_slot_count: slots.len() - unused_slot_count,
+        kept_count,
+        freed,
+    }
+}
+
+#[derive(Serialize)]
+struct ReplaceTable<'a> {
+    replace: &'a BTreeMap<char, char>,
+}
+
+/// Reads the `[replace]` table of a mapping written by [`Mapping::write_mapping`], or of
+/// any rebuild config.
+pub fn read_mapping(path: &Path) -> io::Result<BTreeMap<char, char>> {
+    Ok(crate::rebuild::RebuildConfig::load(path)?.replace)
+}
+
+impl Mapping {
+    /// Writes the `[replace]` table, loadable as a rebuild config.
+    pub fn write_mapping(&self, path: &Path) -> io::Result<()> {
+        let content = toml::to_string(&ReplaceTable {
+            replace: &self.replace,
+        })
+        .map_err(|e| io::Error::other(format!("TOML serialization error: {}", e)))?;
+
+        std::fs::write(
+            path,
+            format!("# Generated Mapping Table for fnt4-tool\n{}", content),
+        )
+    }
+
+    /// Replaces every mapped character with its slot character.
+    pub fn apply(&self, text: &str) -> String {
+        let slots: BTreeMap<char, char> = self.replace.iter().map(|(&s, &c)| (c, s)).collect();
+        text.chars()
+            .map(|c| slots.get(&c).copied().unwrap_or(c))
+            .collect()
+    }
+
+    /// Remaps the translation columns of a CSV.
+    pub fn map_csv(&self, csv: &mut Csv, config: &CsvConfig) -> io::Result<()> {
+        for name in &config.translation_cols {
+            let column = find_column(csv, name, &config.input)?;
+            csv.map_column(column, |value| self.apply(value));
+        }
+        Ok(())
+    }
+}
+
+/// Scans the CSVs, allocates slots for the characters the font lacks, writes the mapping
+/// to `mapping_path` and the remapped CSVs to their outputs.
+pub fn create_mapping(
+    metadata: &FntMetadata,
+    csv_configs: &[CsvConfig],
+    options: &SlotOptions,
+    mapping_path: &Path,
+) -> io::Result<Mapping> {
+    let mut csvs = Vec::with_capacity(csv_configs.len());
+    let mut chars = ScriptChars::default();
+    for config in csv_configs {
+        let csv = Csv::read(&config.input).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read {:?}: {}", config.input, e),
+            )
+        })?;
+        chars.scan_csv(&csv, config)?;
+        csvs.push(csv);
+    }
+
+    let mapping = allocate_slots(metadata, &chars, options);
+    mapping.write_mapping(mapping_path)?;
+
+    for (mut csv, config) in csvs.into_iter().zip(csv_configs) {
+        mapping.map_csv(&mut csv, config)?;
+        std::fs::create_dir_all(config.output.parent().unwrap_or(Path::new("")))?;
+        csv.write(&config.output)?;
+    }
+
+    Ok(mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fnt::build_test_fnt;
+    use crate::metadata::FntVersion;
+    use crate::rebuild::RebuildConfig;
+
+    /// V0 font where each character has its own glyph, and all others share glyph 0.
+    fn build_metadata(chars: &str) -> FntMetadata {
+        let glyphs: Vec<_> = std::iter::once(' ')
+            .chain(chars.chars())
+            .map(|c| (c, 10, 12))
+            .collect();
+        build_test_fnt(FntVersion::V0, 1, 12, &glyphs).metadata
+    }
+
+    #[test]
+    fn test_font_inventory() {
+        let metadata = build_metadata("あ亜唖");
+        let inventory = font_inventory(&metadata);
+
+        // Space is the first character of the shared glyph 0
+        assert_eq!(
+            inventory.keys().collect::<String>(),
+            " あ亜唖".chars().collect::<String>()
+        );
+    }
+
+    fn counts(pairs: &[(char, usize)]) -> BTreeMap<char, usize> {
+        pairs.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_create_mapping() {
+        let dir = std::env::temp_dir().join(format!("fnt4_create_mapping_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // 亜 and 阿 are used by the original script, so they are taken last
+        let metadata = build_metadata("あ亜唖娃阿");
+        let input = dir.join("main.csv");
+        std::fs::write(&input, "s,translated\n亜あ,\"你好, 你\"\n阿,好们我\n").unwrap();
+
+        let config = CsvConfig::new(input);
+        let mapping_path = dir.join("mapping.toml");
+        let mapping = create_mapping(
+            &metadata,
+            std::slice::from_ref(&config),
+            &SlotOptions::default(),
+            &mapping_path,
+        )
+        .unwrap();
+
+        assert_eq!(mapping.needed_chars, ['你', '好', '们', '我']);
+        assert_eq!(mapping.unused_slot_count, 2);
+        assert_eq!(mapping.low_priority_slot_count, 2);
+        assert!(mapping.unplaced_chars.is_empty());
+        let expected: BTreeMap<char, char> =
+            [('唖', '你'), ('娃', '好'), ('亜', '们'), ('阿', '我')]
+                .into_iter()
+                .collect();
+        assert_eq!(mapping.replace, expected);
+
+        let rebuild_config = RebuildConfig::load(&mapping_path).unwrap();
+        assert_eq!(rebuild_config.replace, expected);
+
+        let output = std::fs::read_to_string(&config.output).unwrap();
+        assert_eq!(output, "s,translated\n亜あ,\"唖娃, 唖\"\n阿,娃亜阿\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_slot_ranking() {
+        // Kana and punctuation are never slots
+        let metadata = build_metadata("あア、亜唖娃阿");
+        let chars = ScriptChars {
+            original: counts(&[('亜', 5), ('唖', 1)]),
+            translated: counts(&[('你', 1), ('好', 3), ('我', 1)]),
+        };
+        let mut options = SlotOptions {
+            reserved: ['阿'].into_iter().collect(),
+            ..SlotOptions::default()
+        };
+
+        let mapping = allocate_slots(&metadata, &chars, &options);
+        assert_eq!(mapping.needed_chars, ['好', '你', '我']);
+        let expected: BTreeMap<char, char> = [('娃', '好'), ('唖', '你'), ('亜', '我')]
+            .into_iter()
+            .collect();
+        assert_eq!(mapping.replace, expected);
+
+        // Frequencies from a file replace the counts of the original columns
+        let dir = std::env::temp_dir().join(format!("fnt4_slot_ranking_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("frequency.txt");
+        std::fs::write(&path, "# char count\n娃 10\n\n").unwrap();
+        options.frequencies = Some(read_frequency_file(&path).unwrap());
+
+        let mapping = allocate_slots(&metadata, &chars, &options);
+        let expected: BTreeMap<char, char> = [('亜', '好'), ('唖', '你'), ('娃', '我')]
+            .into_iter()
+            .collect();
+        assert_eq!(mapping.replace, expected);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_previous_mapping() {
+        let metadata = build_metadata("亜唖娃阿");
+        let chars = ScriptChars {
+            original: BTreeMap::new(),
+            translated: counts(&[('你', 1), ('好', 1), ('我', 1), ('阿', 1)]),
+        };
+        let options = SlotOptions {
+            // 们 is no longer needed, 阿 is now used by the translation itself
+            previous: [('娃', '好'), ('亜', '们'), ('阿', '我')]
+                .into_iter()
+                .collect(),
+            ..SlotOptions::default()
+        };
+
+        let mapping = allocate_slots(&metadata, &chars, &options);
+
+        assert_eq!(mapping.kept_count, 1);
+        let freed: BTreeMap<char, char> = [('亜', '们'), ('阿', '我')].into_iter().collect();
+        assert_eq!(mapping.freed, freed);
+        let expected: BTreeMap<char, char> = [('娃', '好'), ('亜', '你'), ('唖', '我')]
+            .into_iter()
+            .collect();
+        assert_eq!(mapping.replace, expected);
+
+        // Running again on its own output changes nothing
+        let options = SlotOptions {
+            previous: mapping.replace.clone(),
+            ..SlotOptions::default()
+        };
+        let again = allocate_slots(&metadata, &chars, &options);
+        assert_eq!(again.replace, mapping.replace);
+        assert_eq!(again.kept_count, 3);
+        assert!(again.freed.is_empty());
+    }
+
+    #[test]
+    fn test_slot_classes() {
+        let metadata = build_metadata("Ａα々〇亜");
+        let chars = ScriptChars {
+            original: BTreeMap::new(),
+            translated: counts(&[('你', 6), ('好', 5), ('我', 4), ('们', 3), ('他', 2)]),
+        };
+
+        // Only ideographs are slots by default
+        let mapping = allocate_slots(&metadata, &chars, &SlotOptions::default());
+        assert_eq!(mapping.replace.keys().collect::<String>(), "亜");
+        for c in ['Ａ', 'α', '々', 'é', '〇'] {
+            assert!(!is_slot_candidate(c, &[]));
+        }
+
+        let options = SlotOptions {
+            classes: vec![CharClass::Fullwidth],
+            ..SlotOptions::default()
+        };
+        let mapping = allocate_slots(&metadata, &chars, &options);
+        assert_eq!(mapping.replace.keys().collect::<String>(), "亜Ａ");
+        assert!(is_slot_candidate('é', &[CharClass::Latin]));
+        assert!(!is_slot_candidate('e', &[CharClass::Latin]));
+
+        // Kana and punctuation stay out, even when their class is asked for
+        assert!(!is_slot_candidate('あ', &[CharClass::Kana]));
+        assert!(!is_slot_candidate('、', &[CharClass::Punctuation]));
+        assert!(!is_slot_candidate('！', &[CharClass::Fullwidth]));
+
+        let dir = std::env::temp_dir().join(format!("fnt4_slot_classes_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("mapping.toml");
+        std::fs::write(&path, "slot_classes = [\"latin\", \"kana\"]\n").unwrap();
+        let error = MappingConfig::load(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unplaced_chars() {
+        let metadata = build_metadata("亜");
+        let chars = ScriptChars {
+            original: BTreeMap::new(),
+            translated: counts(&[('你', 2), ('好', 1)]),
+        };
+        let mapping = allocate_slots(&metadata, &chars, &SlotOptions::default());
+
+        assert_eq!(mapping.replace.len(), 1);
+        assert_eq!(mapping.unplaced_chars, ['好']);
+    }
+}
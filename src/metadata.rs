@@ -7,6 +7,8 @@ use std::{
 use serde::{Deserialize, Serialize};
 
 use crate::glyph::LazyGlyph;
+use crate::mipmap::MipmapOptions;
+use crate::utils::{decode_sjis_u32, encode_sjis_u32, generate_sjis_map};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FntMetadata {
@@ -22,6 +24,21 @@ pub struct FntMetadata {
     #[serde(with = "hex_character")]
     pub characters: BTreeMap<u32, u32>, // Maps character code to glyph ID
     pub glyphs: BTreeMap<u32, GlyphMetadata>, // glyph_id -> glyph_metadata
+    /// How `repack` generates the mipmap levels, and which ones it reads from PNGs instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mipmaps: Option<MipmapMetadata>,
+}
+
+/// The `[mipmaps]` table of `metadata.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MipmapMetadata {
+    #[serde(flatten)]
+    pub options: MipmapOptions,
+    /// Levels read from their PNG (`_1.png` to `_3.png`) instead of generated. The other
+    /// level PNGs written by `extract` are ignored, so they can't go stale when `_0.png` is
+    /// edited.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub supplied: Vec<u8>,
 }
 
 impl FntMetadata {
@@ -43,12 +60,8 @@ impl FntMetadata {
     }
 
     pub fn write_metadata(&self, path: &Path) -> io::Result<()> {
-        let content = toml::to_string_pretty(self).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("TOML serialization error: {}", e),
-            )
-        })?;
+        let content = toml::to_string_pretty(self)
+            .map_err(|e| io::Error::other(format!("TOML serialization error: {}", e)))?;
 
         let file = std::fs::File::create(path)?;
 
@@ -56,6 +69,46 @@ impl FntMetadata {
 
         Ok(())
     }
+
+    /// Returns a function converting `characters` keys to Unicode characters: V1 keys are code
+    /// points, V0 keys are table indices converted through their Shift-JIS code.
+    pub fn key_decoder(&self) -> impl Fn(u32) -> Option<char> + use<> {
+        let sjis_map = match self.version {
+            FntVersion::V0 => Some(generate_sjis_map()),
+            FntVersion::V1 => None,
+        };
+
+        move |key| match &sjis_map {
+            Some(map) => decode_sjis_u32(*map.get(key as usize)?),
+            None => char::from_u32(key),
+        }
+    }
+
+    /// Maps every character of the character table to its glyph id.
+    ///
+    /// Entries with no Unicode equivalent are left out.
+    pub fn unicode_characters(&self) -> BTreeMap<char, u32> {
+        let decode = self.key_decoder();
+
+        self.characters
+            .iter()
+            .filter_map(|(&key, &glyph_id)| Some((decode(key)?, glyph_id)))
+            .collect()
+    }
+
+    /// Like [`unicode_characters`](Self::unicode_characters), without the characters that fall
+    /// back to the default glyph (the glyph of character 0). Character 0 itself (`'\0'` in V1,
+    /// the space in V0) is kept.
+    pub fn mapped_characters(&self) -> BTreeMap<char, u32> {
+        let decode = self.key_decoder();
+        let default_glyph = self.characters.get(&0).copied();
+
+        self.characters
+            .iter()
+            .filter(|&(&key, &glyph_id)| key == 0 || Some(glyph_id) != default_glyph)
+            .filter_map(|(&key, &glyph_id)| Some((decode(key)?, glyph_id)))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -73,6 +126,30 @@ impl FntVersion {
             _ => None,
         }
     }
+
+    /// Returns a function converting Unicode characters to `(char_code, characters key)`, the
+    /// inverse of [`FntMetadata::key_decoder`]. Characters the character table can't hold
+    /// give `None`: V0 only covers Shift-JIS, V1 every code point.
+    pub fn key_encoder(self) -> impl Fn(char) -> Option<(u32, u32)> + use<> {
+        let sjis_index: Option<BTreeMap<u32, u32>> = match self {
+            FntVersion::V0 => Some(
+                generate_sjis_map()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, code)| (code, index as u32))
+                    .collect(),
+            ),
+            FntVersion::V1 => None,
+        };
+
+        move |c| match &sjis_index {
+            Some(index) => {
+                let code = encode_sjis_u32(c)?;
+                Some((code, *index.get(&code)?))
+            }
+            None => Some((c as u32, c as u32)),
+        }
+    }
 }
 
 impl FntVersion {
@@ -89,6 +166,15 @@ impl FntVersion {
             FntVersion::V1 => 2,
         }
     }
+
+    /// Largest width or height of a glyph texture. Sizes are stored in a byte, and V1
+    /// textures are rounded up to a power of two.
+    pub fn max_texture_size(self) -> usize {
+        match self {
+            FntVersion::V0 => 255,
+            FntVersion::V1 => 128,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
@@ -117,7 +203,9 @@ pub fn detect_mipmap_level(lazy_glyphs: &BTreeMap<u32, LazyGlyph>) -> usize {
             continue;
         }
 
-        let decompressed = lazy_glyph.glyph_data.decompress(10, 2);
+        let Ok(decompressed) = lazy_glyph.glyph_data.decompress(10, 2) else {
+            continue;
+        };
         let mut pos = 0;
         let mut levels = 0;
 
@@ -173,8 +261,8 @@ mod hex_character {
         let mut map = BTreeMap::new();
 
         for (hex_key, v) in hex_map {
-            let key_result = if hex_key.starts_with("0x") {
-                u32::from_str_radix(&hex_key[2..], 16)
+            let key_result = if let Some(stripped) = hex_key.strip_prefix("0x") {
+                u32::from_str_radix(stripped, 16)
             } else {
                 u32::from_str_radix(&hex_key, 16)
             };
@@ -203,8 +291,8 @@ mod hex_string {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        if s.starts_with("0x") {
-            u32::from_str_radix(&s[2..], 16).map_err(serde::de::Error::custom)
+        if let Some(stripped) = s.strip_prefix("0x") {
+            u32::from_str_radix(stripped, 16).map_err(serde::de::Error::custom)
         } else {
             u32::from_str_radix(&s, 16).map_err(serde::de::Error::custom)
         }
@@ -0,0 +1,265 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::glyph::MipmapImage;
+use crate::resample::{ResampleFilter, resample};
+
+/// Strength of the unsharp mask of [`MipmapStrategy::Sharpened`].
+const SHARPEN_AMOUNT: f32 = 0.5;
+
+/// How the mipmap levels of V1 glyph textures are generated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MipmapStrategy {
+    /// Average of 2x2 pixels of the level above.
+    #[default]
+    Box,
+    /// Like `box`, with an unsharp mask so strokes keep their contrast.
+    Sharpened,
+    /// Resampled from the level above with the mipmap filter.
+    Filter,
+    /// Maximum of 2x2 pixels of the level above. Thin strokes stay fully opaque, glyphs get
+    /// bolder.
+    Max,
+    /// Like `box`, with the alpha scaled so the share of pixels at least half covered stays
+    /// that of level 0. Thin strokes don't fade out.
+    Coverage,
+}
+
+impl MipmapStrategy {
+    pub const ALL: [MipmapStrategy; 5] = [
+        MipmapStrategy::Box,
+        MipmapStrategy::Sharpened,
+        MipmapStrategy::Filter,
+        MipmapStrategy::Max,
+        MipmapStrategy::Coverage,
+    ];
+
+    /// The name used in configs and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            MipmapStrategy::Box => "box",
+            MipmapStrategy::Sharpened => "sharpened",
+            MipmapStrategy::Filter => "filter",
+            MipmapStrategy::Max => "max",
+            MipmapStrategy::Coverage => "coverage",
+        }
+    }
+}
+
+/// Mipmap generation, in the `[mipmaps]` table of `metadata.toml` and of the rebuild config.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MipmapOptions {
+    #[serde(default)]
+    pub strategy: MipmapStrategy,
+    /// Filter of the `filter` strategy.
+    #[serde(default)]
+    pub filter: ResampleFilter,
+}
+
+/// Generates the mipmap levels below a `width` x `height` level 0, until `count` levels
+/// or a level 1 pixel wide or high. Supplied levels are used as they are, placed at the
+/// top-left corner, and levels below them are generated from them.
+pub(crate) fn generate_mipmaps(
+    level0: &[u8],
+    width: usize,
+    height: usize,
+    count: usize,
+    supplied: &BTreeMap<u8, MipmapImage>,
+    options: MipmapOptions,
+) -> Vec<Vec<u8>> {
+    let level0: Vec<f32> = level0.iter().map(|&v| v as f32 / 255.0).collect();
+    let covered = |level: &[f32]| level.iter().filter(|&&a| a >= 0.5).count();
+    let level0_coverage = covered(&level0) as f32 / level0.len() as f32;
+
+    let mut levels = Vec::new();
+    // The level the next one is generated from, before sharpening or coverage scaling
+    let mut above = level0.clone();
+    let (mut w, mut h) = (width, height);
+
+    for level in 1..count {
+        if w <= 1 || h <= 1 {
+            break;
+        }
+        let (new_w, new_h) = (w / 2, h / 2);
+
+        let (mip, next_above) = if let Some(image) = supplied.get(&(level as u8)) {
+            let mut mip = vec![0.0f32; new_w * new_h];
+            let image_w = image.width as usize;
+            for y in 0..(image.height as usize).min(new_h) {
+                for x in 0..image_w.min(new_w) {
+                    if let Some(&pixel) = image.pixels.get(y * image_w + x) {
+                        mip[y * new_w + x] = pixel as f32 / 255.0;
+                    }
+                }
+            }
+            (mip.clone(), mip)
+        } else {
+            match options.strategy {
+                MipmapStrategy::Box => {
+                    let mip = halve(&above, w, h, |block| block.iter().sum::<f32>() / 4.0);
+                    (mip.clone(), mip)
+                }
+                MipmapStrategy::Sharpened => {
+                    let mip = halve(&above, w, h, |block| block.iter().sum::<f32>() / 4.0);
+                    (sharpen(&mip, new_w, new_h), mip)
+                }
+                MipmapStrategy::Filter => {
+                    let mip = resample(&above, w, h, new_w, new_h, options.filter, 1.0);
+                    (mip.clone(), mip)
+                }
+                MipmapStrategy::Max => {
+                    let mip = halve(&above, w, h, |block| {
+                        block.iter().copied().fold(0.0, f32::max)
+                    });
+                    (mip.clone(), mip)
+                }
+                MipmapStrategy::Coverage => {
+                    let mip = halve(&above, w, h, |block| block.iter().sum::<f32>() / 4.0);
+                    (scale_coverage(&mip, level0_coverage), mip)
+                }
+            }
+        };
+
+        levels.push(
+            mip.iter()
+                .map(|&v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+                .collect(),
+        );
+        above = next_above;
+        w = new_w;
+        h = new_h;
+    }
+
+    levels
+}
+
+/// Combines every 2x2 block of a level into one pixel.
+fn halve(src: &[f32], width: usize, height: usize, combine: impl Fn([f32; 4]) -> f32) -> Vec<f32> {
+    let (new_w, new_h) = (width / 2, height / 2);
+    let mut dst = vec![0.0f32; new_w * new_h];
+    for y in 0..new_h {
+        for x in 0..new_w {
+            dst[y * new_w + x] = combine([
+                src[(y * 2) * width + x * 2],
+                src[(y * 2) * width + x * 2 + 1],
+                src[(y * 2 + 1) * width + x * 2],
+                src[(y * 2 + 1) * width + x * 2 + 1],
+            ]);
+        }
+    }
+    dst
+}
+
+/// Unsharp mask against a 3x3 box blur, with empty pixels around the level.
+fn sharpen(src: &[f32], width: usize, height: usize) -> Vec<f32> {
+    let mut dst = vec![0.0f32; src.len()];
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let mut blurred = 0.0;
+            for (dx, dy) in (-1..=1).flat_map(|dy| (-1..=1).map(move |dx| (dx, dy))) {
+                let (sx, sy) = (x + dx, y + dy);
+                if sx >= 0 && sy >= 0 && sx < width as isize && sy < height as isize {
+                    blurred += src[sy as usize * width + sx as usize];
+                }
+            }
+            let value = src[y as usize * width + x as usize];
+            dst[y as usize * width + x as usize] =
+                (value + SHARPEN_AMOUNT * (value - blurred / 9.0)).clamp(0.0, 1.0);
+        }
+    }
+    dst
+}
+
+/// Scales the alpha of a level so the share of pixels at least half covered is `coverage`.
+fn scale_coverage(src: &[f32], coverage: f32) -> Vec<f32> {
+    let covered_at =
+        |scale: f32| src.iter().filter(|&&a| a * scale >= 0.5).count() as f32 / src.len() as f32;
+    if coverage <= 0.0 || covered_at(1.0) >= coverage {
+        return src.to_vec();
+    }
+
+    // Coverage grows with the scale, find the smallest scale that reaches it
+    let (mut low, mut high) = (1.0f32, 1.0f32);
+    while covered_at(high) < coverage && high < 256.0 {
+        high *= 2.0;
+    }
+    for _ in 0..16 {
+        let mid = (low + high) / 2.0;
+        if covered_at(mid) >= coverage {
+            high = mid;
+        } else {
+            low = mid;
+        }
+    }
+
+    src.iter().map(|&a| (a * high).min(1.0)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strategies() {
+        // A vertical stroke one pixel wide in an 8x8 texture
+        let mut level0 = vec![0u8; 64];
+        for y in 0..8 {
+            level0[y * 8 + 3] = 255;
+        }
+        let generate = |strategy| {
+            let options = MipmapOptions {
+                strategy,
+                ..Default::default()
+            };
+            generate_mipmaps(&level0, 8, 8, 4, &BTreeMap::new(), options)
+        };
+
+        let levels = generate(MipmapStrategy::Box);
+        assert_eq!(levels.len(), 3);
+        // Rounded, not truncated
+        assert_eq!(levels[0][1], 128);
+        assert_eq!(levels[1][0], 64);
+        assert_eq!(levels[2], [32]);
+
+        // Strokes stay opaque with max, and stay at least half covered with coverage
+        assert_eq!(generate(MipmapStrategy::Max)[2], [255]);
+        let coverage = generate(MipmapStrategy::Coverage);
+        assert_eq!(coverage[0][1], 128);
+        assert_eq!(coverage[1][0], 128);
+
+        // Sharpening keeps the stroke darker than its blurred surroundings
+        let sharpened = generate(MipmapStrategy::Sharpened);
+        assert!(sharpened[0][1] > 128 && sharpened[0][0] == 0);
+
+        let filtered = generate(MipmapStrategy::Filter);
+        assert_eq!(filtered.len(), 3);
+        assert!(filtered[0][1] > 0);
+    }
+
+    #[test]
+    fn test_filter_follows_supplied_level() {
+        // An empty level 0 with a full supplied level 1, level 2 comes from level 1
+        let level0 = vec![0u8; 64];
+        let supplied = BTreeMap::from([(
+            1,
+            MipmapImage {
+                width: 4,
+                height: 4,
+                pixels: vec![255; 16],
+            },
+        )]);
+        let options = MipmapOptions {
+            strategy: MipmapStrategy::Filter,
+            ..Default::default()
+        };
+
+        let levels = generate_mipmaps(&level0, 8, 8, 4, &supplied, options);
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[0], [255; 16]);
+        // Resampled from the full level 1, not from the empty level 0
+        assert!(levels[1].iter().all(|&v| v > 128));
+        assert!(levels[2][0] > 128);
+    }
+}
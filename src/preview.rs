@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+
+use crate::error::FntError;
+use crate::fnt::Fnt;
+use crate::glyph::Glyph;
+use crate::metadata::FntMetadata;
+
+/// Empty pixels around the text.
+const MARGIN: u32 = 4;
+
+#[derive(Debug, Clone, Default)]
+pub struct PreviewOptions {
+    /// Wrap lines wider than this many pixels, breaking after the last space when there is one.
+    pub max_width: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PreviewReport {
+    pub line_count: usize,
+    /// Characters drawn with the default glyph, because the font has no glyph of their own.
+    pub missing_chars: Vec<char>,
+}
+
+/// A glyph placed on a line, at pen position `x`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PlacedGlyph {
+    glyph_id: u32,
+    x: i32,
+}
+
+#[derive(Debug, Default)]
+struct Line {
+    glyphs: Vec<PlacedGlyph>,
+    /// Sum of advances.
+    width: i32,
+}
+
+impl Line {
+    fn push(&mut self, glyph_id: u32, advance: u8) {
+        self.glyphs.push(PlacedGlyph {
+            glyph_id,
+            x: self.width,
+        });
+        self.width += advance as i32;
+    }
+
+    /// Moves the glyphs from `index` on to a new line.
+    fn split_off(&mut self, index: usize) -> Line {
+        let glyphs: Vec<PlacedGlyph> = self.glyphs.split_off(index);
+        let start = glyphs.first().map_or(self.width, |glyph| glyph.x);
+        let rest = Line {
+            glyphs: glyphs
+                .into_iter()
+                .map(|glyph| PlacedGlyph {
+                    x: glyph.x - start,
+                    ..glyph
+                })
+                .collect(),
+            width: self.width - start,
+        };
+        self.width = start;
+        rest
+    }
+}
+
+/// Lays out `text` with the advances of the font, one [`Line`] per line break or wrap.
+///
+/// Characters that are not in the character table use the default glyph (the glyph of
+/// character 0). Those and the characters mapped to the default glyph are returned as missing.
+fn layout_text(
+    metadata: &FntMetadata,
+    text: &str,
+    max_width: Option<u32>,
+) -> (Vec<Line>, Vec<char>) {
+    let characters = metadata.mapped_characters();
+    let default_glyph = metadata.characters.get(&0).copied();
+
+    let mut lines = Vec::new();
+    let mut missing_chars: Vec<char> = Vec::new();
+
+    for paragraph in text.lines() {
+        let mut line = Line::default();
+        // Glyph index right after the last space of the line
+        let mut break_index: Option<usize> = None;
+
+        for c in paragraph.chars() {
+            let glyph_id = characters.get(&c).copied().or(default_glyph);
+            if !characters.contains_key(&c) && !missing_chars.contains(&c) {
+                missing_chars.push(c);
+            }
+            let Some(glyph_id) = glyph_id else {
+                continue;
+            };
+            let advance = metadata.glyphs.get(&glyph_id).map_or(0, |g| g.advance);
+
+            // Spaces may hang past the line end
+            if let Some(max_width) = max_width
+                && !c.is_whitespace()
+                && !line.glyphs.is_empty()
+                && line.width + advance as i32 > max_width as i32
+            {
+                let rest = match break_index.take() {
+                    Some(index) => line.split_off(index),
+                    None => Line::default(),
+                };
+                lines.push(std::mem::replace(&mut line, rest));
+            }
+
+            line.push(glyph_id, advance);
+            if c.is_whitespace() {
+                break_index = Some(line.glyphs.len());
+            }
+        }
+
+        lines.push(line);
+    }
+
+    (lines, missing_chars)
+}
+
+/// Renders `text` in black on white, laid out the way the game does with the font metrics.
+///
+/// Lines are `ascent + descent` pixels apart. Glyphs are drawn at the pen position plus
+/// `bearing_x`, with their top `bearing_y` pixels above the baseline.
+pub fn render_preview(
+    fnt: &Fnt,
+    text: &str,
+    options: &PreviewOptions,
+) -> Result<(image::RgbaImage, PreviewReport), FntError> {
+    let metadata = &fnt.metadata;
+    let (lines, missing_chars) = layout_text(metadata, text, options.max_width);
+
+    let mut glyphs: BTreeMap<u32, Glyph> = BTreeMap::new();
+    for glyph in lines.iter().flat_map(|line| &line.glyphs) {
+        if glyphs.contains_key(&glyph.glyph_id) {
+            continue;
+        }
+        let Some(lazy_glyph) = fnt.lazy_glyphs.get(&glyph.glyph_id) else {
+            continue;
+        };
+        let decoded = Glyph::from_lazy_glyph(lazy_glyph, metadata.version).map_err(|e| {
+            FntError::GlyphData {
+                glyph_id: glyph.glyph_id,
+                source: Box::new(e),
+            }
+        })?;
+        glyphs.insert(glyph.glyph_id, decoded);
+    }
+
+    let line_height = metadata.ascent as u32 + metadata.descent as u32;
+    let text_width = lines.iter().map(|line| line.width.max(0) as u32).max();
+    let width = options.max_width.or(text_width).unwrap_or(0) + MARGIN * 2;
+    let height = lines.len() as u32 * line_height + MARGIN * 2;
+
+    let mut image = image::RgbaImage::from_pixel(width, height, image::Rgba([255, 255, 255, 255]));
+
+    for (i, line) in lines.iter().enumerate() {
+        let baseline = (MARGIN + i as u32 * line_height + metadata.ascent as u32) as i32;
+
+        for placed in &line.glyphs {
+            let Some(glyph) = glyphs.get(&placed.glyph_id) else {
+                continue;
+            };
+            let left = MARGIN as i32 + placed.x + glyph.info.bearing_x as i32;
+            let top = baseline - glyph.info.bearing_y as i32;
+
+            for (gx, gy, pixel) in glyph.to_image(0).enumerate_pixels() {
+                let (x, y) = (left + gx as i32, top + gy as i32);
+                if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+                    continue;
+                }
+                // Overlapping glyphs keep the darker pixel
+                let target = image.get_pixel_mut(x as u32, y as u32);
+                let value = target.0[0].min(255 - pixel.0[3]);
+                *target = image::Rgba([value, value, value, 255]);
+            }
+        }
+    }
+
+    let report = PreviewReport {
+        line_count: lines.len(),
+        missing_chars,
+    };
+
+    Ok((image, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fnt::build_test_fnt;
+    use crate::metadata::FntVersion;
+
+    fn test_fnt() -> Fnt {
+        build_test_fnt(
+            FntVersion::V1,
+            1,
+            12,
+            &[('\0', 4, 6), ('a', 6, 8), (' ', 0, 4)],
+        )
+    }
+
+    fn line_glyphs(line: &Line) -> Vec<(u32, i32)> {
+        line.glyphs.iter().map(|g| (g.glyph_id, g.x)).collect()
+    }
+
+    #[test]
+    fn test_layout_text() {
+        let fnt = test_fnt();
+
+        let (lines, missing) = layout_text(&fnt.metadata, "aa\na z", None);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(line_glyphs(&lines[0]), [(1, 0), (1, 8)]);
+        assert_eq!(line_glyphs(&lines[1]), [(1, 0), (2, 8), (0, 12)]);
+        assert_eq!(lines[1].width, 18);
+        assert_eq!(missing, ['z']);
+
+        // Breaks after the space, the space hangs at the end of the first line
+        let (lines, _) = layout_text(&fnt.metadata, "aa aa", Some(30));
+        assert_eq!(line_glyphs(&lines[0]), [(1, 0), (1, 8), (2, 16)]);
+        assert_eq!(line_glyphs(&lines[1]), [(1, 0), (1, 8)]);
+
+        // No space to break at
+        let (lines, _) = layout_text(&fnt.metadata, "aaaaa", Some(20));
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[2].width, 8);
+    }
+
+    #[test]
+    fn test_render_preview() {
+        let fnt = test_fnt();
+        let options = PreviewOptions {
+            max_width: Some(20),
+        };
+        let (image, report) = render_preview(&fnt, "aaa", &options).unwrap();
+
+        assert_eq!(report.line_count, 2);
+        assert!(report.missing_chars.is_empty());
+        assert_eq!(image.dimensions(), (20 + MARGIN * 2, 16 * 2 + MARGIN * 2));
+
+        // First glyph starts at the margin plus bearing_x, its top bearing_y above the baseline
+        let (left, top) = (MARGIN + 1, MARGIN + 12 - 10);
+        assert_eq!(image.get_pixel(left, top).0, [0, 0, 0, 255]);
+        assert_eq!(image.get_pixel(left - 1, top).0, [255, 255, 255, 255]);
+        assert_eq!(image.get_pixel(left, top - 1).0, [255, 255, 255, 255]);
+        assert_eq!(image.get_pixel(left, top + 16).0, [0, 0, 0, 255]);
+    }
+}
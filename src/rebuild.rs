@@ -1,17 +1,24 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::io::Write;
-use std::path::Path;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+use ab_glyph::{Font, FontRef, GlyphId, PxScale, ScaleFont};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::charset::{CharClass, Charset, parse_range};
+use crate::effects::GlyphEffects;
 use crate::fnt::Fnt;
-use crate::glyph::{GlyphInfo, ProcessedGlyph, RenderedGlyph, encode_glyph_texture};
-use crate::metadata::{CodeType, FntVersion, GlyphMetadata};
-use crate::utils::{decode_sjis_u32, downsample_lanczos};
+use crate::glyph::{GlyphInfo, ProcessedGlyph, RenderedGlyph, encode_glyph_texture_with_mipmaps};
+use crate::hinting::render_hinted_glyph;
+use crate::metadata::{CodeType, FntMetadata, FntVersion, GlyphMetadata};
+use crate::mipmap::MipmapOptions;
+use crate::resample::{ResampleFilter, resample};
+use crate::sdf::{SdfConfig, render_sdf_glyph};
+use crate::utils::{ProgressFn, decode_sjis_u32};
+use crate::vertical::{VerticalConfig, rotate_glyph, vertical_forms};
 
 fn default_size() -> Option<f32> {
     None
@@ -25,18 +32,163 @@ fn default_letter_spacing() -> i8 {
     0
 }
 
+fn default_gamma() -> f32 {
+    1.0
+}
+
+fn default_contrast() -> f32 {
+    1.0
+}
+
+fn default_resample_gamma() -> f32 {
+    1.0
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RebuildConfig {
     #[serde(default = "default_size")]
     pub size: Option<f32>,
     #[serde(default = "default_quality")]
     pub quality: u8,
+    /// Filter for downsampling glyphs rendered at `quality` times the size.
+    #[serde(default)]
+    pub resample_filter: ResampleFilter,
+    /// Gamma the coverage is decoded with before downsampling and encoded with after. 1.0
+    /// filters the linear coverage.
+    #[serde(default = "default_resample_gamma")]
+    pub resample_gamma: f32,
+    /// How mipmap levels are generated.
+    #[serde(default)]
+    pub mipmaps: MipmapOptions,
     #[serde(default = "default_letter_spacing")]
     pub letter_spacing: i8,
+    /// Snap horizontal and vertical stems to the pixel grid and render at the font size,
+    /// ignoring `quality`. Crisper at small sizes, especially for CJK.
+    #[serde(default)]
+    pub hinting: bool,
+    /// Gamma of the glyph alpha. Above 1.0 makes antialiased edges heavier, below 1.0
+    /// lighter.
+    #[serde(default = "default_gamma")]
+    pub gamma: f32,
+    /// Contrast of the glyph alpha, at least 1.0. Higher values make antialiased edges
+    /// sharper.
+    #[serde(default = "default_contrast")]
+    pub contrast: f32,
     #[serde(default)]
     pub texture_padding: Option<u8>,
     #[serde(default)]
     pub replace: BTreeMap<char, char>,
+    /// Characters to add to the font, rendered from the source font with new glyph ids.
+    /// Characters that already have a glyph of their own are rebuilt as usual.
+    #[serde(default)]
+    pub add_chars: String,
+    /// Build the font with exactly these characters instead of the glyphs of the input font,
+    /// which then only provides the header values (version, ascent, descent, mipmap level)
+    /// and the default glyph.
+    #[serde(default)]
+    pub charset: Option<Charset>,
+    /// Fonts tried before the main source font, in order. Each glyph is rendered from the
+    /// first font that covers its character and has a glyph for it.
+    #[serde(default)]
+    pub fonts: Vec<SourceFont>,
+    /// Metrics of some characters that differ from the rest of the font. When several
+    /// overrides match a character, later ones win.
+    #[serde(default)]
+    pub overrides: Vec<MetricOverride>,
+    /// Render the glyphs for vertical text.
+    #[serde(default)]
+    pub vertical: Option<VerticalConfig>,
+    /// Outline, glow and shadow drawn around every glyph.
+    #[serde(default)]
+    pub effects: Option<GlyphEffects>,
+    /// Encode the glyphs as signed distance fields instead of coverage.
+    #[serde(default)]
+    pub sdf: Option<SdfConfig>,
+}
+
+/// Metric changes for the characters matching any of `chars`, `classes` or `ranges`.
+///
+/// Characters are matched after `replace`, as they are drawn. Offsets and widths are in
+/// pixels.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MetricOverride {
+    #[serde(default)]
+    pub chars: String,
+    #[serde(default)]
+    pub classes: Vec<CharClass>,
+    /// Unicode ranges, see [`parse_range`].
+    #[serde(default)]
+    pub ranges: Vec<String>,
+    /// Font size in pixels, instead of the size of the rebuild or source font.
+    #[serde(default)]
+    pub size: Option<f32>,
+    /// Moves the glyph right, or left when negative.
+    #[serde(default)]
+    pub offset_x: Option<i8>,
+    /// Moves the glyph up, or down when negative.
+    #[serde(default)]
+    pub offset_y: Option<i8>,
+    /// Advance width, before letter spacing.
+    #[serde(default)]
+    pub advance: Option<u8>,
+    /// Advance width with the glyph centered in it, before letter spacing.
+    #[serde(default)]
+    pub monospace: Option<u8>,
+}
+
+/// The metric changes of all [`MetricOverride`]s matching one character.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct GlyphOverride {
+    size: Option<f32>,
+    offset_x: Option<i8>,
+    offset_y: Option<i8>,
+    advance: Option<u8>,
+    monospace: Option<u8>,
+}
+
+impl GlyphOverride {
+    fn merge(self, other: &MetricOverride) -> GlyphOverride {
+        GlyphOverride {
+            size: other.size.or(self.size),
+            offset_x: other.offset_x.or(self.offset_x),
+            offset_y: other.offset_y.or(self.offset_y),
+            advance: other.advance.or(self.advance),
+            monospace: other.monospace.or(self.monospace),
+        }
+    }
+
+    /// Applies the changes to the `(bearing_x, bearing_y, advance)` of a glyph `width` pixels wide.
+    fn apply(&self, bearing_x: i8, bearing_y: i8, advance: u8, width: u8) -> (i8, i8, u8) {
+        let (mut bearing_x, mut advance) = (bearing_x, self.advance.unwrap_or(advance));
+        if let Some(monospace) = self.monospace {
+            advance = monospace;
+            bearing_x = ((monospace as i16 - width as i16) / 2).clamp(-128, 127) as i8;
+        }
+
+        (
+            bearing_x.saturating_add(self.offset_x.unwrap_or(0)),
+            bearing_y.saturating_add(self.offset_y.unwrap_or(0)),
+            advance,
+        )
+    }
+}
+
+/// A source font of the fallback chain, see [`RebuildConfig::fonts`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceFont {
+    /// TTF/OTF font file.
+    pub path: PathBuf,
+    /// Unicode ranges the font is used for, see [`parse_range`].
+    /// Default: every character
+    #[serde(default)]
+    pub ranges: Vec<String>,
+    /// Font size in pixels.
+    /// Default: the size of the rebuild
+    #[serde(default)]
+    pub size: Option<f32>,
+    /// Pixels the glyphs are moved up, or down when negative.
+    #[serde(default)]
+    pub baseline_offset: i8,
 }
 
 impl Default for RebuildConfig {
@@ -44,9 +196,22 @@ impl Default for RebuildConfig {
         RebuildConfig {
             size: default_size(),
             quality: default_quality(),
+            resample_filter: ResampleFilter::default(),
+            resample_gamma: default_resample_gamma(),
+            mipmaps: MipmapOptions::default(),
             texture_padding: None,
             letter_spacing: default_letter_spacing(),
+            hinting: false,
+            gamma: default_gamma(),
+            contrast: default_contrast(),
             replace: BTreeMap::new(),
+            add_chars: String::new(),
+            charset: None,
+            fonts: Vec::new(),
+            overrides: Vec::new(),
+            vertical: None,
+            effects: None,
+            sdf: None,
         }
     }
 }
@@ -61,133 +226,498 @@ impl RebuildConfig {
             )
         })?;
 
-        println!("Loaded {} replace entries.", config.replace.len());
         Ok(config)
     }
 }
 
 struct ResolvedConfig {
-    size: f32,
     quality: u8,
+    resample_filter: ResampleFilter,
+    resample_gamma: f32,
+    mipmaps: MipmapOptions,
     texture_padding: u8,
     letter_spacing: i8,
+    hinting: bool,
+    gamma: f32,
+    contrast: f32,
     replace: BTreeMap<char, char>,
+    /// With their ranges parsed.
+    overrides: Vec<(MetricOverride, Vec<RangeInclusive<char>>)>,
+    vertical: Option<VerticalConfig>,
+    effects: Option<GlyphEffects>,
+    sdf: Option<SdfConfig>,
 }
 
-pub fn rebuild_fnt(
-    fnt: Fnt,
-    output_fnt: &Path,
-    source_font: &Path,
-    config: &RebuildConfig,
-) -> std::io::Result<()> {
-    let font_size = if let Some(size) = config.size {
-        size
-    } else {
-        let original_height =
-            (fnt.metadata.ascent as i16 + fnt.metadata.descent as i16).unsigned_abs() as f32;
-        println!(
-            "Auto-calculated font size: {:.1} (ascent={}, descent={})",
-            original_height, fnt.metadata.ascent, fnt.metadata.descent
-        );
-        original_height
+fn parse_override_ranges(
+    overrides: &[MetricOverride],
+) -> std::io::Result<Vec<(MetricOverride, Vec<RangeInclusive<char>>)>> {
+    overrides
+        .iter()
+        .map(|metric_override| {
+            let ranges = metric_override
+                .ranges
+                .iter()
+                .map(|range| parse_range(range))
+                .collect::<std::io::Result<_>>()?;
+            Ok((metric_override.clone(), ranges))
+        })
+        .collect()
+}
+
+impl ResolvedConfig {
+    fn glyph_override(&self, c: char) -> GlyphOverride {
+        self.overrides
+            .iter()
+            .filter(|(metric_override, ranges)| {
+                metric_override.chars.contains(c)
+                    || metric_override
+                        .classes
+                        .iter()
+                        .any(|class| class.contains(c))
+                    || ranges.iter().any(|range| range.contains(&c))
+            })
+            .fold(GlyphOverride::default(), |merged, (metric_override, _)| {
+                merged.merge(metric_override)
+            })
+    }
+}
+
+/// A font of the fallback chain, with the defaults of [`SourceFont`] resolved.
+struct ChainFont<F> {
+    font: F,
+    /// Empty for every character.
+    ranges: Vec<RangeInclusive<char>>,
+    size: f32,
+    baseline_offset: i8,
+    /// Glyphs of the vertical forms, when rendering for vertical text.
+    vertical_forms: BTreeMap<char, GlyphId>,
+}
+
+impl<F: Font> ChainFont<F> {
+    fn has_glyph(&self, c: char) -> bool {
+        // Character 0 renders the .notdef glyph
+        (self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&c)))
+            && (self.font.glyph_id(c).0 != 0 || c == '\0')
+    }
+}
+
+/// Index of the first font of the chain that has a glyph for `c`.
+fn select_font<F: Font>(chain: &[ChainFont<F>], c: char) -> Option<usize> {
+    chain.iter().position(|font| font.has_glyph(c))
+}
+
+/// A glyph that could not be rendered from the source font and was copied from the original FNT4 font.
+#[derive(Debug, Clone)]
+pub struct RestoredGlyph {
+    pub glyph_id: u32,
+    pub code_type: CodeType,
+    pub char_code: u32,
+    pub character: char,
+    /// The character that was looked up in the source font, if it was replaced.
+    pub replaced_with: Option<char>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RebuildReport {
+    /// Font size in pixels actually used, either configured or auto-calculated.
+    pub size: f32,
+    /// Texture padding in pixels actually used, either configured or auto-calculated.
+    pub texture_padding: u8,
+    pub glyph_count: usize,
+    pub restored_glyphs: Vec<RestoredGlyph>,
+    /// Glyphs the source fonts can't render that were left empty instead of restored, because
+    /// the coverage bitmaps of the original font don't mix with SDF glyphs.
+    pub unrestored_glyphs: Vec<RestoredGlyph>,
+    /// Characters of [`RebuildConfig::add_chars`] and [`RebuildConfig::charset`] that got a
+    /// new glyph.
+    pub added_chars: Vec<char>,
+    /// Characters of [`RebuildConfig::add_chars`] and [`RebuildConfig::charset`] the character
+    /// table can't hold, i.e. characters outside Shift-JIS in V0.
+    pub unsupported_chars: Vec<char>,
+    /// Characters of [`RebuildConfig::add_chars`] and [`RebuildConfig::charset`] none of the
+    /// source fonts has a glyph for.
+    pub missing_chars: Vec<char>,
+    /// Number of glyphs rendered from each font of [`RebuildConfig::fonts`], followed by the
+    /// main source font.
+    pub source_glyph_counts: Vec<usize>,
+}
+
+/// Removes every glyph and character table entry except the default glyph (the glyph of
+/// character 0), so that only the header values are left of the font.
+fn keep_default_glyph_only(fnt: &mut Fnt) {
+    let default_glyph = fnt.metadata.characters.get(&0).copied();
+
+    fnt.metadata.characters.retain(|&key, _| key == 0);
+    fnt.metadata
+        .glyphs
+        .retain(|&glyph_id, _| Some(glyph_id) == default_glyph);
+    fnt.lazy_glyphs
+        .retain(|&glyph_id, _| Some(glyph_id) == default_glyph);
+}
+
+/// Result of [`add_characters`].
+struct AddedCharacters {
+    added: Vec<char>,
+    unsupported: Vec<char>,
+    missing: Vec<char>,
+}
+
+/// Gives every character of `chars` without a glyph of its own a new glyph id and character
+/// table entry. The glyphs are left to be rendered from the source fonts, so characters none
+/// of them has (after `replace`) are skipped.
+fn add_characters<F: Font>(
+    metadata: &mut FntMetadata,
+    chain: &[ChainFont<F>],
+    chars: &str,
+    replace: &BTreeMap<char, char>,
+) -> AddedCharacters {
+    let existing = metadata.mapped_characters();
+    let encode = metadata.version.key_encoder();
+    let code_type = match metadata.version {
+        FntVersion::V0 => CodeType::Sjis,
+        FntVersion::V1 => CodeType::Unicode,
     };
 
-    let texture_padding = if let Some(padding) = config.texture_padding {
-        padding
-    } else {
-        let padding = (1 << fnt.metadata.mipmap_level.saturating_sub(1)).max(2);
-        println!(
-            "Auto-calculated texture padding: {} (based on mipmap level {})",
-            padding, fnt.metadata.mipmap_level
+    let mut result = AddedCharacters {
+        added: Vec::new(),
+        unsupported: Vec::new(),
+        missing: Vec::new(),
+    };
+    let mut seen = BTreeSet::new();
+
+    for c in chars.chars() {
+        if c.is_control() || existing.contains_key(&c) || !seen.insert(c) {
+            continue;
+        }
+        let Some((char_code, key)) = encode(c) else {
+            result.unsupported.push(c);
+            continue;
+        };
+        let source_char = replace.get(&c).copied().unwrap_or(c);
+        if select_font(chain, source_char).is_none() {
+            result.missing.push(c);
+            continue;
+        }
+
+        let glyph_id = metadata
+            .glyphs
+            .keys()
+            .next_back()
+            .map_or(0, |&glyph_id| glyph_id + 1);
+        metadata.glyphs.insert(
+            glyph_id,
+            GlyphMetadata {
+                char_code,
+                code_type,
+                bearing_x: 0,
+                bearing_y: 0,
+                advance: 0,
+            },
         );
-        padding as u8
+        metadata.characters.insert(key, glyph_id);
+        result.added.push(c);
+    }
+
+    result
+}
+
+fn parse_font(font_data: &[u8]) -> std::io::Result<FontRef<'_>> {
+    FontRef::try_from_slice(font_data).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Failed to parse TTF/OTF font: {:?}", e),
+        )
+    })
+}
+
+/// Header values of a font made by [`create_fnt`].
+#[derive(Debug, Clone)]
+pub struct CreateOptions {
+    pub version: FntVersion,
+    /// Total number of mipmap levels (1-4). V0 fonts always have 1.
+    pub mipmap_level: usize,
+    /// Pixels above the baseline.
+    /// If not specified, taken from the source font at the configured size.
+    pub ascent: Option<u16>,
+    /// Pixels below the baseline.
+    /// If not specified, taken from the source font at the configured size.
+    pub descent: Option<u16>,
+}
+
+/// Builds a new font from a TTF/OTF font alone, with the characters of
+/// [`RebuildConfig::charset`] and [`RebuildConfig::add_chars`].
+///
+/// Unlike [`rebuild_fnt`] there is no original font, so `config.size` is required and the
+/// header values come from `options`. The default glyph is the `.notdef` glyph of the source
+/// font in V1, and the space in V0 (Shift-JIS table entry 0).
+pub fn create_fnt(
+    font_data: &[u8],
+    options: &CreateOptions,
+    config: &RebuildConfig,
+    progress: &ProgressFn,
+) -> std::io::Result<(Fnt, RebuildReport)> {
+    let size = config.size.ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "A font size is required to create a font",
+        )
+    })?;
+    if config.charset.as_ref().is_none_or(Charset::is_empty) && config.add_chars.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "A charset is required to create a font",
+        ));
+    }
+
+    let scaled_font = parse_font(font_data)?.into_scaled(PxScale::from(size));
+    let ascent = options
+        .ascent
+        .unwrap_or_else(|| scaled_font.ascent().round().clamp(0.0, u16::MAX as f32) as u16);
+    let descent = options
+        .descent
+        .unwrap_or_else(|| (-scaled_font.descent()).round().clamp(0.0, u16::MAX as f32) as u16);
+
+    let (code_type, mipmap_level) = match options.version {
+        FntVersion::V0 => (CodeType::Sjis, 1),
+        FntVersion::V1 => (CodeType::Unicode, options.mipmap_level.clamp(1, 4)),
+    };
+    // Table entry 0 is U+0000 in V1 and the space (Shift-JIS 0x20) in V0
+    let char_code = match options.version {
+        FntVersion::V0 => 0x20,
+        FntVersion::V1 => 0,
+    };
+
+    // Only the default glyph, the characters are added by rebuild_fnt
+    let metadata = FntMetadata {
+        version: options.version,
+        mipmap_level,
+        ascent,
+        descent,
+        characters: BTreeMap::from([(0, 0)]),
+        glyphs: BTreeMap::from([(
+            0,
+            GlyphMetadata {
+                char_code,
+                code_type,
+                bearing_x: 0,
+                bearing_y: 0,
+                advance: 0,
+            },
+        )]),
+        mipmaps: None,
     };
+    let template = Fnt::from_processed_glyphs(metadata, BTreeMap::new());
+
+    rebuild_fnt(template, font_data, config, progress)
+}
+
+pub fn rebuild_fnt(
+    mut fnt: Fnt,
+    font_data: &[u8],
+    config: &RebuildConfig,
+    progress: &ProgressFn,
+) -> std::io::Result<(Fnt, RebuildReport)> {
+    let font_size = config.size.unwrap_or_else(|| {
+        (fnt.metadata.ascent as i16 + fnt.metadata.descent as i16).unsigned_abs() as f32
+    });
+
+    let texture_padding = config
+        .texture_padding
+        .unwrap_or_else(|| (1 << fnt.metadata.mipmap_level.saturating_sub(1)).max(2) as u8);
+
+    if config.gamma <= 0.0 || config.contrast < 1.0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Gamma must be above 0 and contrast at least 1, got gamma {} and contrast {}",
+                config.gamma, config.contrast
+            ),
+        ));
+    }
+    if let Some(sdf) = &config.sdf {
+        if sdf.spread <= 0.0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("SDF spread must be above 0, got {}", sdf.spread),
+            ));
+        }
+        // Distances are not coverage, none of these would do what they say
+        let conflict = if fnt.metadata.version == FntVersion::V0 {
+            Some("FNT4 V0 fonts")
+        } else if config.hinting {
+            Some("hinting")
+        } else if config.gamma != 1.0 || config.contrast != 1.0 {
+            Some("gamma or contrast")
+        } else if config
+            .effects
+            .as_ref()
+            .is_some_and(|effects| !effects.is_empty())
+        {
+            Some("effects")
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("SDF glyphs can't be combined with {}", conflict),
+            ));
+        }
+    }
+    if config.resample_gamma <= 0.0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Resample gamma must be above 0, got {}",
+                config.resample_gamma
+            ),
+        ));
+    }
 
     let resolved_config = ResolvedConfig {
-        size: font_size,
         quality: config.quality,
-        texture_padding: texture_padding,
+        resample_filter: config.resample_filter,
+        resample_gamma: config.resample_gamma,
+        mipmaps: config.mipmaps,
+        texture_padding,
         letter_spacing: config.letter_spacing,
+        hinting: config.hinting,
+        gamma: config.gamma,
+        contrast: config.contrast,
         replace: config.replace.clone(),
+        overrides: parse_override_ranges(&config.overrides)?,
+        vertical: config.vertical.clone(),
+        effects: config.effects.clone().filter(|effects| !effects.is_empty()),
+        sdf: config.sdf.clone(),
+    };
+    let vertical_forms = |data: &[u8]| match &config.vertical {
+        Some(vertical) => vertical_forms(data, &vertical.chars)
+            .into_iter()
+            .map(|(c, glyph)| (c, GlyphId(glyph)))
+            .collect(),
+        None => BTreeMap::new(),
     };
 
-    let font_data = std::fs::read(source_font)?;
-    let font = FontRef::try_from_slice(&font_data).map_err(|e| {
-        std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            format!("Failed to parse TTF/OTF font: {:?}", e),
-        )
-    })?;
+    let fallback_data = config
+        .fonts
+        .iter()
+        .map(|source| {
+            fs::read(&source.path).map_err(|e| {
+                std::io::Error::new(e.kind(), format!("{}: {}", source.path.display(), e))
+            })
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    let mut chain = Vec::new();
+    for (source, data) in config.fonts.iter().zip(&fallback_data) {
+        chain.push(ChainFont {
+            font: parse_font(data).map_err(|e| {
+                std::io::Error::new(e.kind(), format!("{}: {}", source.path.display(), e))
+            })?,
+            ranges: source
+                .ranges
+                .iter()
+                .map(|range| parse_range(range))
+                .collect::<std::io::Result<_>>()?,
+            size: source.size.unwrap_or(font_size),
+            baseline_offset: source.baseline_offset,
+            vertical_forms: vertical_forms(data),
+        });
+    }
+    chain.push(ChainFont {
+        font: parse_font(font_data)?,
+        ranges: Vec::new(),
+        size: font_size,
+        baseline_offset: 0,
+        vertical_forms: vertical_forms(font_data),
+    });
+
+    let mut add_chars = config.add_chars.clone();
+    if let Some(charset) = &config.charset {
+        add_chars.extend(charset.load()?);
+        keep_default_glyph_only(&mut fnt);
+    }
+    let added = add_characters(
+        &mut fnt.metadata,
+        &chain,
+        &add_chars,
+        &resolved_config.replace,
+    );
 
-    let mut processed_glyphs = process_glyphs_from_source_font(&fnt, &font, &resolved_config)?;
+    let (mut processed_glyphs, source_glyph_counts) =
+        process_glyphs_from_source_font(&fnt, &chain, &resolved_config, progress)?;
 
-    let mut restored_count = 0;
+    let mut restored_glyphs = Vec::new();
+    let mut unrestored_glyphs = Vec::new();
     for (glyph_id, processed_glyph) in processed_glyphs.iter_mut() {
-        if processed_glyph.actual_width == 0 || processed_glyph.actual_height == 0 {
-            if let Some(original_glyph) = fnt.lazy_glyphs.get(glyph_id) {
-                let compressed_size = if original_glyph.glyph_data.is_compressed {
-                    original_glyph.glyph_data.data.len() as u16
-                } else {
-                    0
-                };
-
-                *processed_glyph = ProcessedGlyph {
-                    glyph_info: fnt.metadata.glyphs[glyph_id].clone(),
-                    actual_width: original_glyph.info.actual_width,
-                    actual_height: original_glyph.info.actual_height,
-                    texture_width: original_glyph.info.texture_width,
-                    texture_height: original_glyph.info.texture_height,
-                    data: original_glyph.glyph_data.data.clone(),
-                    compressed_size,
-                };
-
-                restored_count += 1;
-
-                let code_type = fnt.metadata.glyphs[glyph_id].code_type;
-                let original_code = fnt.metadata.glyphs[glyph_id].char_code;
-                let original_char = match code_type {
-                    CodeType::Unicode => char::from_u32(original_code).unwrap_or(' '),
-                    CodeType::Sjis => decode_sjis_u32(original_code).unwrap_or(' '),
-                };
-
-                match resolved_config.replace.get(&original_char) {
-                    Some(&target_char) => {
-                        println!(
-                            "Restored glyph ID: {} ({:?} 0x{:04X} '{}' -> '{}') from original fnt",
-                            glyph_id, code_type, original_code, original_char, target_char
-                        );
-                    }
-                    None => {
-                        println!(
-                            "Restored glyph ID: {} ({:?} 0x{:04X} '{}') from original fnt",
-                            glyph_id, code_type, original_code, original_char
-                        );
-                    }
-                }
+        if (processed_glyph.actual_width == 0 || processed_glyph.actual_height == 0)
+            && let Some(original_glyph) = fnt.lazy_glyphs.get(glyph_id)
+        {
+            let code_type = fnt.metadata.glyphs[glyph_id].code_type;
+            let char_code = fnt.metadata.glyphs[glyph_id].char_code;
+            let character = match code_type {
+                CodeType::Unicode => char::from_u32(char_code).unwrap_or(' '),
+                CodeType::Sjis => decode_sjis_u32(char_code).unwrap_or(' '),
+            };
+            let restored = RestoredGlyph {
+                glyph_id: *glyph_id,
+                code_type,
+                char_code,
+                character,
+                replaced_with: resolved_config.replace.get(&character).copied(),
+            };
+
+            // A distance field shader would draw a coverage bitmap wrong
+            let has_pixels =
+                original_glyph.info.actual_width > 0 && original_glyph.info.actual_height > 0;
+            if config.sdf.is_some() && has_pixels {
+                unrestored_glyphs.push(restored);
+                continue;
             }
+
+            let compressed_size = if original_glyph.glyph_data.is_compressed {
+                original_glyph.glyph_data.data.len() as u16
+            } else {
+                0
+            };
+
+            *processed_glyph = ProcessedGlyph {
+                glyph_info: fnt.metadata.glyphs[glyph_id],
+                actual_width: original_glyph.info.actual_width,
+                actual_height: original_glyph.info.actual_height,
+                texture_width: original_glyph.info.texture_width,
+                texture_height: original_glyph.info.texture_height,
+                data: original_glyph.glyph_data.data.clone(),
+                compressed_size,
+            };
+            restored_glyphs.push(restored);
         }
     }
 
-    if restored_count > 0 {
-        println!(
-            "Fallback Summary: Restored {} glyphs from original fnt (missing or empty in TTF/OTF).",
-            restored_count
-        );
-    }
+    let report = RebuildReport {
+        size: font_size,
+        texture_padding,
+        glyph_count: processed_glyphs.len(),
+        restored_glyphs,
+        unrestored_glyphs,
+        added_chars: added.added,
+        unsupported_chars: added.unsupported,
+        missing_chars: added.missing,
+        source_glyph_counts,
+    };
 
     let new_fnt = Fnt::from_processed_glyphs(fnt.metadata, processed_glyphs);
 
-    new_fnt.write_fnt(output_fnt)?;
-
-    println!("Successfully rebuilt to {:?}", output_fnt);
-    Ok(())
+    Ok((new_fnt, report))
 }
 
+/// Renders every glyph of the font, and counts the glyphs rendered from each font of the chain.
 fn process_glyphs_from_source_font<F: Font + Sync>(
     fnt: &Fnt,
-    font: &F,
+    chain: &[ChainFont<F>],
     config: &ResolvedConfig,
-) -> std::io::Result<BTreeMap<u32, ProcessedGlyph>> {
+    progress: &ProgressFn,
+) -> std::io::Result<(BTreeMap<u32, ProcessedGlyph>, Vec<usize>)> {
     let metadata = fnt.metadata.clone();
     let mipmap_level = metadata.mipmap_level;
     let mut glyph_ids: Vec<u32> = metadata.glyphs.keys().copied().collect();
@@ -196,70 +726,74 @@ fn process_glyphs_from_source_font<F: Font + Sync>(
     let total = glyph_ids.len();
     let counter = AtomicUsize::new(0);
 
-    println!(
-        "Processing {} glyphs (size={}, quality={}x, letter_spacing={}, texture_padding={})...",
-        total, config.size, config.quality, config.letter_spacing, config.texture_padding
-    );
-
     let results: Vec<_> = glyph_ids
         .par_iter()
         .filter_map(|&glyph_id| {
             let glyph_metadata = metadata.glyphs.get(&glyph_id)?;
-            let lazy_glyph = fnt.lazy_glyphs.get(&glyph_id)?;
+            // Added characters have no original glyph
+            let original_glyph_info = fnt.lazy_glyphs.get(&glyph_id).map(|g| &g.info);
 
             let result = process_single_glyph_from_source_font(
-                font,
+                chain,
                 glyph_metadata,
-                &lazy_glyph.info,
+                original_glyph_info,
                 mipmap_level,
                 config,
                 fnt.metadata.version,
-            );
+            )
+            .map_err(|e| {
+                std::io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Glyph ID {} ({:?} 0x{:04X}): {}",
+                        glyph_id, glyph_metadata.code_type, glyph_metadata.char_code, e
+                    ),
+                )
+            });
 
             let done = counter.fetch_add(1, Ordering::Relaxed) + 1;
-            if done % 100 == 0 || done == total {
-                print!(
-                    "\rProcessing glyphs: {}/{} ({:.1}%)",
-                    done,
-                    total,
-                    done as f64 / total as f64 * 100.0
-                );
-                std::io::stdout().flush().ok();
-            }
+            progress(done, total);
 
-            result.map(|pg| (glyph_id, pg))
+            result
+                .map(|processed| processed.map(|(pg, font_index)| (glyph_id, pg, font_index)))
+                .transpose()
         })
-        .collect();
+        .collect::<std::io::Result<_>>()?;
 
-    println!();
+    let mut source_glyph_counts = vec![0; chain.len()];
+    for (_, _, font_index) in &results {
+        if let Some(font_index) = font_index {
+            source_glyph_counts[*font_index] += 1;
+        }
+    }
+    let processed_glyphs = results
+        .into_iter()
+        .map(|(glyph_id, pg, _)| (glyph_id, pg))
+        .collect();
 
-    Ok(results.into_iter().collect())
+    Ok((processed_glyphs, source_glyph_counts))
 }
 
+/// Renders one glyph from the first font of the chain that has it. Also returns the index of
+/// that font, `None` when no font has the glyph. Fails when the effects or the SDF spread grow
+/// the glyph past the texture size limit.
 fn process_single_glyph_from_source_font<F: Font>(
-    font: &F,
+    chain: &[ChainFont<F>],
     glyph_metadata: &GlyphMetadata,
-    original_glyph_info: &GlyphInfo,
+    original_glyph_info: Option<&GlyphInfo>,
     mipmap_level: usize,
     config: &ResolvedConfig,
     fnt_version: FntVersion,
-) -> Option<ProcessedGlyph> {
+) -> std::io::Result<Option<(ProcessedGlyph, Option<usize>)>> {
     let original_code = glyph_metadata.char_code;
     let code_type = glyph_metadata.code_type;
-    let font_size = config.size;
 
     let char = match code_type {
-        CodeType::Unicode => char::from_u32(original_code)?,
-        CodeType::Sjis => match decode_sjis_u32(original_code) {
-            Some(c) => c,
-            None => {
-                println!(
-                    "Failed to decode SJIS to Unicode: (U+{:04X})",
-                    original_code
-                );
-                char::from_u32(0)?
-            }
-        },
+        CodeType::Unicode => char::from_u32(original_code),
+        CodeType::Sjis => decode_sjis_u32(original_code).or(Some('\0')),
+    };
+    let Some(char) = char else {
+        return Ok(None);
     };
 
     let replaced_char = config.replace.get(&char);
@@ -269,40 +803,93 @@ fn process_single_glyph_from_source_font<F: Font>(
         None => (char, false),
     };
 
-    let rendered = render_glyph_from_source_font(font, target_char, font_size, config.quality);
+    let glyph_override = config.glyph_override(target_char);
+    let font_index = select_font(chain, target_char);
+    // Room left for the glyph once the texture padding is added
+    let max_size = fnt_version
+        .max_texture_size()
+        .saturating_sub(config.texture_padding as usize * 2);
+    let rendered = font_index.map(|index| -> std::io::Result<Option<_>> {
+        let source = &chain[index];
+        let size = glyph_override.size.unwrap_or(source.size);
+        let vertical_form = source.vertical_forms.get(&target_char).copied();
+        let glyph_id = vertical_form.unwrap_or_else(|| source.font.glyph_id(target_char));
+        let rendered = if let Some(sdf) = &config.sdf {
+            let sdf_glyph = render_sdf_glyph(
+                &source.font,
+                glyph_id,
+                size,
+                config.quality,
+                sdf.spread,
+                max_size,
+            )?;
+            Some(sdf_glyph)
+        } else if config.hinting {
+            render_hinted_glyph(&source.font, glyph_id, size)
+        } else {
+            render_glyph_from_source_font(&source.font, glyph_id, size, config)
+        };
+        let Some(mut rendered) = rendered else {
+            return Ok(None);
+        };
+        adjust_alpha(&mut rendered.raw_pixels, config.gamma, config.contrast);
+
+        if let Some(vertical) = &config.vertical {
+            let scaled_font = source.font.as_scaled(PxScale::from(size));
+            if vertical_form.is_none() && vertical.rotate_chars.contains(target_char) {
+                let em_center = (
+                    size / 2.0,
+                    (scaled_font.ascent() + scaled_font.descent()) / 2.0,
+                );
+                rendered = rotate_glyph(&rendered, em_center);
+            }
+            // The step down the column
+            let mut v_advance = scaled_font.v_advance(glyph_id);
+            if v_advance <= 0.0 {
+                v_advance = scaled_font.height();
+            }
+            rendered.advance = v_advance.round().clamp(0.0, 255.0) as u8;
+        }
+        if let Some(effects) = &config.effects {
+            rendered = effects.apply(&rendered, max_size)?;
+        }
+
+        Ok(Some((rendered, source.baseline_offset)))
+    });
+    let rendered = rendered.transpose()?.flatten();
 
     let (bearing_x, bearing_y, advance, actual_width, actual_height, raw_pixels) =
-        if let Some(r) = rendered {
-            (
+        if let Some((r, baseline_offset)) = rendered {
+            let (bearing_x, bearing_y, advance) = glyph_override.apply(
                 r.bearing_x,
-                r.bearing_y,
+                r.bearing_y.saturating_add(baseline_offset),
                 r.advance,
                 r.actual_width,
+            );
+            (
+                bearing_x,
+                bearing_y,
+                advance,
+                r.actual_width,
                 r.actual_height,
                 r.raw_pixels,
             )
         } else {
-            (
-                original_glyph_info.bearing_x,
-                original_glyph_info.bearing_y,
-                original_glyph_info.advance,
-                0u8,
-                0u8,
-                vec![],
-            )
+            let (bearing_x, bearing_y, advance) = original_glyph_info.map_or((0, 0, 0), |info| {
+                (info.bearing_x, info.bearing_y, info.advance)
+            });
+            (bearing_x, bearing_y, advance, 0u8, 0u8, vec![])
         };
 
     if actual_width == 0 || actual_height == 0 {
-        let new_advance = (advance as i16 + config.letter_spacing as i16)
-            .max(0)
-            .min(255) as u8;
+        let new_advance = (advance as i16 + config.letter_spacing as i16).clamp(0, 255) as u8;
 
-        let mut new_metadata = glyph_metadata.clone();
+        let mut new_metadata = *glyph_metadata;
         new_metadata.bearing_x = bearing_x;
         new_metadata.bearing_y = bearing_y;
         new_metadata.advance = new_advance;
 
-        return Some(ProcessedGlyph {
+        let processed = ProcessedGlyph {
             glyph_info: new_metadata,
             actual_width: 0,
             actual_height: 0,
@@ -310,7 +897,8 @@ fn process_single_glyph_from_source_font<F: Font>(
             texture_height: 0,
             data: vec![],
             compressed_size: 0,
-        });
+        };
+        return Ok(Some((processed, font_index)));
     }
 
     let tp = config.texture_padding as usize;
@@ -350,7 +938,7 @@ fn process_single_glyph_from_source_font<F: Font>(
             let new_bearing_y = bearing_y.saturating_add(config.texture_padding as i8);
 
             let calc_advance = advance as i16 + config.letter_spacing as i16;
-            let new_advance = calc_advance.max(0).min(255) as u8;
+            let new_advance = calc_advance.clamp(0, 255) as u8;
 
             (
                 final_w as u8,
@@ -384,7 +972,7 @@ fn process_single_glyph_from_source_font<F: Font>(
             };
 
             let calc_advance = advance as i16 + config.letter_spacing as i16;
-            let new_advance = calc_advance.max(0).min(255) as u8;
+            let new_advance = calc_advance.clamp(0, 255) as u8;
 
             (
                 final_w as u8,
@@ -396,33 +984,30 @@ fn process_single_glyph_from_source_font<F: Font>(
             )
         };
 
-    let mut new_metadata = glyph_metadata.clone();
+    let mut new_metadata = *glyph_metadata;
     new_metadata.bearing_x = final_bearing_x;
     new_metadata.bearing_y = final_bearing_y;
     new_metadata.advance = final_advance;
 
-    create_processed_glyph(
+    Ok(create_processed_glyph(
         &new_metadata,
         final_width,
         final_height,
         &final_data,
         mipmap_level,
         fnt_version,
+        config.mipmaps,
     )
+    .map(|processed| (processed, font_index)))
 }
 
 fn render_glyph_from_source_font<F: Font>(
     font: &F,
-    character: char,
+    glyph_id: GlyphId,
     font_size: f32,
-    quality: u8,
+    config: &ResolvedConfig,
 ) -> Option<RenderedGlyph> {
-    let glyph_id = font.glyph_id(character);
-    if glyph_id.0 == 0 && character != '\0' {
-        return None;
-    }
-
-    let ss = quality.max(1) as f32;
+    let ss = config.quality.max(1) as f32;
     let render_size = font_size * ss;
 
     let scale = PxScale::from(render_size);
@@ -462,12 +1047,24 @@ fn render_glyph_from_source_font<F: Font>(
         let dst_height = ((hi_height as f32 / ss).ceil() as u32).max(1);
 
         let downsampled = if ss > 1.0 {
-            let hi_u8: Vec<u8> = hi_pixels
-                .iter()
-                .map(|&c| (c * 255.0).clamp(0.0, 255.0) as u8)
-                .collect();
-            let down = downsample_lanczos(&hi_u8, hi_width, hi_height, dst_width, dst_height);
-            down.iter().map(|&v| v as f32 / 255.0).collect::<Vec<_>>()
+            // Pad to whole multiples of the quality factor, so every output pixel covers
+            // exactly quality x quality rendered pixels
+            let padded_width = (dst_width as f32 * ss) as usize;
+            let padded_height = (dst_height as f32 * ss) as usize;
+            let mut padded = vec![0.0f32; padded_width * padded_height];
+            for y in 0..hi_height as usize {
+                let row = &hi_pixels[y * hi_width as usize..(y + 1) * hi_width as usize];
+                padded[y * padded_width..y * padded_width + hi_width as usize].copy_from_slice(row);
+            }
+            resample(
+                &padded,
+                padded_width,
+                padded_height,
+                dst_width as usize,
+                dst_height as usize,
+                config.resample_filter,
+                config.resample_gamma,
+            )
         } else {
             hi_pixels
         };
@@ -483,7 +1080,7 @@ fn render_glyph_from_source_font<F: Font>(
         Some(RenderedGlyph {
             bearing_x,
             bearing_y,
-            advance: h_advance.round().max(0.0).min(255.0) as u8,
+            advance: h_advance.round().clamp(0.0, 255.0) as u8,
             actual_width: dst_width.min(255) as u8,
             actual_height: dst_height.min(255) as u8,
             raw_pixels: final_pixels,
@@ -492,7 +1089,7 @@ fn render_glyph_from_source_font<F: Font>(
         Some(RenderedGlyph {
             bearing_x: 0,
             bearing_y: 0,
-            advance: h_advance.round().max(0.0).min(255.0) as u8,
+            advance: h_advance.round().clamp(0.0, 255.0) as u8,
             actual_width: 0,
             actual_height: 0,
             raw_pixels: vec![],
@@ -500,6 +1097,24 @@ fn render_glyph_from_source_font<F: Font>(
     }
 }
 
+/// Applies gamma, then contrast around half coverage, to glyph alpha.
+fn adjust_alpha(pixels: &mut [u8], gamma: f32, contrast: f32) {
+    if gamma == 1.0 && contrast == 1.0 {
+        return;
+    }
+
+    let table: Vec<u8> = (0..=255)
+        .map(|alpha| {
+            let alpha = (alpha as f32 / 255.0).powf(1.0 / gamma);
+            let alpha = (alpha - 0.5) * contrast + 0.5;
+            (alpha.clamp(0.0, 1.0) * 255.0).round() as u8
+        })
+        .collect();
+    for pixel in pixels {
+        *pixel = table[*pixel as usize];
+    }
+}
+
 fn create_processed_glyph(
     glyph_metadata: &GlyphMetadata,
     actual_width: u8,
@@ -507,12 +1122,20 @@ fn create_processed_glyph(
     data: &[u8],
     mipmap_level: usize,
     fnt_version: FntVersion,
+    mipmaps: MipmapOptions,
 ) -> Option<ProcessedGlyph> {
-    let encoded =
-        encode_glyph_texture(data, actual_width, actual_height, mipmap_level, fnt_version);
+    let encoded = encode_glyph_texture_with_mipmaps(
+        data,
+        actual_width,
+        actual_height,
+        mipmap_level,
+        fnt_version,
+        &BTreeMap::new(),
+        mipmaps,
+    );
 
     Some(ProcessedGlyph {
-        glyph_info: glyph_metadata.clone(),
+        glyph_info: *glyph_metadata,
         actual_width,
         actual_height,
         texture_width: encoded.texture_width,
@@ -521,3 +1144,114 @@ fn create_processed_glyph(
         compressed_size: encoded.compressed_size,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glyph_override() {
+        let overrides = [
+            MetricOverride {
+                classes: vec![CharClass::Punctuation],
+                offset_x: Some(2),
+                advance: Some(20),
+                ..MetricOverride::default()
+            },
+            MetricOverride {
+                chars: "。".to_string(),
+                ranges: vec!["U+0030-U+0039".to_string()],
+                monospace: Some(16),
+                ..MetricOverride::default()
+            },
+        ];
+        let resolved = ResolvedConfig {
+            quality: 1,
+            resample_filter: ResampleFilter::default(),
+            resample_gamma: 1.0,
+            mipmaps: MipmapOptions::default(),
+            texture_padding: 0,
+            letter_spacing: 0,
+            hinting: false,
+            gamma: 1.0,
+            contrast: 1.0,
+            replace: BTreeMap::new(),
+            overrides: parse_override_ranges(&overrides).unwrap(),
+            vertical: None,
+            effects: None,
+            sdf: None,
+        };
+
+        assert_eq!(resolved.glyph_override('漢'), GlyphOverride::default());
+        assert_eq!(
+            resolved.glyph_override('、').apply(1, 10, 24, 6),
+            (3, 10, 20)
+        );
+        // Both match, the later override centers the glyph, the offset of the first is kept
+        assert_eq!(
+            resolved.glyph_override('。').apply(1, 10, 24, 6),
+            (7, 10, 16)
+        );
+        assert_eq!(
+            resolved.glyph_override('5').apply(1, 10, 12, 8),
+            (4, 10, 16)
+        );
+    }
+
+    #[test]
+    fn test_sdf_conflicts() {
+        let rebuild = |version: FntVersion, config: &RebuildConfig| {
+            let mut fnt = crate::fnt::build_test_fnt(FntVersion::V1, 1, 12, &[('\0', 4, 6)]);
+            fnt.metadata.version = version;
+            rebuild_fnt(fnt, &[], config, &|_, _| {}).unwrap_err()
+        };
+        let sdf = RebuildConfig {
+            sdf: Some(SdfConfig::default()),
+            ..RebuildConfig::default()
+        };
+
+        let configs = [
+            RebuildConfig {
+                hinting: true,
+                ..sdf.clone()
+            },
+            RebuildConfig {
+                gamma: 1.5,
+                ..sdf.clone()
+            },
+            RebuildConfig {
+                effects: Some(GlyphEffects {
+                    stroke_width: 1.0,
+                    ..GlyphEffects::default()
+                }),
+                ..sdf.clone()
+            },
+        ];
+        for config in &configs {
+            let error = rebuild(FntVersion::V1, config);
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+            assert!(error.to_string().starts_with("SDF glyphs can't"));
+        }
+        let error = rebuild(FntVersion::V0, &sdf);
+        assert!(error.to_string().ends_with("FNT4 V0 fonts"));
+
+        // Without conflicts it gets as far as parsing the missing source font
+        let error = rebuild(FntVersion::V1, &sdf);
+        assert!(!error.to_string().starts_with("SDF glyphs can't"));
+    }
+
+    #[test]
+    fn test_adjust_alpha() {
+        let mut pixels = [0, 64, 128, 192, 255];
+        adjust_alpha(&mut pixels, 1.0, 1.0);
+        assert_eq!(pixels, [0, 64, 128, 192, 255]);
+
+        // Heavier edges, empty and full pixels stay as they are
+        adjust_alpha(&mut pixels, 2.0, 1.0);
+        assert_eq!(pixels, [0, 128, 181, 221, 255]);
+
+        let mut pixels = [0, 64, 128, 192, 255];
+        adjust_alpha(&mut pixels, 1.0, 2.0);
+        assert_eq!(pixels, [0, 1, 129, 255, 255]);
+    }
+}
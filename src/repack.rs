@@ -1,62 +1,112 @@
 use std::collections::BTreeMap;
-use std::io::Write;
 use std::path::Path;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 use image::ImageReader;
 use rayon::prelude::*;
 
-use crate::glyph::{ProcessedGlyph, encode_glyph_texture};
+use crate::extract::glyph_png_filename;
+use crate::glyph::{MipmapImage, ProcessedGlyph, encode_glyph_texture_with_mipmaps};
 use crate::metadata::{FntMetadata, FntVersion, GlyphMetadata};
+use crate::mipmap::MipmapOptions;
+use crate::utils::ProgressFn;
 
-fn process_single_glyph(
+/// Glyph bitmaps loaded from disk, ready to be encoded.
+pub(crate) struct LoadedGlyph {
+    pub glyph_info: GlyphMetadata,
+    pub image: MipmapImage,
+    /// Hand-made lower mipmap levels, the missing ones are generated.
+    pub mipmaps: BTreeMap<u8, MipmapImage>,
+}
+
+pub(crate) fn read_alpha_png(path: &Path) -> Option<MipmapImage> {
+    if !path.exists() {
+        return None;
+    }
+
+    let img = ImageReader::open(path).ok()?.decode().ok()?;
+    let rgba = img.to_rgba8();
+
+    Some(MipmapImage {
+        width: rgba.width(),
+        height: rgba.height(),
+        pixels: rgba.pixels().map(|p| p.0[3]).collect(),
+    })
+}
+
+fn load_glyph_pngs(
     input_dir: &Path,
     glyph_id: u32,
     glyph_info: &GlyphMetadata,
-    fnt_version: FntVersion,
     mipmap_level: usize,
-) -> Option<(u32, ProcessedGlyph)> {
-    let png_filename = format!("{:04}_{:04x}_0.png", glyph_id, glyph_info.char_code);
-    let png_path = input_dir.join(&png_filename);
+    supplied: &[u8],
+) -> Option<LoadedGlyph> {
+    let png_path = input_dir.join(glyph_png_filename(glyph_id, glyph_info.char_code, 0));
+    let image = read_alpha_png(&png_path)?;
 
-    if !png_path.exists() {
-        return None;
-    }
+    let mipmaps = supplied
+        .iter()
+        .copied()
+        .filter(|&level| level >= 1 && (level as usize) < mipmap_level.min(4))
+        .filter_map(|level| {
+            let path = input_dir.join(glyph_png_filename(glyph_id, glyph_info.char_code, level));
+            read_alpha_png(&path).map(|image| (level, image))
+        })
+        .collect();
 
-    let img = ImageReader::open(&png_path).ok()?.decode().ok()?;
-    let rgba = img.to_rgba8();
-    let actual_width = rgba.width() as u8;
-    let actual_height = rgba.height() as u8;
+    Some(LoadedGlyph {
+        glyph_info: *glyph_info,
+        image,
+        mipmaps,
+    })
+}
 
-    let raw_pixels: Vec<u8> = rgba.pixels().map(|p| p.0[3]).collect();
+fn encode_loaded_glyph(
+    loaded: LoadedGlyph,
+    fnt_version: FntVersion,
+    mipmap_level: usize,
+    mipmaps: MipmapOptions,
+) -> ProcessedGlyph {
+    let actual_width = loaded.image.width as u8;
+    let actual_height = loaded.image.height as u8;
 
-    let encoded = encode_glyph_texture(
-        &raw_pixels,
+    let encoded = encode_glyph_texture_with_mipmaps(
+        &loaded.image.pixels,
         actual_width,
         actual_height,
         mipmap_level,
         fnt_version,
+        &loaded.mipmaps,
+        mipmaps,
     );
 
-    Some((
-        glyph_id,
-        ProcessedGlyph {
-            glyph_info: glyph_info.clone(),
-            actual_width,
-            actual_height,
-            texture_width: encoded.texture_width,
-            texture_height: encoded.texture_height,
-            data: encoded.data,
-            compressed_size: encoded.compressed_size,
-        },
-    ))
+    ProcessedGlyph {
+        glyph_info: loaded.glyph_info,
+        actual_width,
+        actual_height,
+        texture_width: encoded.texture_width,
+        texture_height: encoded.texture_height,
+        data: encoded.data,
+        compressed_size: encoded.compressed_size,
+    }
 }
 
-pub fn process_glyphs(
-    input_dir: &Path,
+/// Loads and encodes every glyph in `metadata` in parallel.
+/// Glyphs for which `load` returns `None` are left out.
+pub(crate) fn process_loaded_glyphs<L>(
     metadata: &FntMetadata,
-) -> std::io::Result<BTreeMap<u32, ProcessedGlyph>> {
+    progress: &ProgressFn,
+    load: L,
+) -> BTreeMap<u32, ProcessedGlyph>
+where
+    L: Fn(u32, &GlyphMetadata) -> Option<LoadedGlyph> + Sync,
+{
     let mipmap_level = metadata.mipmap_level;
+    let mipmaps = metadata
+        .mipmaps
+        .as_ref()
+        .map(|mipmaps| mipmaps.options)
+        .unwrap_or_default();
     let mut glyph_ids: Vec<u32> = metadata.glyphs.keys().copied().collect();
     glyph_ids.sort();
 
@@ -67,31 +117,91 @@ pub fn process_glyphs(
         .par_iter()
         .filter_map(|&glyph_id| {
             let glyph_info = metadata.glyphs.get(&glyph_id)?;
-            let result = process_single_glyph(
-                input_dir,
-                glyph_id,
-                glyph_info,
-                metadata.version,
-                mipmap_level,
-            );
+            let result = load(glyph_id, glyph_info).map(|loaded| {
+                (
+                    glyph_id,
+                    encode_loaded_glyph(loaded, metadata.version, mipmap_level, mipmaps),
+                )
+            });
 
             let done = counter.fetch_add(1, Ordering::Relaxed) + 1;
-            if done % 100 == 0 || done == total {
-                print!(
-                    "\rProcessing glyphs: {}/{} ({:.1}%)",
-                    done,
-                    total,
-                    done as f64 / total as f64 * 100.0
-                );
-                std::io::stdout().flush().ok();
-            }
+            progress(done, total);
 
             result
         })
         .collect();
 
-    println!();
+    results.into_iter().collect()
+}
 
-    let processed_glyphs: BTreeMap<u32, ProcessedGlyph> = results.into_iter().collect();
-    Ok(processed_glyphs)
+/// Loads the glyph PNGs written by `extract`. Lower mipmap levels are generated from
+/// `_0.png`, except the levels listed in `[mipmaps] supplied` of the metadata.
+pub fn process_glyphs(
+    input_dir: &Path,
+    metadata: &FntMetadata,
+    progress: &ProgressFn,
+) -> std::io::Result<BTreeMap<u32, ProcessedGlyph>> {
+    let supplied = metadata
+        .mipmaps
+        .as_ref()
+        .map(|mipmaps| mipmaps.supplied.as_slice())
+        .unwrap_or_default();
+
+    Ok(process_loaded_glyphs(
+        metadata,
+        progress,
+        |glyph_id, glyph_info| {
+            load_glyph_pngs(
+                input_dir,
+                glyph_id,
+                glyph_info,
+                metadata.mipmap_level,
+                supplied,
+            )
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::extract_fnt;
+    use crate::fnt::{Fnt, build_test_fnt};
+    use crate::metadata::MipmapMetadata;
+
+    #[test]
+    fn test_mipmaps_follow_edited_level0() {
+        let fnt = build_test_fnt(FntVersion::V1, 3, 8, &[('A', 8, 8)]);
+        let mut metadata = fnt.metadata.clone();
+
+        let dir = std::env::temp_dir().join(format!("fnt4_repack_mipmaps_{}", std::process::id()));
+        extract_fnt(&fnt, &dir, &|_, _| {}).unwrap();
+        assert!(dir.join(glyph_png_filename(0, 0x41, 1)).exists());
+
+        // Only level 0 is cleared, the extracted level 1 PNG is left as it was
+        image::RgbaImage::new(8, 10)
+            .save(dir.join(glyph_png_filename(0, 0x41, 0)))
+            .unwrap();
+
+        let repack = |metadata: &FntMetadata| {
+            let processed = process_glyphs(&dir, metadata, &|_, _| {}).unwrap();
+            let fnt = Fnt::from_processed_glyphs(metadata.clone(), processed);
+            fnt.decode_glyphs(&|_, _| {}).unwrap().remove(&0).unwrap()
+        };
+
+        let glyph = repack(&metadata);
+        assert_eq!(glyph.mipmap[&0][0], 0);
+        assert_eq!(glyph.mipmap[&1][0], 0);
+        assert_eq!(glyph.mipmap[&2][0], 0);
+
+        metadata.mipmaps = Some(MipmapMetadata {
+            supplied: vec![1],
+            ..Default::default()
+        });
+        let glyph = repack(&metadata);
+        assert_eq!(glyph.mipmap[&1][0], 0xFF);
+        assert_eq!(glyph.mipmap[&2][0], 0xFF);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,246 @@
+use serde::{Deserialize, Serialize};
+
+/// Filter used to scale glyph coverage, when downsampling supersampled glyphs and when
+/// generating mipmaps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResampleFilter {
+    /// Average of the covered pixels. Sharp, but aliases when the scale isn't a whole number.
+    Box,
+    /// Triangle filter.
+    Bilinear,
+    /// Mitchell-Netravali cubic (B = C = 1/3). Soft, without ringing.
+    Mitchell,
+    /// Lanczos windowed sinc, 2 lobes.
+    Lanczos2,
+    /// Lanczos windowed sinc, 3 lobes.
+    #[default]
+    Lanczos3,
+    /// Lanczos windowed sinc, 4 lobes. Sharpest, with the most ringing.
+    Lanczos4,
+    /// Kaiser windowed sinc (beta 4), 3 lobes.
+    Kaiser,
+}
+
+impl ResampleFilter {
+    pub const ALL: [ResampleFilter; 7] = [
+        ResampleFilter::Box,
+        ResampleFilter::Bilinear,
+        ResampleFilter::Mitchell,
+        ResampleFilter::Lanczos2,
+        ResampleFilter::Lanczos3,
+        ResampleFilter::Lanczos4,
+        ResampleFilter::Kaiser,
+    ];
+
+    /// The name used in configs and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            ResampleFilter::Box => "box",
+            ResampleFilter::Bilinear => "bilinear",
+            ResampleFilter::Mitchell => "mitchell",
+            ResampleFilter::Lanczos2 => "lanczos2",
+            ResampleFilter::Lanczos3 => "lanczos3",
+            ResampleFilter::Lanczos4 => "lanczos4",
+            ResampleFilter::Kaiser => "kaiser",
+        }
+    }
+
+    /// Radius of the filter in source pixels, at a scale of 1.
+    fn support(self) -> f32 {
+        match self {
+            ResampleFilter::Box => 0.5,
+            ResampleFilter::Bilinear => 1.0,
+            ResampleFilter::Mitchell | ResampleFilter::Lanczos2 => 2.0,
+            ResampleFilter::Lanczos3 | ResampleFilter::Kaiser => 3.0,
+            ResampleFilter::Lanczos4 => 4.0,
+        }
+    }
+
+    fn weight(self, x: f32) -> f32 {
+        let x_abs = x.abs();
+        match self {
+            ResampleFilter::Box => {
+                if (-0.5..0.5).contains(&x) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            ResampleFilter::Bilinear => (1.0 - x_abs).max(0.0),
+            ResampleFilter::Mitchell => {
+                let (x2, x3) = (x_abs * x_abs, x_abs * x_abs * x_abs);
+                if x_abs < 1.0 {
+                    (7.0 * x3 - 12.0 * x2 + 16.0 / 3.0) / 6.0
+                } else if x_abs < 2.0 {
+                    (-7.0 / 3.0 * x3 + 12.0 * x2 - 20.0 * x_abs + 32.0 / 3.0) / 6.0
+                } else {
+                    0.0
+                }
+            }
+            ResampleFilter::Lanczos2 | ResampleFilter::Lanczos3 | ResampleFilter::Lanczos4 => {
+                let a = self.support();
+                if x_abs < a {
+                    sinc(x) * sinc(x / a)
+                } else {
+                    0.0
+                }
+            }
+            ResampleFilter::Kaiser => {
+                const BETA: f32 = 4.0;
+                let a = self.support();
+                if x_abs < a {
+                    let t = x / a;
+                    sinc(x) * bessel_i0(BETA * (1.0 - t * t).sqrt()) / bessel_i0(BETA)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else {
+        let pi_x = std::f32::consts::PI * x;
+        pi_x.sin() / pi_x
+    }
+}
+
+/// Modified Bessel function of the first kind, order 0.
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_x2 = x * x / 4.0;
+    for k in 1..20 {
+        term *= half_x2 / (k * k) as f32;
+        sum += term;
+    }
+    sum
+}
+
+/// Source pixels and their weights for each destination pixel along one axis. Pixels outside
+/// the image count as empty, so weights are normalized over the whole filter.
+fn axis_weights(src_len: usize, dst_len: usize, filter: ResampleFilter) -> Vec<(usize, Vec<f32>)> {
+    let scale = src_len as f32 / dst_len as f32;
+    // Widen the filter when downsampling so every source pixel contributes
+    let filter_scale = scale.max(1.0);
+    let support = filter.support() * filter_scale;
+
+    (0..dst_len)
+        .map(|d| {
+            let center = (d as f32 + 0.5) * scale;
+            let start = (center - support).floor() as isize;
+            let end = (center + support).ceil() as isize;
+            let weights: Vec<(isize, f32)> = (start..end)
+                .map(|s| (s, filter.weight((s as f32 + 0.5 - center) / filter_scale)))
+                .collect();
+            let sum: f32 = weights.iter().map(|&(_, w)| w).sum();
+
+            let first = start.max(0) as usize;
+            let in_range = weights
+                .iter()
+                .filter(|&&(s, _)| s >= 0 && (s as usize) < src_len)
+                .map(|&(_, w)| if sum != 0.0 { w / sum } else { 0.0 })
+                .collect();
+            (first, in_range)
+        })
+        .collect()
+}
+
+/// Scales coverage in `0.0..=1.0` with `filter`, separably.
+///
+/// With a `gamma` other than 1.0, values are decoded with it before filtering and encoded
+/// again after, so the filter averages `value^gamma` instead of the values themselves.
+pub fn resample(
+    src: &[f32],
+    src_width: usize,
+    src_height: usize,
+    dst_width: usize,
+    dst_height: usize,
+    filter: ResampleFilter,
+    gamma: f32,
+) -> Vec<f32> {
+    if src_width == 0 || src_height == 0 || dst_width == 0 || dst_height == 0 {
+        return vec![0.0; dst_width * dst_height];
+    }
+
+    let decoded: Vec<f32>;
+    let src = if gamma != 1.0 {
+        decoded = src.iter().map(|v| v.max(0.0).powf(gamma)).collect();
+        &decoded
+    } else {
+        src
+    };
+
+    let x_weights = axis_weights(src_width, dst_width, filter);
+    let mut horizontal = vec![0.0f32; dst_width * src_height];
+    for y in 0..src_height {
+        let row = &src[y * src_width..(y + 1) * src_width];
+        for (x, (first, weights)) in x_weights.iter().enumerate() {
+            horizontal[y * dst_width + x] = weights
+                .iter()
+                .enumerate()
+                .map(|(i, w)| row[first + i] * w)
+                .sum();
+        }
+    }
+
+    let y_weights = axis_weights(src_height, dst_height, filter);
+    let mut dst = vec![0.0f32; dst_width * dst_height];
+    for (y, (first, weights)) in y_weights.iter().enumerate() {
+        for x in 0..dst_width {
+            let value: f32 = weights
+                .iter()
+                .enumerate()
+                .map(|(i, w)| horizontal[(first + i) * dst_width + x] * w)
+                .sum();
+            dst[y * dst_width + x] = value.clamp(0.0, 1.0);
+        }
+    }
+
+    if gamma != 1.0 {
+        dst.iter_mut().for_each(|v| *v = v.powf(1.0 / gamma));
+    }
+    dst
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resample() {
+        // Box halving averages 2x2 blocks
+        let src = [0.0, 1.0, 0.5, 0.5, 1.0, 0.0, 0.5, 0.5];
+        let dst = resample(&src, 4, 2, 2, 1, ResampleFilter::Box, 1.0);
+        assert_eq!(dst, [0.5, 0.5]);
+
+        // Interpolating filters keep an image at the same size
+        let src: Vec<f32> = (0..16).map(|i| (i % 5) as f32 / 4.0).collect();
+        for filter in [
+            ResampleFilter::Box,
+            ResampleFilter::Bilinear,
+            ResampleFilter::Lanczos3,
+            ResampleFilter::Kaiser,
+        ] {
+            let dst = resample(&src, 4, 4, 4, 4, filter, 1.0);
+            for (a, b) in src.iter().zip(&dst) {
+                assert!((a - b).abs() < 1e-4, "{:?}: {} != {}", filter, a, b);
+            }
+        }
+
+        // Full coverage away from the edges stays full with every filter
+        let src = vec![1.0; 36 * 36];
+        for filter in ResampleFilter::ALL {
+            let dst = resample(&src, 36, 36, 12, 12, filter, 1.0);
+            assert!((dst[6 * 12 + 5] - 1.0).abs() < 1e-4, "{:?}", filter);
+        }
+
+        // Gamma averages the decoded values
+        let dst = resample(&[0.0, 1.0], 2, 1, 1, 1, ResampleFilter::Box, 2.0);
+        assert!((dst[0] - 0.5f32.sqrt()).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,315 @@
+use std::io;
+
+use ab_glyph::{Font, GlyphId, PxScale, ScaleFont};
+use serde::{Deserialize, Serialize};
+
+use crate::glyph::RenderedGlyph;
+
+fn default_spread() -> f32 {
+    4.0
+}
+
+/// Glyphs encoded as signed distance fields, for shaders that threshold the alpha.
+///
+/// The alpha is 128 on the outline, rising to 255 `spread` pixels inside the glyph and falling
+/// to 0 `spread` pixels outside it. Glyph textures grow by the spread on every side, and the
+/// bearings move so the glyph stays in place.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SdfConfig {
+    /// Distance in pixels (at the font size) covered by the alpha range on each side of the
+    /// outline.
+    #[serde(default = "default_spread")]
+    pub spread: f32,
+}
+
+impl Default for SdfConfig {
+    fn default() -> Self {
+        SdfConfig {
+            spread: default_spread(),
+        }
+    }
+}
+
+/// Renders a glyph at `quality` times the font size and computes its signed distance field
+/// from the supersampled outline.
+///
+/// Fails when the glyph padded by the spread is wider or higher than `max_size` pixels, or
+/// its bearings no longer fit in a byte.
+pub fn render_sdf_glyph<F: Font>(
+    font: &F,
+    glyph_id: GlyphId,
+    font_size: f32,
+    quality: u8,
+    spread: f32,
+    max_size: usize,
+) -> io::Result<RenderedGlyph> {
+    let ss = quality.max(1) as usize;
+    let advance = font
+        .as_scaled(PxScale::from(font_size))
+        .h_advance(glyph_id)
+        .round()
+        .clamp(0.0, 255.0) as u8;
+    let empty = RenderedGlyph {
+        bearing_x: 0,
+        bearing_y: 0,
+        advance,
+        actual_width: 0,
+        actual_height: 0,
+        raw_pixels: vec![],
+    };
+
+    let glyph = glyph_id.with_scale(PxScale::from(font_size * ss as f32));
+    let Some(outlined) = font.outline_glyph(glyph) else {
+        return Ok(empty);
+    };
+    let bounds = outlined.px_bounds();
+    let (hi_width, hi_height) = (bounds.width() as usize, bounds.height() as usize);
+    if hi_width == 0 || hi_height == 0 {
+        return Ok(empty);
+    }
+
+    // Room for the spread on every side, in whole output pixels
+    let pad = spread.ceil() as usize;
+    let width = hi_width.div_ceil(ss) + 2 * pad;
+    let height = hi_height.div_ceil(ss) + 2 * pad;
+    if width > max_size || height > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the SDF spread grows the glyph to {}x{} pixels, above the limit of {}",
+                width, height, max_size
+            ),
+        ));
+    }
+    let bearing_x = (bounds.min.x / ss as f32).round() as isize - pad as isize;
+    let bearing_y = (-bounds.min.y / ss as f32).round() as isize + pad as isize;
+    let (Ok(bearing_x), Ok(bearing_y)) = (i8::try_from(bearing_x), i8::try_from(bearing_y)) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "the SDF spread moves the glyph bearings to ({}, {}), outside -128 to 127",
+                bearing_x, bearing_y
+            ),
+        ));
+    };
+    let (canvas_width, canvas_height) = (width * ss, height * ss);
+
+    let mut inside = vec![false; canvas_width * canvas_height];
+    outlined.draw(|x, y, coverage| {
+        let (x, y) = (x as usize + pad * ss, y as usize + pad * ss);
+        if x < canvas_width && y < canvas_height && coverage >= 0.5 {
+            inside[y * canvas_width + x] = true;
+        }
+    });
+
+    let to_inside = squared_distances(&inside, canvas_width, canvas_height, true);
+    let to_outside = squared_distances(&inside, canvas_width, canvas_height, false);
+    // The outline runs between pixel centers, half a pixel from either side
+    let signed: Vec<f32> = (0..inside.len())
+        .map(|i| {
+            if inside[i] {
+                to_outside[i].sqrt() - 0.5
+            } else {
+                0.5 - to_inside[i].sqrt()
+            }
+        })
+        .collect();
+
+    let mut raw_pixels = vec![0u8; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            for sy in y * ss..(y + 1) * ss {
+                sum += signed[sy * canvas_width + x * ss..sy * canvas_width + (x + 1) * ss]
+                    .iter()
+                    .sum::<f32>();
+            }
+            let distance = sum / (ss * ss) as f32 / ss as f32;
+            let value = 0.5 + distance / (2.0 * spread);
+            raw_pixels[y * width + x] = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        }
+    }
+
+    Ok(RenderedGlyph {
+        bearing_x,
+        bearing_y,
+        advance,
+        actual_width: width as u8,
+        actual_height: height as u8,
+        raw_pixels,
+    })
+}
+
+/// Squared distance from every pixel to the nearest pixel whose `inside` equals `target`,
+/// with the exact Euclidean distance transform of Felzenszwalb and Huttenlocher.
+fn squared_distances(inside: &[bool], width: usize, height: usize, target: bool) -> Vec<f32> {
+    let far = ((width * width + height * height) * 4) as f32;
+    let mut grid: Vec<f32> = inside
+        .iter()
+        .map(|&pixel| if pixel == target { 0.0 } else { far })
+        .collect();
+
+    let mut column = vec![0.0f32; height];
+    for x in 0..width {
+        for y in 0..height {
+            column[y] = grid[y * width + x];
+        }
+        let transformed = distance_transform_1d(&column);
+        for y in 0..height {
+            grid[y * width + x] = transformed[y];
+        }
+    }
+    for y in 0..height {
+        let transformed = distance_transform_1d(&grid[y * width..(y + 1) * width]);
+        grid[y * width..(y + 1) * width].copy_from_slice(&transformed);
+    }
+
+    grid
+}
+
+/// Lower envelope of the parabolas rooted at each sample.
+fn distance_transform_1d(f: &[f32]) -> Vec<f32> {
+    let n = f.len();
+    let mut d = vec![0.0f32; n];
+    // Roots of the parabolas of the envelope, and where each starts
+    let mut v = vec![0usize; n];
+    let mut z = vec![0.0f32; n + 1];
+    let mut k = 0;
+    z[0] = f32::NEG_INFINITY;
+    z[1] = f32::INFINITY;
+
+    let intersection = |q: usize, p: usize| {
+        ((f[q] + (q * q) as f32) - (f[p] + (p * p) as f32)) / (2.0 * q as f32 - 2.0 * p as f32)
+    };
+    for q in 1..n {
+        let mut s = intersection(q, v[k]);
+        while s <= z[k] {
+            k -= 1;
+            s = intersection(q, v[k]);
+        }
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = f32::INFINITY;
+    }
+
+    k = 0;
+    for (q, distance) in d.iter_mut().enumerate() {
+        while z[k + 1] < q as f32 {
+            k += 1;
+        }
+        let offset = q as f32 - v[k] as f32;
+        *distance = offset * offset + f[v[k]];
+    }
+    d
+}
+
+#[cfg(test)]
+mod tests {
+    use ab_glyph::{Outline, OutlineCurve, Rect, point, v2};
+
+    use super::*;
+
+    /// A font whose only glyph is a square from (100, 0) to (600, 500) in a 1000 unit em.
+    struct SquareFont;
+
+    impl Font for SquareFont {
+        fn units_per_em(&self) -> Option<f32> {
+            Some(1000.0)
+        }
+        fn ascent_unscaled(&self) -> f32 {
+            800.0
+        }
+        fn descent_unscaled(&self) -> f32 {
+            -200.0
+        }
+        fn line_gap_unscaled(&self) -> f32 {
+            0.0
+        }
+        fn glyph_id(&self, _: char) -> GlyphId {
+            GlyphId(0)
+        }
+        fn h_advance_unscaled(&self, _: GlyphId) -> f32 {
+            700.0
+        }
+        fn h_side_bearing_unscaled(&self, _: GlyphId) -> f32 {
+            100.0
+        }
+        fn v_advance_unscaled(&self, _: GlyphId) -> f32 {
+            1000.0
+        }
+        fn v_side_bearing_unscaled(&self, _: GlyphId) -> f32 {
+            0.0
+        }
+        fn kern_unscaled(&self, _: GlyphId, _: GlyphId) -> f32 {
+            0.0
+        }
+        fn outline(&self, _: GlyphId) -> Option<Outline> {
+            let corners = [
+                point(100.0, 0.0),
+                point(600.0, 0.0),
+                point(600.0, 500.0),
+                point(100.0, 500.0),
+            ];
+            Some(Outline {
+                // Outline bounds run from the top left to the bottom right
+                bounds: Rect {
+                    min: corners[3],
+                    max: corners[1],
+                },
+                curves: (0..4)
+                    .map(|i| OutlineCurve::Line(corners[i], corners[(i + 1) % 4]))
+                    .collect(),
+            })
+        }
+        fn glyph_count(&self) -> usize {
+            1
+        }
+        fn codepoint_ids(&self) -> ab_glyph::CodepointIdIter<'_> {
+            unimplemented!()
+        }
+        fn glyph_raster_image2(&self, _: GlyphId, _: u16) -> Option<v2::GlyphImage<'_>> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_render_sdf_glyph() {
+        // The square is 10x10 pixels at size 20, 2 pixels right of the origin
+        let glyph = render_sdf_glyph(&SquareFont, GlyphId(0), 20.0, 2, 2.0, 128).unwrap();
+
+        assert_eq!((glyph.actual_width, glyph.actual_height), (14, 14));
+        assert_eq!((glyph.bearing_x, glyph.bearing_y), (0, 12));
+        assert_eq!(glyph.advance, 14);
+
+        // Across the middle row: the padding, either side of the left edge, the center
+        let row = &glyph.raw_pixels[7 * 14..8 * 14];
+        assert!(row[0] < row[1]);
+        assert!(row[1] < 128 && row[2] > 128);
+        assert_eq!(row[7], 255);
+        assert_eq!(row, row.iter().rev().copied().collect::<Vec<_>>());
+        // The outline is half way between the pixels on either side of it
+        assert!((row[1] as i32 + row[2] as i32 - 255).abs() <= 2);
+
+        let error = render_sdf_glyph(&SquareFont, GlyphId(0), 20.0, 1, 60.0, 128).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_squared_distances() {
+        // A single inside pixel in the middle of a 5x3 image
+        let mut inside = vec![false; 15];
+        inside[7] = true;
+
+        let to_inside = squared_distances(&inside, 5, 3, true);
+        assert_eq!(
+            to_inside,
+            [
+                5.0, 2.0, 1.0, 2.0, 5.0, 4.0, 1.0, 0.0, 1.0, 4.0, 5.0, 2.0, 1.0, 2.0, 5.0
+            ]
+        );
+        let to_outside = squared_distances(&inside, 5, 3, false);
+        assert_eq!(to_outside[7], 1.0);
+        assert_eq!(to_outside[0], 0.0);
+    }
+}
@@ -0,0 +1,703 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use image::ImageEncoder;
+
+use crate::error::FntError;
+use crate::fnt::Fnt;
+use crate::glyph::Glyph;
+use crate::utils::ProgressFn;
+
+/// Font units per bitmap pixel, so all outline metrics are whole pixels.
+const UNITS_PER_PIXEL: i32 = 16;
+
+#[derive(Debug, Clone)]
+pub struct TtfExportReport {
+    pub glyph_count: usize,
+    /// Number of Unicode characters mapped to a glyph.
+    pub character_count: usize,
+    /// Pixels per em of the bitmap strike, ascent + descent.
+    pub ppem: u32,
+}
+
+/// Writes the font as an OpenType font with the glyph bitmaps embedded as PNG (CBDT/CBLC tables).
+///
+/// See [`build_ttf`].
+pub fn export_ttf(
+    fnt: &Fnt,
+    output: &Path,
+    family_name: &str,
+    progress: &ProgressFn,
+) -> Result<TtfExportReport, FntError> {
+    let (data, report) = build_ttf(fnt, family_name, progress)?;
+
+    std::fs::create_dir_all(output.parent().unwrap_or(Path::new("")))?;
+    std::fs::write(output, data)?;
+
+    Ok(report)
+}
+
+/// Builds an OpenType font with one bitmap strike holding the level 0 bitmap of every glyph.
+///
+/// The strike is `ascent + descent` pixels per em, so text set at that pixel size looks exactly
+/// like in game. Characters are mapped to Unicode (V0 through their Shift-JIS code). Characters
+/// that fall back to the default glyph (the glyph of character 0) are left out, so text editors
+/// show them in a fallback font. The empty `glyf` outlines only exist to keep TrueType parsers happy.
+pub fn build_ttf(
+    fnt: &Fnt,
+    family_name: &str,
+    progress: &ProgressFn,
+) -> Result<(Vec<u8>, TtfExportReport), FntError> {
+    let metadata = &fnt.metadata;
+    let ppem = metadata.ascent as u32 + metadata.descent as u32;
+    if !(1..=255).contains(&ppem) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "ascent + descent must be between 1 and 255 pixels, got {}",
+                ppem
+            ),
+        )
+        .into());
+    }
+
+    let glyphs = fnt.decode_glyphs(progress)?;
+
+    // Glyph 0 is .notdef, FNT4 glyphs follow in id order
+    let glyph_index: BTreeMap<u32, u16> = glyphs
+        .keys()
+        .enumerate()
+        .map(|(i, &glyph_id)| (glyph_id, i as u16 + 1))
+        .collect();
+    let num_glyphs = glyphs.len() as u16 + 1;
+
+    let cmap: BTreeMap<u32, u16> = metadata
+        .mapped_characters()
+        .into_iter()
+        .filter(|&(c, _)| !c.is_control())
+        .filter_map(|(c, glyph_id)| Some((c as u32, *glyph_index.get(&glyph_id)?)))
+        .collect();
+
+    let metrics = FontMetrics::new(metadata.ascent, metadata.descent, glyphs.values());
+
+    let mut tables: Vec<([u8; 4], Vec<u8>)> = vec![
+        (*b"CBDT", Vec::new()),
+        (*b"CBLC", Vec::new()),
+        (*b"OS/2", build_os2(&metrics, &cmap)),
+        (*b"cmap", build_cmap(&cmap)),
+        (*b"glyf", Vec::new()),
+        (*b"head", build_head(&metrics)),
+        (*b"hhea", build_hhea(&metrics, glyphs.values(), num_glyphs)),
+        (*b"hmtx", build_hmtx(&metrics, glyphs.values())),
+        (*b"loca", vec![0; (num_glyphs as usize + 1) * 2]),
+        (*b"maxp", build_maxp(num_glyphs)),
+        (*b"name", build_name(family_name)),
+        (*b"post", build_post(&metrics)),
+    ];
+
+    let (cbdt, cblc) = build_bitmaps(&metrics, glyphs.values(), ppem as u8)?;
+    tables[0].1 = cbdt;
+    tables[1].1 = cblc;
+
+    let report = TtfExportReport {
+        glyph_count: glyphs.len(),
+        character_count: cmap.len(),
+        ppem,
+    };
+
+    Ok((write_sfnt(tables), report))
+}
+
+/// Font-wide values in font units.
+struct FontMetrics {
+    units_per_em: u16,
+    ascender: i16,
+    descender: i16,
+    x_min: i16,
+    y_min: i16,
+    x_max: i16,
+    y_max: i16,
+}
+
+impl FontMetrics {
+    fn new<'a>(ascent: u16, descent: u16, glyphs: impl Iterator<Item = &'a Glyph>) -> Self {
+        let (mut x_min, mut y_min, mut x_max, mut y_max) = (0i32, 0i32, 0i32, 0i32);
+
+        for glyph in glyphs {
+            let (width, height) = glyph.mipmap_size(0);
+            let left = glyph.info.bearing_x as i32;
+            let top = glyph.info.bearing_y as i32;
+            x_min = x_min.min(left);
+            x_max = x_max.max(left + width as i32);
+            y_min = y_min.min(top - height as i32);
+            y_max = y_max.max(top);
+        }
+
+        FontMetrics {
+            units_per_em: ((ascent as i32 + descent as i32) * UNITS_PER_PIXEL) as u16,
+            ascender: units(ascent as i32),
+            descender: -units(descent as i32),
+            x_min: units(x_min),
+            y_min: units(y_min),
+            x_max: units(x_max),
+            y_max: units(y_max),
+        }
+    }
+}
+
+fn units(pixels: i32) -> i16 {
+    (pixels * UNITS_PER_PIXEL) as i16
+}
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_i16(data: &mut Vec<u8>, value: i16) {
+    data.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_be_bytes());
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+/// Lays out the table directory and tables, and fills in `head.checksumAdjustment`.
+fn write_sfnt(mut tables: Vec<([u8; 4], Vec<u8>)>) -> Vec<u8> {
+    tables.sort_by_key(|(tag, _)| *tag);
+
+    let num_tables = tables.len() as u16;
+    let entry_selector = 15 - num_tables.leading_zeros() as u16;
+    let search_range = (1u16 << entry_selector) * 16;
+
+    let mut data = Vec::new();
+    push_u32(&mut data, 0x00010000);
+    push_u16(&mut data, num_tables);
+    push_u16(&mut data, search_range);
+    push_u16(&mut data, entry_selector);
+    push_u16(&mut data, num_tables * 16 - search_range);
+
+    let mut offset = 12 + tables.len() * 16;
+    let mut head_offset = 0;
+    for (tag, table) in &tables {
+        if tag == b"head" {
+            head_offset = offset;
+        }
+        data.extend_from_slice(tag);
+        push_u32(&mut data, checksum(table));
+        push_u32(&mut data, offset as u32);
+        push_u32(&mut data, table.len() as u32);
+        offset += table.len().next_multiple_of(4);
+    }
+
+    for (_, table) in &tables {
+        data.extend_from_slice(table);
+        data.resize(data.len().next_multiple_of(4), 0);
+    }
+
+    let adjustment = 0xB1B0AFBAu32.wrapping_sub(checksum(&data));
+    data[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+
+    data
+}
+
+fn build_head(metrics: &FontMetrics) -> Vec<u8> {
+    let mut data = Vec::with_capacity(54);
+    push_u32(&mut data, 0x00010000); // version
+    push_u32(&mut data, 0x00010000); // fontRevision
+    push_u32(&mut data, 0); // checksumAdjustment, filled in by write_sfnt
+    push_u32(&mut data, 0x5F0F3CF5); // magicNumber
+    push_u16(&mut data, 0b1011); // baseline at y=0, lsb at x=0, integer scaling
+    push_u16(&mut data, metrics.units_per_em);
+    data.extend_from_slice(&[0; 16]); // created, modified
+    push_i16(&mut data, metrics.x_min);
+    push_i16(&mut data, metrics.y_min);
+    push_i16(&mut data, metrics.x_max);
+    push_i16(&mut data, metrics.y_max);
+    push_u16(&mut data, 0); // macStyle
+    push_u16(&mut data, 8); // lowestRecPPEM
+    push_i16(&mut data, 2); // fontDirectionHint
+    push_i16(&mut data, 0); // indexToLocFormat: short offsets
+    push_i16(&mut data, 0); // glyphDataFormat
+    data
+}
+
+fn notdef_advance(metrics: &FontMetrics) -> u16 {
+    metrics.units_per_em / 2
+}
+
+fn build_hhea<'a>(
+    metrics: &FontMetrics,
+    glyphs: impl Iterator<Item = &'a Glyph>,
+    num_glyphs: u16,
+) -> Vec<u8> {
+    let mut advance_max = notdef_advance(metrics);
+    let mut min_lsb = 0i16;
+    let mut min_rsb = 0i16;
+    for glyph in glyphs {
+        let (width, _) = glyph.mipmap_size(0);
+        let advance = units(glyph.info.advance as i32);
+        let lsb = units(glyph.info.bearing_x as i32);
+        advance_max = advance_max.max(advance as u16);
+        min_lsb = min_lsb.min(lsb);
+        min_rsb = min_rsb.min(advance - lsb - units(width as i32));
+    }
+
+    let mut data = Vec::with_capacity(36);
+    push_u32(&mut data, 0x00010000); // version
+    push_i16(&mut data, metrics.ascender);
+    push_i16(&mut data, metrics.descender);
+    push_i16(&mut data, 0); // lineGap
+    push_u16(&mut data, advance_max);
+    push_i16(&mut data, min_lsb);
+    push_i16(&mut data, min_rsb);
+    push_i16(&mut data, metrics.x_max);
+    push_i16(&mut data, 1); // caretSlopeRise
+    push_i16(&mut data, 0); // caretSlopeRun
+    data.extend_from_slice(&[0; 12]); // caretOffset, reserved, metricDataFormat
+    push_u16(&mut data, num_glyphs);
+    data
+}
+
+fn build_hmtx<'a>(metrics: &FontMetrics, glyphs: impl Iterator<Item = &'a Glyph>) -> Vec<u8> {
+    let mut data = Vec::new();
+    push_u16(&mut data, notdef_advance(metrics));
+    push_i16(&mut data, 0);
+    for glyph in glyphs {
+        push_u16(&mut data, units(glyph.info.advance as i32) as u16);
+        push_i16(&mut data, units(glyph.info.bearing_x as i32));
+    }
+    data
+}
+
+fn build_maxp(num_glyphs: u16) -> Vec<u8> {
+    let mut data = Vec::with_capacity(32);
+    push_u32(&mut data, 0x00010000); // version
+    push_u16(&mut data, num_glyphs);
+    data.extend_from_slice(&[0; 8]); // maxPoints, maxContours, maxCompositePoints, maxCompositeContours
+    push_u16(&mut data, 1); // maxZones
+    data.extend_from_slice(&[0; 16]); // twilight points, storage, defs, stack, instructions, components
+    data
+}
+
+fn build_os2(metrics: &FontMetrics, cmap: &BTreeMap<u32, u16>) -> Vec<u8> {
+    let em = metrics.units_per_em as i16;
+    let first_char = cmap.keys().next().copied().unwrap_or(0).min(0xFFFF) as u16;
+    let last_char = cmap.keys().next_back().copied().unwrap_or(0).min(0xFFFF) as u16;
+
+    let mut data = Vec::with_capacity(96);
+    push_u16(&mut data, 4); // version
+    push_i16(&mut data, em / 2); // xAvgCharWidth
+    push_u16(&mut data, 400); // usWeightClass
+    push_u16(&mut data, 5); // usWidthClass
+    push_u16(&mut data, 0); // fsType: installable
+    for value in [em / 2, em / 2, 0, em / 10, em / 2, em / 2, 0, em / 3] {
+        push_i16(&mut data, value); // subscript and superscript size and offset
+    }
+    push_i16(&mut data, UNITS_PER_PIXEL as i16); // yStrikeoutSize
+    push_i16(&mut data, metrics.ascender / 3); // yStrikeoutPosition
+    push_i16(&mut data, 0); // sFamilyClass
+    data.extend_from_slice(&[0; 10]); // panose
+    data.extend_from_slice(&[0; 16]); // ulUnicodeRange1-4
+    data.extend_from_slice(b"NONE"); // achVendID
+    push_u16(&mut data, 0x00C0); // fsSelection: REGULAR, USE_TYPO_METRICS
+    push_u16(&mut data, first_char);
+    push_u16(&mut data, last_char);
+    push_i16(&mut data, metrics.ascender);
+    push_i16(&mut data, metrics.descender);
+    push_i16(&mut data, 0); // sTypoLineGap
+    push_u16(&mut data, metrics.y_max.max(metrics.ascender) as u16); // usWinAscent
+    push_u16(&mut data, (-metrics.y_min.min(metrics.descender)) as u16); // usWinDescent
+    data.extend_from_slice(&[0; 8]); // ulCodePageRange1-2
+    push_i16(&mut data, 0); // sxHeight
+    push_i16(&mut data, 0); // sCapHeight
+    push_u16(&mut data, 0); // usDefaultChar
+    push_u16(&mut data, 0x20); // usBreakChar
+    push_u16(&mut data, 1); // usMaxContext
+    data
+}
+
+fn build_post(metrics: &FontMetrics) -> Vec<u8> {
+    let mut data = Vec::with_capacity(32);
+    push_u32(&mut data, 0x00030000); // version 3: no glyph names
+    push_u32(&mut data, 0); // italicAngle
+    push_i16(&mut data, metrics.descender / 2); // underlinePosition
+    push_i16(&mut data, UNITS_PER_PIXEL as i16); // underlineThickness
+    data.extend_from_slice(&[0; 20]); // isFixedPitch, memory usage
+    data
+}
+
+fn build_name(family_name: &str) -> Vec<u8> {
+    let postscript_name: String = family_name
+        .chars()
+        .filter(|c| c.is_ascii_graphic() && !"[](){}<>/%".contains(*c))
+        .take(63)
+        .collect();
+    let records = [
+        (1u16, family_name.to_string()),
+        (2, "Regular".to_string()),
+        (3, format!("{}-Regular", postscript_name)),
+        (4, family_name.to_string()),
+        (6, postscript_name),
+    ];
+
+    let mut strings = Vec::new();
+    let mut data = Vec::new();
+    push_u16(&mut data, 0); // format
+    push_u16(&mut data, records.len() as u16);
+    push_u16(&mut data, 6 + records.len() as u16 * 12);
+    for (name_id, value) in &records {
+        let start = strings.len();
+        for unit in value.encode_utf16() {
+            push_u16(&mut strings, unit);
+        }
+        push_u16(&mut data, 3); // platformID: Windows
+        push_u16(&mut data, 1); // encodingID: Unicode BMP
+        push_u16(&mut data, 0x0409); // languageID: English (US)
+        push_u16(&mut data, *name_id);
+        push_u16(&mut data, (strings.len() - start) as u16);
+        push_u16(&mut data, start as u16);
+    }
+    data.extend_from_slice(&strings);
+    data
+}
+
+/// Builds a `cmap` with a format 12 subtable for all characters, plus a format 4 subtable
+/// for the Basic Multilingual Plane when it fits in one.
+fn build_cmap(cmap: &BTreeMap<u32, u16>) -> Vec<u8> {
+    let format12 = build_cmap_format12(cmap);
+    let format4 = build_cmap_format4(cmap);
+
+    // (platformID, encodingID, subtable)
+    let mut encodings: Vec<(u16, u16, &[u8])> = Vec::new();
+    if let Some(format4) = &format4 {
+        encodings.push((0, 3, format4));
+    }
+    encodings.push((0, 4, &format12));
+    if let Some(format4) = &format4 {
+        encodings.push((3, 1, format4));
+    }
+    encodings.push((3, 10, &format12));
+
+    let mut data = Vec::new();
+    push_u16(&mut data, 0); // version
+    push_u16(&mut data, encodings.len() as u16);
+
+    let format4_offset = 4 + encodings.len() * 8;
+    let format12_offset = format4_offset + format4.as_ref().map_or(0, Vec::len);
+    for (platform_id, encoding_id, subtable) in &encodings {
+        push_u16(&mut data, *platform_id);
+        push_u16(&mut data, *encoding_id);
+        let offset = if subtable[1] == 4 {
+            format4_offset
+        } else {
+            format12_offset
+        };
+        push_u32(&mut data, offset as u32);
+    }
+
+    if let Some(format4) = &format4 {
+        data.extend_from_slice(format4);
+    }
+    data.extend_from_slice(&format12);
+    data
+}
+
+fn build_cmap_format12(cmap: &BTreeMap<u32, u16>) -> Vec<u8> {
+    // (startCharCode, endCharCode, startGlyphID)
+    let mut groups: Vec<(u32, u32, u32)> = Vec::new();
+    for (&code, &glyph) in cmap {
+        match groups.last_mut() {
+            Some((start, end, start_glyph))
+                if code == *end + 1 && glyph as u32 == *start_glyph + (code - *start) =>
+            {
+                *end = code;
+            }
+            _ => groups.push((code, code, glyph as u32)),
+        }
+    }
+
+    let mut data = Vec::new();
+    push_u16(&mut data, 12); // format
+    push_u16(&mut data, 0); // reserved
+    push_u32(&mut data, 16 + groups.len() as u32 * 12);
+    push_u32(&mut data, 0); // language
+    push_u32(&mut data, groups.len() as u32);
+    for (start, end, start_glyph) in groups {
+        push_u32(&mut data, start);
+        push_u32(&mut data, end);
+        push_u32(&mut data, start_glyph);
+    }
+    data
+}
+
+/// Format 4 subtable for the Basic Multilingual Plane, or `None` if it would exceed 64 KiB.
+///
+/// Runs of consecutive characters become one segment, using `idDelta` when their glyphs are
+/// consecutive too and `glyphIdArray` otherwise.
+fn build_cmap_format4(cmap: &BTreeMap<u32, u16>) -> Option<Vec<u8>> {
+    // (startCode, glyphs)
+    let mut runs: Vec<(u16, Vec<u16>)> = Vec::new();
+    for (&code, &glyph) in cmap.range(..0xFFFF) {
+        match runs.last_mut() {
+            Some((start, glyphs)) if code == *start as u32 + glyphs.len() as u32 => {
+                glyphs.push(glyph)
+            }
+            _ => runs.push((code as u16, vec![glyph])),
+        }
+    }
+
+    let seg_count = runs.len() + 1;
+    let mut end_codes = Vec::new();
+    let mut start_codes = Vec::new();
+    let mut id_deltas = Vec::new();
+    let mut id_range_offsets = Vec::new();
+    let mut glyph_id_array = Vec::new();
+
+    for (i, (start, glyphs)) in runs.iter().enumerate() {
+        start_codes.push(*start);
+        end_codes.push(start + (glyphs.len() - 1) as u16);
+
+        let delta = glyphs[0].wrapping_sub(*start);
+        let consecutive = glyphs
+            .iter()
+            .enumerate()
+            .all(|(j, &glyph)| glyph == glyphs[0].wrapping_add(j as u16));
+        if consecutive {
+            id_deltas.push(delta);
+            id_range_offsets.push(0);
+        } else {
+            // Byte offset from this idRangeOffset entry to its first glyphIdArray entry
+            let offset = (seg_count - i + glyph_id_array.len()) * 2;
+            id_deltas.push(0);
+            id_range_offsets.push(u16::try_from(offset).ok()?);
+            glyph_id_array.extend_from_slice(glyphs);
+        }
+    }
+
+    // Required final segment
+    start_codes.push(0xFFFF);
+    end_codes.push(0xFFFF);
+    id_deltas.push(1);
+    id_range_offsets.push(0);
+
+    let length = 16 + seg_count * 8 + glyph_id_array.len() * 2;
+    let length = u16::try_from(length).ok()?;
+
+    let entry_selector = 15 - (seg_count as u16).leading_zeros() as u16;
+    let search_range = (1u16 << entry_selector) * 2;
+
+    let mut data = Vec::with_capacity(length as usize);
+    push_u16(&mut data, 4); // format
+    push_u16(&mut data, length);
+    push_u16(&mut data, 0); // language
+    push_u16(&mut data, seg_count as u16 * 2);
+    push_u16(&mut data, search_range);
+    push_u16(&mut data, entry_selector);
+    push_u16(&mut data, seg_count as u16 * 2 - search_range);
+    end_codes.iter().for_each(|&v| push_u16(&mut data, v));
+    push_u16(&mut data, 0); // reservedPad
+    start_codes.iter().for_each(|&v| push_u16(&mut data, v));
+    id_deltas.iter().for_each(|&v| push_u16(&mut data, v));
+    id_range_offsets
+        .iter()
+        .for_each(|&v| push_u16(&mut data, v));
+    glyph_id_array.iter().for_each(|&v| push_u16(&mut data, v));
+    Some(data)
+}
+
+/// Builds the `CBDT` and `CBLC` tables for a single strike covering every glyph except .notdef.
+///
+/// Glyphs are stored as format 17 (small metrics and PNG data). Empty glyphs get no data.
+fn build_bitmaps<'a>(
+    metrics: &FontMetrics,
+    glyphs: impl ExactSizeIterator<Item = &'a Glyph>,
+    ppem: u8,
+) -> Result<(Vec<u8>, Vec<u8>), FntError> {
+    let num_glyphs = glyphs.len() as u16;
+
+    let mut cbdt = Vec::new();
+    push_u32(&mut cbdt, 0x00030000); // version
+
+    // Offsets from the start of CBDT, one more than the glyph count
+    let mut offsets = vec![cbdt.len() as u32];
+    let mut width_max = 0u8;
+    for glyph in glyphs {
+        let (width, height) = glyph.mipmap_size(0);
+        if width > 0 && height > 0 {
+            let mut png = Vec::new();
+            image::codecs::png::PngEncoder::new(&mut png)
+                .write_image(
+                    &glyph.to_image(0),
+                    width,
+                    height,
+                    image::ExtendedColorType::Rgba8,
+                )
+                .map_err(io::Error::other)?;
+
+            cbdt.push(height as u8);
+            cbdt.push(width as u8);
+            cbdt.push(glyph.info.bearing_x as u8);
+            cbdt.push(glyph.info.bearing_y as u8);
+            cbdt.push(glyph.info.advance);
+            push_u32(&mut cbdt, png.len() as u32);
+            cbdt.extend_from_slice(&png);
+            width_max = width_max.max(width as u8);
+        }
+        offsets.push(cbdt.len() as u32);
+    }
+
+    let ascender = (metrics.ascender as i32 / UNITS_PER_PIXEL) as i8;
+    let descender = (metrics.descender as i32 / UNITS_PER_PIXEL) as i8;
+    let mut line_metrics = Vec::with_capacity(12);
+    line_metrics.extend_from_slice(&[ascender as u8, descender as u8, width_max]);
+    line_metrics.extend_from_slice(&[1, 0, 0]); // caretSlopeNumerator, caretSlopeDenominator, caretOffset
+    line_metrics.extend_from_slice(&[0; 6]); // minOriginSB, minAdvanceSB, maxBeforeBL, minAfterBL, padding
+
+    // Header, one BitmapSize record, one IndexSubTableArray entry, one format 1 IndexSubTable
+    let index_subtable_array_offset = 8 + 48;
+    let index_tables_size = 8 + 8 + offsets.len() * 4;
+
+    let mut cblc = Vec::new();
+    push_u32(&mut cblc, 0x00030000); // version
+    push_u32(&mut cblc, 1); // numSizes
+
+    push_u32(&mut cblc, index_subtable_array_offset as u32);
+    push_u32(&mut cblc, index_tables_size as u32);
+    push_u32(&mut cblc, 1); // numberOfIndexSubTables
+    push_u32(&mut cblc, 0); // colorRef
+    cblc.extend_from_slice(&line_metrics); // hori
+    cblc.extend_from_slice(&line_metrics); // vert
+    push_u16(&mut cblc, 1); // startGlyphIndex
+    push_u16(&mut cblc, num_glyphs.max(1)); // endGlyphIndex
+    cblc.extend_from_slice(&[ppem, ppem, 32, 0x01]); // ppemX, ppemY, bitDepth, flags: horizontal
+
+    // IndexSubTableArray
+    push_u16(&mut cblc, 1); // firstGlyphIndex
+    push_u16(&mut cblc, num_glyphs.max(1)); // lastGlyphIndex
+    push_u32(&mut cblc, 8); // additionalOffsetToIndexSubtable
+
+    // IndexSubTable format 1: variable metrics glyphs with 4-byte offsets
+    push_u16(&mut cblc, 1); // indexFormat
+    push_u16(&mut cblc, 17); // imageFormat
+    push_u32(&mut cblc, 0); // imageDataOffset
+    offsets.resize(num_glyphs.max(1) as usize + 1, *offsets.last().unwrap());
+    for offset in offsets {
+        push_u32(&mut cblc, offset);
+    }
+
+    Ok((cbdt, cblc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ab_glyph::{Font, FontRef, GlyphImageFormat};
+
+    use crate::fnt::build_test_fnt;
+    use crate::metadata::FntVersion;
+
+    fn test_fnt() -> Fnt {
+        let mut fnt = build_test_fnt(
+            FntVersion::V1,
+            1,
+            12,
+            &[('\0', 0, 2), ('A', 6, 8), ('B', 5, 7), ('あ', 10, 12)],
+        );
+        // 'C' falls back to the default glyph
+        fnt.metadata.characters.insert('C' as u32, 0);
+        Fnt::from_data(&fnt.to_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_build_ttf() {
+        let fnt = test_fnt();
+        let (data, report) = build_ttf(&fnt, "Test Font", &|_, _| {}).unwrap();
+
+        assert_eq!(report.glyph_count, 4);
+        assert_eq!(report.character_count, 3);
+        assert_eq!(checksum(&data), 0xB1B0AFBA);
+
+        let font = FontRef::try_from_slice(&data).unwrap();
+        assert_eq!(font.units_per_em(), Some(16.0 * 16.0));
+        assert_eq!(font.ascent_unscaled(), 12.0 * 16.0);
+        assert_eq!(font.descent_unscaled(), -4.0 * 16.0);
+
+        assert_eq!(font.glyph_id('C').0, 0);
+        let a = font.glyph_id('A');
+        let kana = font.glyph_id('あ');
+        assert_ne!(a.0, 0);
+        assert_ne!(kana.0, 0);
+        assert_eq!(font.h_advance_unscaled(a), 8.0 * 16.0);
+
+        let image = font.glyph_raster_image2(kana, 16).unwrap();
+        assert!(matches!(image.format, GlyphImageFormat::Png));
+        assert_eq!((image.width, image.height), (10, 10));
+        assert_eq!(image.pixels_per_em, 16);
+
+        let png = image::load_from_memory(image.data).unwrap().to_rgba8();
+        assert_eq!(png.dimensions(), (10, 10));
+        assert_eq!(png.get_pixel(5, 5).0, [0, 0, 0, 0xFF]);
+    }
+
+    /// Looks up a character the way a font renderer reads a format 4 subtable.
+    fn format4_lookup(data: &[u8], code: u16) -> u16 {
+        let read = |offset: usize| u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let seg_count = read(6) as usize / 2;
+        let end_codes = 14;
+        let start_codes = end_codes + seg_count * 2 + 2;
+        let id_deltas = start_codes + seg_count * 2;
+        let id_range_offsets = id_deltas + seg_count * 2;
+
+        for i in 0..seg_count {
+            if code > read(end_codes + i * 2) {
+                continue;
+            }
+            let start = read(start_codes + i * 2);
+            if code < start {
+                return 0;
+            }
+            let range_offset = read(id_range_offsets + i * 2) as usize;
+            if range_offset == 0 {
+                return code.wrapping_add(read(id_deltas + i * 2));
+            }
+            let offset = id_range_offsets + i * 2 + range_offset + (code - start) as usize * 2;
+            return read(offset);
+        }
+        0
+    }
+
+    #[test]
+    fn test_cmap_format4() {
+        let cmap: BTreeMap<u32, u16> = [
+            (0x41, 1),
+            (0x42, 2),
+            (0x44, 7),
+            (0x45, 3),
+            (0x46, 5),
+            (0x3042, 9),
+            (0x10000, 4),
+        ]
+        .into_iter()
+        .collect();
+        let data = build_cmap_format4(&cmap).unwrap();
+
+        for (&code, &glyph) in cmap.range(..0xFFFF) {
+            assert_eq!(format4_lookup(&data, code as u16), glyph);
+        }
+        assert_eq!(format4_lookup(&data, 0x43), 0);
+        assert_eq!(format4_lookup(&data, 0x20), 0);
+
+        // Too many unrelated characters for 64 KiB
+        let cmap: BTreeMap<u32, u16> = (0..0x8000).map(|c| (c * 2, 1)).collect();
+        assert!(build_cmap_format4(&cmap).is_none());
+    }
+}
@@ -1,3 +1,8 @@
+/// Progress callback, called with `(done, total)` each time a glyph has been processed.
+///
+/// Glyphs are processed in parallel, so it may be called from several threads.
+pub type ProgressFn = dyn Fn(usize, usize) + Sync;
+
 pub fn generate_sjis_map() -> Vec<u32> {
     let mut map = Vec::with_capacity(8000);
 
@@ -42,6 +47,17 @@ pub fn decode_sjis_u32(code: u32) -> Option<char> {
     if had_errors { None } else { cow.chars().next() }
 }
 
+pub fn encode_sjis_u32(c: char) -> Option<u32> {
+    let mut buf = [0u8; 4];
+    let (bytes, _, had_errors) = encoding_rs::SHIFT_JIS.encode(c.encode_utf8(&mut buf));
+
+    match (had_errors, bytes.as_ref()) {
+        (false, [b]) => Some(*b as u32),
+        (false, [hi, lo]) => Some(((*hi as u32) << 8) | *lo as u32),
+        _ => None,
+    }
+}
+
 pub fn ceil_power_of_2(n: u32) -> u32 {
     if n == 0 {
         return 0;
@@ -52,88 +68,3 @@ pub fn ceil_power_of_2(n: u32) -> u32 {
     }
     p
 }
-
-pub fn downsample_lanczos(src: &[u8], src_w: u32, src_h: u32, dst_w: u32, dst_h: u32) -> Vec<u8> {
-    if src_w == dst_w && src_h == dst_h {
-        return src.to_vec();
-    }
-
-    let mut dst = vec![0u8; (dst_w * dst_h) as usize];
-    let scale_x = src_w as f64 / dst_w as f64;
-    let scale_y = src_h as f64 / dst_h as f64;
-
-    let is_integer_scale = (scale_x.round() - scale_x).abs() < 0.001
-        && (scale_y.round() - scale_y).abs() < 0.001
-        && scale_x >= 1.0
-        && scale_y >= 1.0;
-
-    if is_integer_scale {
-        let sx = scale_x.round() as u32;
-        let sy = scale_y.round() as u32;
-        let area = (sx * sy) as f64;
-
-        for dy in 0..dst_h {
-            for dx in 0..dst_w {
-                let mut sum = 0.0;
-                for oy in 0..sy {
-                    for ox in 0..sx {
-                        let x = dx * sx + ox;
-                        let y = dy * sy + oy;
-                        if x < src_w && y < src_h {
-                            sum += src[(y * src_w + x) as usize] as f64;
-                        }
-                    }
-                }
-                dst[(dy * dst_w + dx) as usize] = (sum / area).round() as u8;
-            }
-        }
-    } else {
-        let a = 3.0;
-
-        for dy in 0..dst_h {
-            for dx in 0..dst_w {
-                let src_x = (dx as f64 + 0.5) * scale_x - 0.5;
-                let src_y = (dy as f64 + 0.5) * scale_y - 0.5;
-
-                let x0 = (src_x - a).floor().max(0.0) as u32;
-                let x1 = (src_x + a).ceil().min(src_w as f64 - 1.0) as u32;
-                let y0 = (src_y - a).floor().max(0.0) as u32;
-                let y1 = (src_y + a).ceil().min(src_h as f64 - 1.0) as u32;
-
-                let mut sum = 0.0;
-                let mut weight_sum = 0.0;
-
-                for sy in y0..=y1 {
-                    for sx in x0..=x1 {
-                        let wx = lanczos_weight(sx as f64 - src_x, a);
-                        let wy = lanczos_weight(sy as f64 - src_y, a);
-                        let w = wx * wy;
-                        sum += src[(sy * src_w + sx) as usize] as f64 * w;
-                        weight_sum += w;
-                    }
-                }
-
-                let value = if weight_sum > 0.0 {
-                    (sum / weight_sum).round().clamp(0.0, 255.0) as u8
-                } else {
-                    0
-                };
-                dst[(dy * dst_w + dx) as usize] = value;
-            }
-        }
-    }
-
-    dst
-}
-
-fn lanczos_weight(x: f64, a: f64) -> f64 {
-    if x.abs() < 1e-10 {
-        1.0
-    } else if x.abs() >= a {
-        0.0
-    } else {
-        let pi_x = std::f64::consts::PI * x;
-        let pi_x_a = pi_x / a;
-        (pi_x.sin() / pi_x) * (pi_x_a.sin() / pi_x_a)
-    }
-}
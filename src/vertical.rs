@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use ttf_parser::gsub::{SingleSubstitution, SubstitutionSubtable};
+use ttf_parser::{Face, GlyphId, Tag};
+
+use crate::glyph::RenderedGlyph;
+
+/// Punctuation, brackets, dashes and small kana, which have their own forms in vertical text.
+const DEFAULT_VERTICAL_CHARS: &str = "、。，．：；！？「」『』（）［］｛｝〈〉《》【】〔〕〖〗〘〙〚〛ー～〜―‐…‥＝＿｜\
+    ぁぃぅぇぉっゃゅょゎゕゖァィゥェォッャュョヮヵヶ";
+
+/// Characters of [`DEFAULT_VERTICAL_CHARS`] that are drawn sideways in vertical text.
+const DEFAULT_ROTATE_CHARS: &str =
+    "「」『』（）［］｛｝〈〉《》【】〔〕〖〗〘〙〚〛ー～〜―‐…‥＝＿｜";
+
+fn default_vertical_chars() -> String {
+    DEFAULT_VERTICAL_CHARS.to_string()
+}
+
+fn default_rotate_chars() -> String {
+    DEFAULT_ROTATE_CHARS.to_string()
+}
+
+/// Rendering of glyphs for vertical text.
+///
+/// Every glyph gets the vertical advance of the source font (or the font size when the font
+/// has no vertical metrics), as the advance is the step down the column.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VerticalConfig {
+    /// Characters drawn with the vertical forms of the source font, its OpenType `vert` and
+    /// `vrt2` substitutions.
+    #[serde(default = "default_vertical_chars")]
+    pub chars: String,
+    /// Characters rotated 90° clockwise when the source font has no vertical form for them.
+    #[serde(default = "default_rotate_chars")]
+    pub rotate_chars: String,
+}
+
+impl Default for VerticalConfig {
+    fn default() -> Self {
+        VerticalConfig {
+            chars: default_vertical_chars(),
+            rotate_chars: default_rotate_chars(),
+        }
+    }
+}
+
+/// Looks up the glyphs the `vert` and `vrt2` single substitutions of the GSUB table give for
+/// `chars`. Characters without a vertical form, and all characters of fonts without a GSUB
+/// table, are left out.
+pub fn vertical_forms(font_data: &[u8], chars: &str) -> BTreeMap<char, u16> {
+    let Ok(face) = Face::parse(font_data, 0) else {
+        return BTreeMap::new();
+    };
+    let Some(gsub) = face.tables().gsub else {
+        return BTreeMap::new();
+    };
+
+    let vertical_features = [Tag::from_bytes(b"vert"), Tag::from_bytes(b"vrt2")];
+    let substitutions: Vec<SingleSubstitution> = gsub
+        .features
+        .into_iter()
+        .filter(|feature| vertical_features.contains(&feature.tag))
+        .flat_map(|feature| feature.lookup_indices)
+        .filter_map(|index| gsub.lookups.get(index))
+        .flat_map(|lookup| lookup.subtables.into_iter::<SubstitutionSubtable>())
+        .filter_map(|subtable| match subtable {
+            SubstitutionSubtable::Single(single) => Some(single),
+            _ => None,
+        })
+        .collect();
+
+    chars
+        .chars()
+        .filter_map(|c| {
+            let glyph = face.glyph_index(c)?;
+            let form = substitutions
+                .iter()
+                .find_map(|single| substitute(single, glyph))?;
+            Some((c, form.0))
+        })
+        .collect()
+}
+
+fn substitute(single: &SingleSubstitution, glyph: GlyphId) -> Option<GlyphId> {
+    match single {
+        SingleSubstitution::Format1 { coverage, delta } => {
+            coverage.get(glyph)?;
+            // The delta is added modulo 65536
+            Some(GlyphId(glyph.0.wrapping_add(*delta as u16)))
+        }
+        SingleSubstitution::Format2 {
+            coverage,
+            substitutes,
+        } => substitutes.get(coverage.get(glyph)?),
+    }
+}
+
+/// Rotates a glyph 90° clockwise around `em_center`, the center of its em square in pixels
+/// right of and above the pen position.
+pub fn rotate_glyph(glyph: &RenderedGlyph, em_center: (f32, f32)) -> RenderedGlyph {
+    let (width, height) = (glyph.actual_width as usize, glyph.actual_height as usize);
+    let (center_x, center_y) = em_center;
+
+    // New column i is old row height - 1 - i, new row j is old column j
+    let mut raw_pixels = vec![0u8; width * height];
+    for j in 0..width {
+        for i in 0..height {
+            raw_pixels[j * height + i] = glyph.raw_pixels[(height - 1 - i) * width + j];
+        }
+    }
+
+    let bearing_x = center_x - center_y + glyph.bearing_y as f32 - height as f32;
+    let bearing_y = center_x + center_y - glyph.bearing_x as f32;
+
+    RenderedGlyph {
+        bearing_x: bearing_x.round().clamp(i8::MIN as f32, i8::MAX as f32) as i8,
+        bearing_y: bearing_y.round().clamp(i8::MIN as f32, i8::MAX as f32) as i8,
+        advance: glyph.advance,
+        actual_width: glyph.actual_height,
+        actual_height: glyph.actual_width,
+        raw_pixels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotate_glyph() {
+        // A 3x2 glyph:
+        // 1 2 3
+        // 4 5 6
+        let glyph = RenderedGlyph {
+            bearing_x: 2,
+            bearing_y: 10,
+            advance: 16,
+            actual_width: 3,
+            actual_height: 2,
+            raw_pixels: vec![1, 2, 3, 4, 5, 6],
+        };
+
+        let rotated = rotate_glyph(&glyph, (8.0, 6.0));
+        assert_eq!((rotated.actual_width, rotated.actual_height), (2, 3));
+        assert_eq!(rotated.raw_pixels, [4, 1, 5, 2, 6, 3]);
+        // The bottom edge (y = 8, 2 above the center) becomes the left edge, 2 right of it
+        assert_eq!(rotated.bearing_x, 8 + 2);
+        // The left edge (x = 2, 6 left of the center) ends up 6 above the center
+        assert_eq!(rotated.bearing_y, 6 + 6);
+        assert_eq!(rotated.advance, 16);
+
+        // Four rotations give the original glyph
+        let turned = (0..4).fold(glyph.clone(), |g, _| rotate_glyph(&g, (8.0, 6.0)));
+        assert_eq!(turned.raw_pixels, glyph.raw_pixels);
+        assert_eq!((turned.bearing_x, turned.bearing_y), (2, 10));
+    }
+}